@@ -161,3 +161,146 @@ impl Replace for Vec<u8> {
 		Vec::from(&self[index_start + pattern_start.len()..index_end])
 	}
 }
+
+const BASE64_ALPHABET: &[u8; 64] =
+	b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes the given data as standard Base64 with padding
+///
+/// # Example
+///
+/// ```
+/// use mi::bin::base64_encode;
+/// assert_eq!(base64_encode(b"mi:rust"), "bWk6cnVzdA==");
+/// ```
+pub fn base64_encode(data: &[u8]) -> String {
+	let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+	for chunk in data.chunks(3) {
+		let b = [
+			chunk[0],
+			*chunk.get(1).unwrap_or(&0),
+			*chunk.get(2).unwrap_or(&0),
+		];
+		let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+		encoded.push(BASE64_ALPHABET[(n >> 18) as usize & 63] as char);
+		encoded.push(BASE64_ALPHABET[(n >> 12) as usize & 63] as char);
+		if chunk.len() > 1 {
+			encoded.push(BASE64_ALPHABET[(n >> 6) as usize & 63] as char);
+		} else {
+			encoded.push('=');
+		}
+		if chunk.len() > 2 {
+			encoded.push(BASE64_ALPHABET[n as usize & 63] as char);
+		} else {
+			encoded.push('=');
+		}
+	}
+
+	encoded
+}
+
+/// Decodes standard Base64 data. Padding is optional, returns None for invalid input.
+///
+/// # Example
+///
+/// ```
+/// use mi::bin::base64_decode;
+/// assert_eq!(base64_decode("bWk6cnVzdA==").unwrap(), b"mi:rust");
+/// assert_eq!(base64_decode("bWk6cnVzdA").unwrap(), b"mi:rust");
+/// assert_eq!(base64_decode("b*=="), None);
+/// ```
+pub fn base64_decode(s: &str) -> Option<Vec<u8>> {
+	let s = s.trim_end_matches('=');
+	let mut decoded = Vec::with_capacity(s.len() * 3 / 4);
+
+	let mut n: u32 = 0;
+	let mut bits = 0;
+	for c in s.bytes() {
+		let value = BASE64_ALPHABET.iter().position(|a| *a == c)? as u32;
+		n = n << 6 | value;
+		bits += 6;
+		if bits >= 8 {
+			bits -= 8;
+			decoded.push((n >> bits) as u8);
+			n &= (1 << bits) - 1;
+		}
+	}
+
+	// A single remaining character cannot encode a full byte
+	if bits >= 6 {
+		return None;
+	}
+
+	Some(decoded)
+}
+
+/// Returns the SHA-1 digest of the given data. SHA-1 is not collision resistant and should only be used where a
+/// protocol requires it, like the WebSocket handshake.
+///
+/// # Example
+///
+/// ```
+/// use mi::bin::sha1;
+/// let digest = sha1(b"abc");
+/// assert_eq!(digest[..4], [0xa9, 0x99, 0x3e, 0x36]);
+/// ```
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+	let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+	let mut message = data.to_vec();
+	message.push(0x80);
+	while message.len() % 64 != 56 {
+		message.push(0);
+	}
+	message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_be_bytes());
+
+	for block in message.chunks(64) {
+		let mut w = [0u32; 80];
+		for i in 0..16 {
+			w[i] = u32::from_be_bytes([
+				block[i * 4],
+				block[i * 4 + 1],
+				block[i * 4 + 2],
+				block[i * 4 + 3],
+			]);
+		}
+		for i in 16..80 {
+			w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+		}
+
+		let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+		for (i, word) in w.iter().enumerate() {
+			let (f, k) = match i {
+				0..=19 => ((b & c) | (!b & d), 0x5A827999),
+				20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+				40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+				_ => (b ^ c ^ d, 0xCA62C1D6),
+			};
+			let temp = a
+				.rotate_left(5)
+				.wrapping_add(f)
+				.wrapping_add(e)
+				.wrapping_add(k)
+				.wrapping_add(*word);
+			e = d;
+			d = c;
+			c = b.rotate_left(30);
+			b = a;
+			a = temp;
+		}
+
+		h[0] = h[0].wrapping_add(a);
+		h[1] = h[1].wrapping_add(b);
+		h[2] = h[2].wrapping_add(c);
+		h[3] = h[3].wrapping_add(d);
+		h[4] = h[4].wrapping_add(e);
+	}
+
+	let mut digest = [0u8; 20];
+	for (i, v) in h.iter().enumerate() {
+		digest[i * 4..i * 4 + 4].copy_from_slice(&v.to_be_bytes());
+	}
+	digest
+}
@@ -3,19 +3,36 @@ use crate::logger::errorln;
 use std::path::{Path, PathBuf};
 
 /// Copies a source to a target path. If source is a directory
-pub fn copy_recursively(from: &PathBuf, to: &PathBuf) -> std::io::Result<()> {
-	if from.is_file() {
+pub fn copy_recursively(from: &Path, to: &Path) -> std::io::Result<()> {
+	copy(from, to, true)
+}
+
+/// Copies a source to a target path like [copy_recursively], but copies symbolic links as links instead of following
+/// them. Fails for links on platforms other than Unix
+pub fn copy_recursively_no_follow(from: &Path, to: &Path) -> std::io::Result<()> {
+	copy(from, to, false)
+}
+
+fn copy(from: &Path, to: &Path, follow_links: bool) -> std::io::Result<()> {
+	let metadata = if follow_links {
+		std::fs::metadata(from)
+	} else {
+		std::fs::symlink_metadata(from)
+	};
+	let file_type = match metadata {
+		Ok(m) => Some(m.file_type()),
+		Err(_) => None,
+	};
+
+	if file_type.is_some_and(|t| t.is_symlink()) {
+		copy_link(from, to)
+	} else if file_type.is_some_and(|t| t.is_file()) {
 		match std::fs::copy(from, to) {
 			Ok(_) => Ok(()),
 			Err(e) => Err(e),
 		}
-	} else if from.is_dir() {
-		let entries = match std::fs::read_dir(from) {
-			Ok(f) => f,
-			Err(e) => {
-				panic!("{}", e);
-			}
-		};
+	} else if file_type.is_some_and(|t| t.is_dir()) {
+		let entries = std::fs::read_dir(from)?;
 
 		if !to.is_dir() {
 			std::fs::create_dir_all(to)?;
@@ -42,7 +59,7 @@ pub fn copy_recursively(from: &PathBuf, to: &PathBuf) -> std::io::Result<()> {
 				}
 			};
 
-			copy_recursively(&path, &to.join(target_filename))?;
+			copy(&path, &to.join(target_filename), follow_links)?;
 		}
 
 		Ok(())
@@ -57,6 +74,19 @@ pub fn copy_recursively(from: &PathBuf, to: &PathBuf) -> std::io::Result<()> {
 	}
 }
 
+#[cfg(unix)]
+fn copy_link(from: &Path, to: &Path) -> std::io::Result<()> {
+	std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_link(_: &Path, _: &Path) -> std::io::Result<()> {
+	Err(std::io::Error::new(
+		std::io::ErrorKind::Unsupported,
+		"Symbolic links can only be copied on Unix",
+	))
+}
+
 /// Reads a directory into a vector of [std::path::PathBuf]. Always returns a Vec<PathBuf>, skipping all entries that
 /// cannot be read, returning an empty one if the dir is not a directory.
 pub fn list_dir(dir: &Path) -> Vec<PathBuf> {
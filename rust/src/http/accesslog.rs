@@ -0,0 +1,246 @@
+use super::response::ResponseStats;
+use super::util::{datetime_parts, MONTHS};
+use super::Request;
+use std::time::Duration;
+
+/// A value that can be written to the access log
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogField {
+	/// IP address of the client
+	RemoteAddr,
+	/// Name of the authenticated user, see [super::Request::user]
+	User,
+	/// Time the request was received
+	Time,
+	/// Request method
+	Method,
+	/// Requested URI
+	Uri,
+	/// HTTP version of the request
+	Protocol,
+	/// Status code of the response
+	Status,
+	/// Number of body bytes sent to the client
+	Bytes,
+	/// Time in milliseconds from receiving the connection until the handler finished
+	Duration,
+	/// Value of the Host request header
+	Host,
+	/// Value of the Referer request header
+	Referer,
+	/// Value of the User-Agent request header
+	UserAgent,
+	/// Value of the given request header
+	Header(String),
+}
+
+/// Format of the lines written to [super::Server::log_access]
+///
+/// # Example
+///
+/// ```
+/// use mi::http::*;
+///
+/// let mut server = Server::new();
+/// server.log_access = std::sync::Arc::new(std::sync::Mutex::new(std::io::stdout()));
+/// server.access_log_format = AccessLogFormat::Json(vec![
+///     LogField::Time,
+///     LogField::Method,
+///     LogField::Uri,
+///     LogField::Status,
+///     LogField::Duration,
+///     LogField::Header(String::from("X-Request-Id")),
+/// ]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum AccessLogFormat {
+	/// Common Log Format: `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /a HTTP/1.1" 200 2326`
+	#[default]
+	Common,
+	/// Combined Log Format, the Common Log Format followed by the quoted Referer and User-Agent headers
+	Combined,
+	/// The given fields separated by spaces, text values are quoted
+	Fields(Vec<LogField>),
+	/// One JSON object per line containing the given fields
+	Json(Vec<LogField>),
+}
+
+/// Everything known about a completed request
+pub(crate) struct LogEntry<'a> {
+	pub(crate) req: &'a Request,
+	pub(crate) stats: &'a ResponseStats,
+	/// Unix timestamp the request was received
+	pub(crate) time: u64,
+	pub(crate) duration: Duration,
+}
+
+impl LogEntry<'_> {
+	/// Returns the line to write to the access log, without line break
+	pub(crate) fn format(&self, format: &AccessLogFormat) -> String {
+		match format {
+			AccessLogFormat::Common => self.common(),
+			AccessLogFormat::Combined => format!(
+				"{} {} {}",
+				self.common(),
+				quote(self.header("Referer")),
+				quote(self.header("User-Agent"))
+			),
+			AccessLogFormat::Fields(fields) => fields
+				.iter()
+				.map(|f| self.text_field(f))
+				.collect::<Vec<String>>()
+				.join(" "),
+			AccessLogFormat::Json(fields) => {
+				let mut object = serde_json::Map::new();
+				for f in fields {
+					object.insert(json_name(f), self.json_field(f));
+				}
+				serde_json::Value::Object(object).to_string()
+			}
+		}
+	}
+
+	fn common(&self) -> String {
+		let bytes = self.stats.bytes_sent();
+		format!(
+			"{} - {} [{}] {} {} {}",
+			self.remote_addr(),
+			self.req.user().unwrap_or_else(|| String::from("-")),
+			clf_time(self.time),
+			quote(Some(&format!(
+				"{} {} {}",
+				self.req.method, self.req.uri, self.req.http_version
+			))),
+			self.stats.status_code(),
+			if bytes == 0 {
+				String::from("-")
+			} else {
+				bytes.to_string()
+			}
+		)
+	}
+
+	fn remote_addr(&self) -> String {
+		match self.req.peer_addr() {
+			Some(a) => a.ip().to_string(),
+			None => String::from("-"),
+		}
+	}
+
+	fn header(&self, name: &str) -> Option<&str> {
+		self.req.headers.get(name)
+	}
+
+	fn duration_ms(&self) -> f64 {
+		(self.duration.as_micros() as f64) / 1000.0
+	}
+
+	fn text_field(&self, field: &LogField) -> String {
+		match field {
+			LogField::RemoteAddr => self.remote_addr(),
+			LogField::User => quote(self.req.user().as_deref()),
+			LogField::Time => format!("[{}]", clf_time(self.time)),
+			LogField::Method => self.req.method.clone(),
+			LogField::Uri => quote(Some(&self.req.uri)),
+			LogField::Protocol => self.req.http_version.clone(),
+			LogField::Status => self.stats.status_code().to_string(),
+			LogField::Bytes => self.stats.bytes_sent().to_string(),
+			LogField::Duration => format!("{:.3}", self.duration_ms()),
+			LogField::Host => quote(self.header("Host")),
+			LogField::Referer => quote(self.header("Referer")),
+			LogField::UserAgent => quote(self.header("User-Agent")),
+			LogField::Header(name) => quote(self.header(name)),
+		}
+	}
+
+	fn json_field(&self, field: &LogField) -> serde_json::Value {
+		use serde_json::Value;
+
+		let optional = |v: Option<&str>| match v {
+			Some(v) => Value::from(v),
+			None => Value::Null,
+		};
+
+		match field {
+			LogField::RemoteAddr => match self.req.peer_addr() {
+				Some(a) => Value::from(a.ip().to_string()),
+				None => Value::Null,
+			},
+			LogField::User => match self.req.user() {
+				Some(u) => Value::from(u),
+				None => Value::Null,
+			},
+			LogField::Time => {
+				let (year, month, day, hour, minute, second) = datetime_parts(self.time);
+				Value::from(format!(
+					"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+					year, month, day, hour, minute, second
+				))
+			}
+			LogField::Method => Value::from(self.req.method.as_str()),
+			LogField::Uri => Value::from(self.req.uri.as_str()),
+			LogField::Protocol => Value::from(self.req.http_version.as_str()),
+			LogField::Status => Value::from(self.stats.status_code()),
+			LogField::Bytes => Value::from(self.stats.bytes_sent()),
+			LogField::Duration => Value::from(self.duration_ms()),
+			LogField::Host => optional(self.header("Host")),
+			LogField::Referer => optional(self.header("Referer")),
+			LogField::UserAgent => optional(self.header("User-Agent")),
+			LogField::Header(name) => optional(self.header(name)),
+		}
+	}
+}
+
+/// Returns the key used for the field in JSON lines
+fn json_name(field: &LogField) -> String {
+	String::from(match field {
+		LogField::RemoteAddr => "remote_addr",
+		LogField::User => "user",
+		LogField::Time => "time",
+		LogField::Method => "method",
+		LogField::Uri => "uri",
+		LogField::Protocol => "protocol",
+		LogField::Status => "status",
+		LogField::Bytes => "bytes",
+		LogField::Duration => "duration_ms",
+		LogField::Host => "host",
+		LogField::Referer => "referer",
+		LogField::UserAgent => "user_agent",
+		LogField::Header(name) => return name.to_ascii_lowercase().replace('-', "_"),
+	})
+}
+
+/// Formats a unix timestamp like "10/Oct/2000:13:55:36 +0000"
+fn clf_time(secs: u64) -> String {
+	let (year, month, day, hour, minute, second) = datetime_parts(secs);
+	format!(
+		"{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+		day,
+		MONTHS[month as usize - 1],
+		year,
+		hour,
+		minute,
+		second
+	)
+}
+
+/// Returns the value in double quotes with quotes, backslashes and control characters escaped, or "-" if missing
+fn quote(value: Option<&str>) -> String {
+	let value = match value {
+		Some(v) => v,
+		None => return String::from("\"-\""),
+	};
+
+	let mut quoted = String::with_capacity(value.len() + 2);
+	quoted.push('"');
+	for c in value.chars() {
+		match c {
+			'"' => quoted.push_str("\\\""),
+			'\\' => quoted.push_str("\\\\"),
+			c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+			c => quoted.push(c),
+		}
+	}
+	quoted.push('"');
+	quoted
+}
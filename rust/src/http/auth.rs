@@ -0,0 +1,267 @@
+use super::{Authorization, Error, Request, RequestHandler, Response};
+use crate::bin::{base64_encode, sha1};
+use crate::log_error;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Compares two byte strings in constant time with respect to their contents
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	if a.len() != b.len() {
+		return false;
+	}
+	a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Returns true if the password matches the hash, which is false for unsupported hashes
+fn check_password(hash: &str, password: &str) -> bool {
+	if let Some(digest) = hash.strip_prefix("{SHA}") {
+		let computed = base64_encode(&sha1(password.as_bytes()));
+		return constant_time_eq(digest.as_bytes(), computed.as_bytes());
+	}
+
+	if ["$2y$", "$2a$", "$2b$", "$5$", "$6$"]
+		.iter()
+		.any(|p| hash.starts_with(p))
+	{
+		return pwhash::unix::verify(password, hash);
+	}
+
+	false
+}
+
+/// User names and password hashes in the format of Apache htpasswd files.
+///
+/// Every line contains a user name and a password hash separated by a colon. Supported hashes are bcrypt ("$2y$",
+/// "$2a$", "$2b$"), SHA-1 ("{SHA}") and the SHA-256 and SHA-512 crypt variants ("$5$", "$6$"). Users with other hashes,
+/// like MD5 ("$apr1$") or plain text passwords, cannot log in.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::Htpasswd;
+/// let users = Htpasswd::parse("alice:{SHA}qUqP5cyxm6YcTAhz05Hph5gvu9M=\n# comment\n");
+///
+/// assert!(users.verify("alice", "test"));
+/// assert!(!users.verify("alice", "wrong"));
+/// assert!(!users.verify("bob", "test"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Htpasswd {
+	users: HashMap<String, String>,
+}
+
+impl Htpasswd {
+	/// Parses the contents of an htpasswd file. Empty lines, comments and malformed lines are ignored
+	pub fn parse(data: &str) -> Htpasswd {
+		let mut users = HashMap::new();
+		for line in data.lines() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			if let Some(p) = line.find(':') {
+				users.insert(String::from(&line[..p]), String::from(&line[p + 1..]));
+			}
+		}
+		Htpasswd { users }
+	}
+
+	/// Reads and parses the htpasswd file at the given path
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Htpasswd, std::io::Error> {
+		Ok(Htpasswd::parse(&std::fs::read_to_string(path)?))
+	}
+
+	/// Returns true if the user exists and the password matches its hash. For unknown users the password is checked
+	/// against the hash of another user, so the time taken does not reveal which users exist
+	pub fn verify(&self, user: &str, password: &str) -> bool {
+		match self.users.get(user) {
+			Some(hash) => check_password(hash, password),
+			None => {
+				if let Some(hash) = self.users.values().next() {
+					check_password(hash, password);
+				}
+				false
+			}
+		}
+	}
+
+	/// Returns the number of users
+	pub fn len(&self) -> usize {
+		self.users.len()
+	}
+
+	/// Returns true if there are no users
+	pub fn is_empty(&self) -> bool {
+		self.users.is_empty()
+	}
+}
+
+/// An htpasswd file that is read again when its modification time changes
+struct HtpasswdFile {
+	path: PathBuf,
+	modified: Option<SystemTime>,
+	/// Shared with requests that are being verified, so the lock is not held during slow hash checks
+	users: Arc<Htpasswd>,
+}
+
+impl HtpasswdFile {
+	fn modified(path: &Path) -> Option<SystemTime> {
+		std::fs::metadata(path).and_then(|m| m.modified()).ok()
+	}
+
+	fn reload_if_changed(&mut self) {
+		let modified = HtpasswdFile::modified(&self.path);
+		if modified == self.modified {
+			return;
+		}
+
+		match Htpasswd::load(&self.path) {
+			Ok(users) => {
+				self.users = Arc::new(users);
+				self.modified = modified;
+			}
+			// Keep the previous users, e.g. while the file is being replaced
+			Err(e) => log_error!(
+				"Cannot reload htpasswd file {}: {}",
+				self.path.to_string_lossy(),
+				e
+			),
+		}
+	}
+}
+
+/// A [RequestHandler] that wraps another handler and only passes on requests with valid credentials. Requests are
+/// authenticated with HTTP Basic authentication against an htpasswd file, which is reloaded when it changes, or with
+/// static Bearer tokens. All other requests are answered with 401 Unauthorized and a WWW-Authenticate header.
+///
+/// The name of the authenticated user is available to the inner handler via [Request::user].
+///
+/// # Example
+///
+/// ```no_run
+/// use mi::http::*;
+/// use std::sync::Arc;
+///
+/// let admin = Arc::new(Handler::new(|r| r.uri.starts_with("/admin"), |req, mut res| {
+///     res.w(format!("Hello {}", req.user().unwrap_or_default()));
+/// }));
+///
+/// let mut auth = AuthHandler::new(admin, "Admin area");
+/// auth.htpasswd("/etc/mi/htpasswd")?;
+/// auth.bearer_token("secret-token-of-the-deploy-script", "deploy");
+///
+/// let mut server = Server::new();
+/// server.handler(Arc::new(auth));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct AuthHandler {
+	/// The realm sent to clients in the WWW-Authenticate header
+	pub realm: String,
+	inner: Arc<dyn RequestHandler>,
+	htpasswd: Option<Mutex<HtpasswdFile>>,
+	tokens: Vec<(String, String)>,
+}
+
+impl AuthHandler {
+	/// Wraps the given handler. Until an htpasswd file or tokens are added, all requests are rejected
+	pub fn new(inner: Arc<dyn RequestHandler>, realm: &str) -> AuthHandler {
+		AuthHandler {
+			realm: String::from(realm),
+			inner,
+			htpasswd: None,
+			tokens: Vec::new(),
+		}
+	}
+
+	/// Enables Basic authentication against the htpasswd file at the given path. Fails if the file cannot be read
+	pub fn htpasswd<P: AsRef<Path>>(&mut self, path: P) -> Result<(), std::io::Error> {
+		let path = PathBuf::from(path.as_ref());
+		let modified = HtpasswdFile::modified(&path);
+		let users = Htpasswd::load(&path)?;
+
+		self.htpasswd = Some(Mutex::new(HtpasswdFile {
+			path,
+			modified,
+			users: Arc::new(users),
+		}));
+		Ok(())
+	}
+
+	/// Accepts the given Bearer token for the given user
+	pub fn bearer_token(&mut self, token: &str, user: &str) {
+		self.tokens.push((String::from(token), String::from(user)));
+	}
+
+	/// Returns the user the request authenticates as, if the credentials are valid
+	fn authenticate(&self, req: &Request) -> Option<String> {
+		match req.headers.authorization()? {
+			Authorization::Basic { user, password } => {
+				let users = {
+					let mut file = match self.htpasswd.as_ref()?.lock() {
+						Ok(f) => f,
+						Err(e) => e.into_inner(),
+					};
+					file.reload_if_changed();
+					Arc::clone(&file.users)
+				};
+				if users.verify(&user, &password) {
+					return Some(user);
+				}
+				None
+			}
+			Authorization::Bearer(credentials) => {
+				// Every token is compared to avoid revealing which one matched by timing
+				let mut user = None;
+				for (token, token_user) in &self.tokens {
+					if constant_time_eq(token.as_bytes(), credentials.as_bytes()) {
+						user = Some(token_user.clone());
+					}
+				}
+				user
+			}
+			Authorization::Other { .. } => None,
+		}
+	}
+}
+
+impl RequestHandler for AuthHandler {
+	fn matches(&self, req: &Request) -> bool {
+		self.inner.matches(req)
+	}
+
+	fn handle(&self, req: &Request, mut res: Response) {
+		if let Some(user) = self.authenticate(req) {
+			req.set_user(&user);
+			self.inner.handle(req, res);
+			return;
+		}
+
+		let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+		if self.htpasswd.is_some() || self.tokens.is_empty() {
+			res.headers.add(
+				"WWW-Authenticate",
+				&format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm),
+			);
+		}
+		if !self.tokens.is_empty() {
+			let error = match req.headers.get("Authorization") {
+				Some(a) if a.trim_start().to_ascii_lowercase().starts_with("bearer ") => {
+					", error=\"invalid_token\""
+				}
+				_ => "",
+			};
+			res.headers.add(
+				"WWW-Authenticate",
+				&format!("Bearer realm=\"{}\"{}", realm, error),
+			);
+		}
+
+		let _ = res.send_error(&Error::new(401, "Authentication required"));
+	}
+
+	fn route(&self) -> Option<String> {
+		self.inner.route()
+	}
+}
@@ -0,0 +1,180 @@
+use super::ValuesMap;
+use std::io::prelude::*;
+
+/// Maximum size of the start line and header fields of a message
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Headers that only apply to a single connection and must not be forwarded by proxies
+pub const HOP_BY_HOP_HEADERS: [&str; 8] = [
+	"Connection",
+	"Keep-Alive",
+	"Proxy-Authenticate",
+	"Proxy-Authorization",
+	"Te",
+	"Trailer",
+	"Transfer-Encoding",
+	"Upgrade",
+];
+
+fn invalid_data(message: &str) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Reads a single line terminated by LF (or CRLF) and returns it without the line break. Fails if the line is longer
+/// than max bytes or the stream ends before the line is complete.
+pub fn read_line<R: BufRead>(reader: &mut R, max: usize) -> Result<String, std::io::Error> {
+	let mut line: Vec<u8> = Vec::new();
+	let read = reader.take(max as u64 + 1).read_until(b'\n', &mut line)?;
+
+	if read == 0 || line.last() != Some(&b'\n') {
+		if line.len() > max {
+			return Err(invalid_data("Line too long"));
+		}
+		return Err(std::io::Error::new(
+			std::io::ErrorKind::UnexpectedEof,
+			"Connection closed before the end of the line",
+		));
+	}
+
+	line.pop();
+	if line.last() == Some(&b'\r') {
+		line.pop();
+	}
+
+	match String::from_utf8(line) {
+		Ok(l) => Ok(l),
+		Err(_) => Err(invalid_data("Line is not valid UTF-8")),
+	}
+}
+
+/// Reads the start line and header fields of an HTTP message up to the empty line that separates them from the body.
+/// Header names in the returned [ValuesMap] are case-normalized.
+pub fn read_head<R: BufRead>(reader: &mut R) -> Result<(String, ValuesMap), std::io::Error> {
+	let start_line = read_line(reader, MAX_HEAD_SIZE)?;
+
+	let mut headers = ValuesMap::new();
+	headers.case_handling = true;
+
+	let mut size = start_line.len();
+	loop {
+		let line = read_line(reader, MAX_HEAD_SIZE)?;
+		size += line.len();
+		if size > MAX_HEAD_SIZE {
+			return Err(invalid_data("Header too large"));
+		}
+
+		if line.is_empty() {
+			break;
+		}
+
+		match line.find(':') {
+			Some(p) => headers.add(line[..p].trim(), line[p + 1..].trim()),
+			None => return Err(invalid_data("Invalid header line")),
+		}
+	}
+
+	Ok((start_line, headers))
+}
+
+/// Returns true if the given header lists "chunked" as transfer coding
+pub fn is_chunked(headers: &ValuesMap) -> bool {
+	match headers.get("Transfer-Encoding") {
+		Some(te) => te
+			.split(',')
+			.any(|c| c.trim().eq_ignore_ascii_case("chunked")),
+		None => false,
+	}
+}
+
+/// Writes everything read from reader to writer with chunked transfer coding, including the last chunk. Returns the
+/// number of body bytes written
+pub fn copy_chunked<R: Read, W: Write>(
+	reader: &mut R,
+	writer: &mut W,
+) -> Result<u64, std::io::Error> {
+	let mut buffer = [0; 16 * 1024];
+	let mut written = 0;
+	loop {
+		let read = match reader.read(&mut buffer) {
+			Ok(0) => break,
+			Ok(r) => r,
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e),
+		};
+
+		writer.write_all(format!("{:x}\r\n", read).as_bytes())?;
+		writer.write_all(&buffer[..read])?;
+		writer.write_all(b"\r\n")?;
+		written += read as u64;
+	}
+
+	writer.write_all(b"0\r\n\r\n")?;
+	Ok(written)
+}
+
+/// Reader that decodes a body sent with chunked transfer coding. Trailer fields are read and discarded. Reading ends
+/// with the last chunk, a connection that closes before is reported as error.
+pub struct ChunkedReader<R: BufRead> {
+	inner: R,
+	remaining: usize,
+	done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+	/// Creates a reader that decodes the chunked body read from inner
+	pub fn new(inner: R) -> ChunkedReader<R> {
+		ChunkedReader {
+			inner,
+			remaining: 0,
+			done: false,
+		}
+	}
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+		if self.done || buf.is_empty() {
+			return Ok(0);
+		}
+
+		if self.remaining == 0 {
+			let line = read_line(&mut self.inner, 1024)?;
+			// Chunk extensions are ignored
+			let size = line.split(';').next().unwrap_or("").trim();
+			// from_str_radix also accepts a sign, which other parsers may read differently
+			if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+				return Err(invalid_data("Invalid chunk size"));
+			}
+			self.remaining = match usize::from_str_radix(size, 16) {
+				Ok(s) => s,
+				Err(_) => return Err(invalid_data("Invalid chunk size")),
+			};
+
+			if self.remaining == 0 {
+				// Skip trailer fields up to the final empty line
+				while !read_line(&mut self.inner, MAX_HEAD_SIZE)?.is_empty() {}
+				self.done = true;
+				return Ok(0);
+			}
+		}
+
+		let max = std::cmp::min(buf.len(), self.remaining);
+		let read = self.inner.read(&mut buf[..max])?;
+		if read == 0 {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::UnexpectedEof,
+				"Connection closed before the end of the chunk",
+			));
+		}
+
+		self.remaining -= read;
+		if self.remaining == 0 {
+			// Every chunk is followed by a line break
+			if !read_line(&mut self.inner, 2)?.is_empty() {
+				return Err(invalid_data("Missing line break after chunk"));
+			}
+		}
+
+		Ok(read)
+	}
+}
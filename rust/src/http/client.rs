@@ -0,0 +1,540 @@
+use super::body::{is_chunked, read_head, ChunkedReader};
+use super::{methods, ValuesMap};
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Maximum number of idle connections kept per host
+const MAX_IDLE_PER_HOST: usize = 4;
+
+/// Idle connections by host and port
+type ConnectionPool = HashMap<(String, u16), Vec<BufReader<TcpStream>>>;
+
+fn invalid_input(message: String) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// The parts of an http:// URL
+#[derive(Debug, Clone, PartialEq)]
+struct Url {
+	host: String,
+	port: u16,
+	/// Path including the query string
+	path: String,
+}
+
+impl Url {
+	fn parse(url: &str) -> Result<Url, std::io::Error> {
+		let rest = match url.get(..7) {
+			Some(scheme) if scheme.eq_ignore_ascii_case("http://") => &url[7..],
+			_ => {
+				return Err(invalid_input(format!(
+					"Unsupported URL {}, only http:// is supported",
+					url
+				)))
+			}
+		};
+
+		// The fragment is never sent to the server
+		let rest = &rest[..rest.find('#').unwrap_or(rest.len())];
+		let (authority, path) = match rest.find(['/', '?']) {
+			Some(p) if rest[p..].starts_with('?') => (&rest[..p], format!("/{}", &rest[p..])),
+			Some(p) => (&rest[..p], String::from(&rest[p..])),
+			None => (rest, String::from("/")),
+		};
+
+		if authority.contains('@') {
+			return Err(invalid_input(format!(
+				"User information in URLs is not supported: {}",
+				url
+			)));
+		}
+
+		let (host, port) = match authority.rfind(':') {
+			// A colon inside brackets belongs to an IPv6 address
+			Some(p) if !authority[p..].contains(']') => match authority[p + 1..].parse() {
+				Ok(port) => (&authority[..p], port),
+				Err(_) => return Err(invalid_input(format!("Invalid port in URL {}", url))),
+			},
+			_ => (authority, 80),
+		};
+
+		if host.is_empty() {
+			return Err(invalid_input(format!("Missing host in URL {}", url)));
+		}
+
+		Ok(Url {
+			host: host.to_ascii_lowercase(),
+			port,
+			path,
+		})
+	}
+
+	/// Returns the value of the Host header
+	fn authority(&self) -> String {
+		if self.port == 80 {
+			self.host.clone()
+		} else {
+			format!("{}:{}", self.host, self.port)
+		}
+	}
+
+	/// Returns the URL the given Location header value refers to
+	fn join(&self, location: &str) -> Result<Url, std::io::Error> {
+		if location.contains("://") {
+			return Url::parse(location);
+		}
+		if let Some(rest) = location.strip_prefix("//") {
+			return Url::parse(&format!("http://{}", rest));
+		}
+
+		let path = if location.starts_with('/') {
+			String::from(location)
+		} else {
+			let own_path = &self.path[..self.path.find('?').unwrap_or(self.path.len())];
+			let directory = &own_path[..own_path.rfind('/').map(|p| p + 1).unwrap_or(0)];
+			format!("{}{}", directory, location)
+		};
+
+		Ok(Url {
+			host: self.host.clone(),
+			port: self.port,
+			path: String::from(&path[..path.find('#').unwrap_or(path.len())]),
+		})
+	}
+}
+
+impl std::fmt::Display for Url {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "http://{}{}", self.authority(), self.path)
+	}
+}
+
+/// A response received by a [Client]
+#[derive(Debug, Clone)]
+pub struct ClientResponse {
+	/// The URL the response was received from, which differs from the requested URL after redirects
+	pub url: String,
+	/// The HTTP status code
+	pub status_code: u16,
+	/// The status string sent along the status code
+	pub status: String,
+	/// The response headers. Header names are case-normalized
+	pub headers: ValuesMap,
+	/// The response body, decoded from chunked transfer coding if necessary
+	pub body: Vec<u8>,
+}
+
+impl ClientResponse {
+	/// Returns the body as string, replacing invalid UTF-8 sequences
+	pub fn text(&self) -> String {
+		String::from_utf8_lossy(&self.body).into_owned()
+	}
+
+	/// Returns true for 2xx status codes
+	pub fn is_success(&self) -> bool {
+		(200..300).contains(&self.status_code)
+	}
+}
+
+/// A blocking HTTP/1.1 client for plain http:// URLs.
+///
+/// Connections are kept open after a request and reused for the next request to the same host and port. Redirects are
+/// followed up to [Client::max_redirects] times, 303 responses and 301/302 responses to POST requests are followed
+/// with a GET request without body, HEAD requests always with HEAD. A Client can be shared between threads.
+///
+/// # Example
+///
+/// ```no_run
+/// use mi::http::*;
+///
+/// let client = Client::new();
+/// let res = client.get("http://localhost:8080/status")?;
+/// println!("{} {}", res.status_code, res.text());
+///
+/// let mut headers = ValuesMap::new();
+/// headers.set("Accept", "application/json");
+/// let res = client.request("PUT", "http://localhost:8080/items/1", &headers, b"{\"done\":true}")?;
+/// assert!(res.is_success());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Client {
+	/// Timeout for connecting and for each read or write. None waits indefinitely. Defaults to 30 seconds
+	pub timeout: Option<Duration>,
+	/// Maximum number of redirects to follow before failing. 0 disables following redirects and returns the redirect
+	/// response instead. Defaults to 10
+	pub max_redirects: usize,
+	/// Headers sent with every request unless the request sets them itself
+	pub headers: ValuesMap,
+	idle: Mutex<ConnectionPool>,
+}
+
+impl Client {
+	/// Creates a new client with default settings
+	pub fn new() -> Client {
+		let mut headers = ValuesMap::new();
+		headers.case_handling = true;
+		headers.set("User-Agent", concat!("mi/", env!("CARGO_PKG_VERSION")));
+
+		Client {
+			timeout: Some(Duration::from_secs(30)),
+			max_redirects: 10,
+			headers,
+			idle: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Sends a GET request
+	pub fn get(&self, url: &str) -> Result<ClientResponse, std::io::Error> {
+		self.request(methods::GET, url, &ValuesMap::new(), &[])
+	}
+
+	/// Sends a POST request with the given body and Content-Type
+	pub fn post(
+		&self,
+		url: &str,
+		content_type: &str,
+		body: &[u8],
+	) -> Result<ClientResponse, std::io::Error> {
+		let mut headers = ValuesMap::new();
+		headers.set("Content-Type", content_type);
+		self.request(methods::POST, url, &headers, body)
+	}
+
+	/// Sends a request with the given method, headers and body and returns the response once it was read completely.
+	/// Content-Length, Host and Connection headers are set by the client.
+	pub fn request(
+		&self,
+		method: &str,
+		url: &str,
+		headers: &ValuesMap,
+		body: &[u8],
+	) -> Result<ClientResponse, std::io::Error> {
+		let mut url = Url::parse(url)?;
+		let mut method = String::from(method);
+		let mut body = body;
+
+		let mut headers_copy = ValuesMap::new();
+		headers_copy.case_handling = true;
+		for (name, values) in headers.all() {
+			for value in values {
+				headers_copy.add(name, value);
+			}
+		}
+		let mut headers = headers_copy;
+
+		let mut redirects = 0;
+		loop {
+			let response = self.send(&method, &url, &headers, body)?;
+
+			let location = match response.headers.get("Location") {
+				Some(l) if self.max_redirects > 0 => l,
+				_ => return Ok(response),
+			};
+
+			match response.status_code {
+				301..=303 => {
+					// HEAD requests stay HEAD requests
+					if method != methods::HEAD
+						&& (response.status_code == 303 || method == methods::POST)
+					{
+						method = String::from(methods::GET);
+						body = &[];
+						headers.remove("Content-Type");
+					}
+				}
+				307 | 308 => {}
+				_ => return Ok(response),
+			}
+
+			redirects += 1;
+			if redirects > self.max_redirects {
+				return Err(std::io::Error::other(format!(
+					"Too many redirects, last location was {}",
+					location
+				)));
+			}
+
+			let next = url.join(location)?;
+			if next.host != url.host || next.port != url.port {
+				// Credentials are only meant for the original host
+				headers.remove("Authorization");
+				headers.remove("Cookie");
+			}
+			url = next;
+		}
+	}
+
+	/// Sends a single request. Idempotent requests are retried once on a fresh connection if a reused connection was
+	/// closed by the server, others may have been processed already and fail instead
+	fn send(
+		&self,
+		method: &str,
+		url: &Url,
+		headers: &ValuesMap,
+		body: &[u8],
+	) -> Result<ClientResponse, std::io::Error> {
+		let mut head = format!(
+			"{} {} HTTP/1.1\r\nHost: {}\r\n",
+			method,
+			url.path,
+			url.authority()
+		);
+		let defaults = self
+			.headers
+			.all()
+			.iter()
+			.filter(|(name, _)| headers.get(name).is_none());
+		for (name, values) in headers.all().iter().chain(defaults) {
+			if name.eq_ignore_ascii_case("Host")
+				|| name.eq_ignore_ascii_case("Content-Length")
+				|| name.eq_ignore_ascii_case("Connection")
+			{
+				continue;
+			}
+			for value in values {
+				head.push_str(&format!("{}: {}\r\n", name, value));
+			}
+		}
+		if !body.is_empty() || method == methods::POST || method == methods::PUT {
+			head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+		}
+		head.push_str("\r\n");
+
+		if let Some(connection) = self.take_idle(url) {
+			match self.exchange(connection, method, url, head.as_bytes(), body) {
+				Ok(r) => return Ok(r),
+				// The server may have closed the idle connection in the meantime
+				Err(e) if is_idempotent(method) && is_stale(&e) => {}
+				Err(e) => return Err(e),
+			}
+		}
+
+		let connection = self.connect(url)?;
+		self.exchange(connection, method, url, head.as_bytes(), body)
+	}
+
+	/// Writes the request to the connection and reads the response. Returns the connection to the idle pool if it can
+	/// be reused.
+	fn exchange(
+		&self,
+		mut connection: BufReader<TcpStream>,
+		method: &str,
+		url: &Url,
+		head: &[u8],
+		body: &[u8],
+	) -> Result<ClientResponse, std::io::Error> {
+		{
+			let stream = connection.get_mut();
+			stream.write_all(head)?;
+			stream.write_all(body)?;
+			stream.flush()?;
+		}
+
+		let (status_line, headers, status_code) = loop {
+			let (status_line, headers) = read_head(&mut connection)?;
+
+			let mut parts = status_line.splitn(3, ' ');
+			let version = parts.next().unwrap_or("");
+			let status_code: u16 = match parts.next().map(|c| c.parse()) {
+				Some(Ok(c)) if version.starts_with("HTTP/1.") && (100..600).contains(&c) => c,
+				_ => {
+					return Err(invalid_data(format!(
+						"Invalid status line: {}",
+						status_line
+					)))
+				}
+			};
+
+			// Skip interim responses like 100 Continue
+			if status_code >= 200 {
+				break (status_line, headers, status_code);
+			}
+		};
+
+		let mut reusable = !status_line.starts_with("HTTP/1.0")
+			&& !headers
+				.get("Connection")
+				.map(|c| c.to_ascii_lowercase().contains("close"))
+				.unwrap_or(false);
+
+		let mut body = Vec::new();
+		if method != methods::HEAD && status_code != 204 && status_code != 304 {
+			if is_chunked(&headers) {
+				ChunkedReader::new(&mut connection).read_to_end(&mut body)?;
+			} else if let Some(length) = headers.get("Content-Length") {
+				let length: u64 = match length.parse() {
+					Ok(l) => l,
+					Err(_) => {
+						return Err(invalid_data(format!("Invalid Content-Length: {}", length)))
+					}
+				};
+				(&mut connection).take(length).read_to_end(&mut body)?;
+				if (body.len() as u64) < length {
+					return Err(std::io::Error::new(
+						std::io::ErrorKind::UnexpectedEof,
+						"Connection closed before the end of the body",
+					));
+				}
+			} else {
+				// The body ends when the server closes the connection
+				connection.read_to_end(&mut body)?;
+				reusable = false;
+			}
+		}
+
+		if reusable {
+			self.put_idle(url, connection);
+		}
+
+		Ok(ClientResponse {
+			url: url.to_string(),
+			status_code,
+			status: String::from(status_line.splitn(3, ' ').nth(2).unwrap_or("")),
+			headers,
+			body,
+		})
+	}
+
+	fn connect(&self, url: &Url) -> Result<BufReader<TcpStream>, std::io::Error> {
+		let host = url.host.trim_start_matches('[').trim_end_matches(']');
+
+		let mut last_error = std::io::Error::new(
+			std::io::ErrorKind::NotFound,
+			format!("Cannot resolve {}", url.host),
+		);
+		for addr in (host, url.port).to_socket_addrs()? {
+			let stream = match self.timeout {
+				Some(t) => TcpStream::connect_timeout(&addr, t),
+				None => TcpStream::connect(addr),
+			};
+
+			match stream {
+				Ok(s) => {
+					s.set_read_timeout(self.timeout)?;
+					s.set_write_timeout(self.timeout)?;
+					s.set_nodelay(true)?;
+					return Ok(BufReader::new(s));
+				}
+				Err(e) => last_error = e,
+			}
+		}
+
+		Err(last_error)
+	}
+
+	fn take_idle(&self, url: &Url) -> Option<BufReader<TcpStream>> {
+		let mut idle = match self.idle.lock() {
+			Ok(i) => i,
+			Err(e) => e.into_inner(),
+		};
+		let connections = idle.get_mut(&(url.host.clone(), url.port))?;
+		while let Some(connection) = connections.pop() {
+			if is_open(&connection) {
+				return Some(connection);
+			}
+		}
+		None
+	}
+
+	fn put_idle(&self, url: &Url, connection: BufReader<TcpStream>) {
+		let mut idle = match self.idle.lock() {
+			Ok(i) => i,
+			Err(e) => e.into_inner(),
+		};
+		let connections = idle.entry((url.host.clone(), url.port)).or_default();
+		if connections.len() < MAX_IDLE_PER_HOST {
+			connections.push(connection);
+		}
+	}
+}
+
+impl Default for Client {
+	fn default() -> Self {
+		Client::new()
+	}
+}
+
+/// Returns true for methods that can be sent again without changing the outcome
+fn is_idempotent(method: &str) -> bool {
+	matches!(
+		method,
+		methods::GET | methods::HEAD | methods::PUT | methods::DELETE | methods::OPTIONS
+	)
+}
+
+/// Returns true if an idle connection can still be used. Servers that close a connection after the response, without
+/// saying so with "Connection: close", are noticed here instead of when sending the next request
+fn is_open(connection: &BufReader<TcpStream>) -> bool {
+	// Unread data means the previous response was longer than announced
+	if !connection.buffer().is_empty() {
+		return false;
+	}
+	let stream = connection.get_ref();
+	if stream.set_nonblocking(true).is_err() {
+		return false;
+	}
+	let open = match stream.peek(&mut [0]) {
+		Err(e) => e.kind() == std::io::ErrorKind::WouldBlock,
+		// Either the end of the stream or data nobody asked for
+		Ok(_) => false,
+	};
+	open && stream.set_nonblocking(false).is_ok()
+}
+
+/// Returns true if the error indicates that a reused connection had been closed by the server before the request
+fn is_stale(e: &std::io::Error) -> bool {
+	matches!(
+		e.kind(),
+		std::io::ErrorKind::UnexpectedEof
+			| std::io::ErrorKind::BrokenPipe
+			| std::io::ErrorKind::ConnectionReset
+			| std::io::ErrorKind::ConnectionAborted
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::http::{Handler, Server};
+	use std::sync::Arc;
+
+	#[test]
+	fn requests_after_the_server_closed_the_connection() {
+		let port = 18475;
+		std::thread::spawn(move || {
+			let mut server = Server::new();
+			server.handler(Arc::new(Handler::new(
+				|_| true,
+				|req, mut res| {
+					let mut body = String::new();
+					let _ = req.body_reader().read_to_string(&mut body);
+					res.w(format!("{} {}", req.method, body))
+				},
+			)));
+			server.listen(port).unwrap();
+		});
+
+		let client = Client::new();
+		let url = format!("http://127.0.0.1:{}/", port);
+		let start = std::time::Instant::now();
+		while client.get(&url).is_err() {
+			assert!(start.elapsed() < Duration::from_secs(5));
+			std::thread::sleep(Duration::from_millis(10));
+		}
+
+		for i in 0..3 {
+			let body = format!("{}", i);
+			let res = client.post(&url, "text/plain", body.as_bytes()).unwrap();
+			assert_eq!(res.headers.get("Connection"), Some("close"));
+			assert_eq!(res.text(), format!("POST {}", i));
+		}
+	}
+}
@@ -0,0 +1,219 @@
+use super::{methods, Error, Request, RequestHandler, Response};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Returns true if value matches the pattern, in which "*" stands for any sequence of characters
+fn glob_match(pattern: &str, value: &str) -> bool {
+	let mut parts = pattern.split('*');
+	let first = parts.next().unwrap_or("");
+	if !value.starts_with(first) {
+		return false;
+	}
+
+	let mut rest = &value[first.len()..];
+	let mut parts: Vec<&str> = parts.collect();
+	let last = match parts.pop() {
+		Some(l) => l,
+		// No wildcard in the pattern
+		None => return rest.is_empty(),
+	};
+
+	for part in parts {
+		match rest.find(part) {
+			Some(p) => rest = &rest[p + part.len()..],
+			None => return false,
+		}
+	}
+	rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// A [RequestHandler] that wraps another handler and adds Cross-Origin Resource Sharing headers to its responses.
+/// Preflight requests (OPTIONS with Access-Control-Request-Method) are answered directly without calling the inner
+/// handler.
+///
+/// Allowed origins are given as exact origins like "https://app.example.test" or as patterns in which "*" matches any
+/// sequence of characters, like "https://*.example.test" or "http://localhost:*". A single "*" allows all origins,
+/// unless credentials are allowed. Requests from other origins are passed on without CORS headers, so browsers reject
+/// the response.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::*;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let api = Arc::new(Handler::new(|r| r.uri.starts_with("/api/"), |_, mut res| {
+///     res.headers.set("Content-Type", "application/json");
+///     res.w("{}");
+/// }));
+///
+/// let mut cors = CorsHandler::new(api, &["https://app.example.test", "http://localhost:*"]);
+/// cors.allowed_methods.push(String::from("DELETE"));
+/// cors.allowed_headers.push(String::from("Authorization"));
+/// cors.allow_credentials = true;
+/// cors.max_age = Some(Duration::from_secs(600));
+///
+/// let mut server = Server::new();
+/// server.handler(Arc::new(cors));
+/// ```
+pub struct CorsHandler {
+	/// Origins or origin patterns that may access the resources. Defaults to none
+	pub allowed_origins: Vec<String>,
+	/// Methods allowed in cross-origin requests. Defaults to GET, HEAD and POST
+	pub allowed_methods: Vec<String>,
+	/// Request headers allowed in cross-origin requests besides the CORS-safelisted ones. "*" allows all headers.
+	/// Defaults to Content-Type
+	pub allowed_headers: Vec<String>,
+	/// Response headers that scripts may read besides the CORS-safelisted ones. Defaults to none
+	pub exposed_headers: Vec<String>,
+	/// Whether or not requests may include cookies and HTTP authentication. Origins have to be listed explicitly or as
+	/// patterns then, a single "*" does not allow any origin with credentials. Defaults to false
+	pub allow_credentials: bool,
+	/// How long browsers may cache the result of a preflight request. Defaults to None, which leaves it to the browser
+	pub max_age: Option<Duration>,
+	inner: Arc<dyn RequestHandler>,
+}
+
+impl CorsHandler {
+	/// Wraps the given handler, allowing requests from the given origins or origin patterns
+	pub fn new(inner: Arc<dyn RequestHandler>, allowed_origins: &[&str]) -> CorsHandler {
+		CorsHandler {
+			allowed_origins: allowed_origins.iter().map(|o| String::from(*o)).collect(),
+			allowed_methods: vec![
+				String::from(methods::GET),
+				String::from(methods::HEAD),
+				String::from(methods::POST),
+			],
+			allowed_headers: vec![String::from("Content-Type")],
+			exposed_headers: Vec::new(),
+			allow_credentials: false,
+			max_age: None,
+			inner,
+		}
+	}
+
+	fn allows_any_origin(&self) -> bool {
+		self.allowed_origins.iter().any(|o| o == "*")
+	}
+
+	fn is_allowed_origin(&self, origin: &str) -> bool {
+		self.allowed_origins
+			.iter()
+			// Browsers deliberately refuse credentials for "*", reflecting every origin instead would circumvent that
+			.filter(|pattern| !(self.allow_credentials && *pattern == "*"))
+			.any(|pattern| glob_match(&pattern.to_ascii_lowercase(), &origin.to_ascii_lowercase()))
+	}
+
+	fn is_allowed_method(&self, method: &str) -> bool {
+		self.allowed_methods.iter().any(|m| m == method)
+	}
+
+	fn is_allowed_header(&self, header: &str) -> bool {
+		self.allowed_headers
+			.iter()
+			.any(|h| h == "*" || h.eq_ignore_ascii_case(header))
+	}
+
+	/// Sets the headers shared by preflight and actual responses for an allowed origin
+	fn allow_origin(&self, origin: &str, res: &mut Response) {
+		if self.allows_any_origin() && !self.allow_credentials {
+			res.headers.set("Access-Control-Allow-Origin", "*");
+		} else {
+			res.headers.set("Access-Control-Allow-Origin", origin);
+		}
+
+		if self.allow_credentials {
+			res.headers.set("Access-Control-Allow-Credentials", "true");
+		}
+	}
+
+	fn preflight(&self, req: &Request, origin: &str, mut res: Response) {
+		res.headers.add(
+			"Vary",
+			"Access-Control-Request-Method, Access-Control-Request-Headers",
+		);
+
+		let method = req
+			.headers
+			.get("Access-Control-Request-Method")
+			.unwrap_or("")
+			.trim();
+		let headers: Vec<&str> = req
+			.headers
+			.get("Access-Control-Request-Headers")
+			.unwrap_or("")
+			.split(',')
+			.map(|h| h.trim())
+			.filter(|h| !h.is_empty())
+			.collect();
+
+		if !self.is_allowed_origin(origin)
+			|| !self.is_allowed_method(method)
+			|| !headers.iter().all(|h| self.is_allowed_header(h))
+		{
+			let _ = res.send_error(&Error::new(403, "Cross-origin request not allowed"));
+			return;
+		}
+
+		self.allow_origin(origin, &mut res);
+		res.headers.set(
+			"Access-Control-Allow-Methods",
+			&self.allowed_methods.join(", "),
+		);
+		if !headers.is_empty() {
+			// Only the requested headers are listed, which also works for "*" with credentials
+			res.headers
+				.set("Access-Control-Allow-Headers", &headers.join(", "));
+		}
+		if let Some(max_age) = self.max_age {
+			res.headers
+				.set("Access-Control-Max-Age", &max_age.as_secs().to_string());
+		}
+
+		res.status_code = 204;
+		// Sending the headers right away avoids a Content-Length header on the 204 response
+		let _ = res.send();
+	}
+}
+
+impl RequestHandler for CorsHandler {
+	fn matches(&self, req: &Request) -> bool {
+		self.inner.matches(req)
+	}
+
+	fn handle(&self, req: &Request, mut res: Response) {
+		// The response depends on the origin unless every origin gets the same answer. This applies to requests without
+		// Origin header as well, so caches do not hand their response to cross-origin requests
+		if !self.allows_any_origin() || self.allow_credentials {
+			res.headers.add("Vary", "Origin");
+		}
+
+		let origin = match req.headers.get("Origin") {
+			Some(o) => String::from(o),
+			None => return self.inner.handle(req, res),
+		};
+
+		if req.method == methods::OPTIONS
+			&& req.headers.get("Access-Control-Request-Method").is_some()
+		{
+			return self.preflight(req, &origin, res);
+		}
+
+		if self.is_allowed_origin(&origin) {
+			self.allow_origin(&origin, &mut res);
+			if !self.exposed_headers.is_empty() {
+				res.headers.set(
+					"Access-Control-Expose-Headers",
+					&self.exposed_headers.join(", "),
+				);
+			}
+		}
+
+		self.inner.handle(req, res)
+	}
+
+	fn route(&self) -> Option<String> {
+		self.inner.route()
+	}
+}
@@ -1,4 +1,11 @@
-use super::util::lookup_status_str;
+use super::negotiate;
+use super::util::{html_escape, lookup_status_str};
+use super::Response;
+use std::sync::Arc;
+
+/// Function that writes the body of an error response. It receives the error, the Accept header of the request (empty
+/// if there was none) and the response, whose status is already set. See [render_error] for the default.
+pub type ErrorRenderer = Arc<dyn Fn(&Error, &str, &mut Response) + Send + Sync>;
 
 #[derive(Debug)]
 /// An HTTP error that can be converted into a response
@@ -16,12 +23,20 @@ impl Error {
 	pub fn new<S: AsRef<str>>(code: u16, message: S) -> Error {
 		Error {
 			code,
-			/// Returns the status string for the given HTTP status code. If given an unknown code the
 			status: lookup_status_str(code),
 			message: String::from(message.as_ref()),
 		}
 	}
 
+	/// Returns a 406 Not Acceptable error listing the offered representations, for requests whose Accept headers match
+	/// none of them, see [super::Request::negotiate_type]
+	pub fn not_acceptable(offered: &[&str]) -> Error {
+		Error::new(
+			406,
+			format!("Available representations: {}", offered.join(", ")),
+		)
+	}
+
 	/// Returns a boxed error with the given properties
 	pub fn boxed<S: AsRef<str>>(code: u16, message: S) -> Box<Error> {
 		Box::new(Self::new(code, message))
@@ -35,3 +50,75 @@ impl std::fmt::Display for Error {
 }
 
 impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+	/// Maps missing files to 404, missing permissions to 403, invalid or incomplete input like a request body the client
+	/// did not finish to 400 and everything else to 500
+	fn from(e: std::io::Error) -> Error {
+		let code = match e.kind() {
+			std::io::ErrorKind::NotFound => 404,
+			std::io::ErrorKind::PermissionDenied => 403,
+			std::io::ErrorKind::InvalidInput
+			| std::io::ErrorKind::InvalidData
+			| std::io::ErrorKind::UnexpectedEof => 400,
+			_ => 500,
+		};
+		Error::new(code, e.to_string())
+	}
+}
+
+/// The media types error pages can be rendered in, by preference if the client accepts several equally
+const ERROR_TYPES: [&str; 3] = ["text/plain", "text/html", "application/json"];
+
+/// The default [ErrorRenderer]. Renders the error as HTML page, JSON object or plain text, whichever the client
+/// accepts. The message of server errors (5xx) is replaced with the status string to not reveal internals.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::*;
+/// use std::sync::Arc;
+///
+/// let mut server = Server::new();
+/// server.error_renderer = Arc::new(|error, accept, res| {
+///     if error.code == 404 && !accept.contains("json") {
+///         res.headers.set("Content-Type", "text/html; charset=utf-8");
+///         res.w("<h1>Nothing here</h1>");
+///     } else {
+///         render_error(error, accept, res);
+///     }
+/// });
+/// ```
+pub fn render_error(error: &Error, accept: &str, res: &mut Response) {
+	let message = if error.code >= 500 {
+		error.status
+	} else {
+		error.message.as_str()
+	};
+
+	let accept = Some(accept).filter(|a| !a.trim().is_empty());
+	match negotiate::media_type(accept, &ERROR_TYPES).unwrap_or(ERROR_TYPES[0]) {
+		"text/html" => {
+			res.headers.set("Content-Type", "text/html; charset=utf-8");
+			res.w(format!(
+				"<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0} {1}</title></head>\n<body><h1>{0} {1}</h1><p>{2}</p></body></html>\n",
+				error.code,
+				html_escape(error.status),
+				html_escape(message)
+			));
+		}
+		"application/json" => {
+			res.headers.set("Content-Type", "application/json");
+			res.w(serde_json::json!({
+				"status": error.code,
+				"error": error.status,
+				"message": message,
+			})
+			.to_string());
+		}
+		_ => {
+			res.headers.set("Content-Type", "text/plain; charset=utf-8");
+			res.w(message);
+		}
+	}
+}
@@ -0,0 +1,359 @@
+use super::body::MAX_HEAD_SIZE;
+use super::metrics::{Gauge, Metrics};
+use super::request::head_end;
+use super::util::{log, unix_time};
+use std::collections::{HashMap, VecDeque};
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Request bodies up to this size are read by the event loop before the request is handed over. Larger bodies are read
+/// by the handler
+const MAX_BUFFERED_BODY: usize = 64 * 1024;
+
+/// Maximum number of events handled per call to epoll_wait
+const MAX_EVENTS: usize = 256;
+
+/// Event token of the listening socket, connections use their file descriptor
+const LISTENER: u64 = u64::MAX;
+
+/// A connection whose request has not been handed to a handler yet
+pub(crate) struct Incoming {
+	pub(crate) stream: TcpStream,
+	/// Everything read from the connection so far
+	pub(crate) data: Vec<u8>,
+	/// When the first byte of the request arrived, or the connection was accepted if nothing arrived yet
+	pub(crate) received: Instant,
+	/// Unix timestamp of received
+	pub(crate) time: u64,
+	/// Counts the connection as waiting while the event loop holds it, as open connection once it is returned
+	pub(crate) connection: Gauge,
+	/// Tells the connection apart from earlier ones with the same file descriptor
+	id: u64,
+}
+
+/// Accepts connections and reads requests without blocking using epoll, so idle and slow clients only cost a buffer
+/// each. Connections are returned by [EventLoop::poll] once a complete request arrived.
+pub(crate) struct EventLoop {
+	epoll: RawFd,
+	listener: TcpListener,
+	connections: HashMap<RawFd, Incoming>,
+	/// File descriptors and ids of the connections in the order they were accepted, including ones that were removed
+	/// since
+	accepted: VecDeque<(RawFd, u64)>,
+	next_id: u64,
+	metrics: Arc<Metrics>,
+	log_errors: Arc<Mutex<dyn Write + Send>>,
+	idle_timeout: Option<Duration>,
+	max_waiting: Option<usize>,
+	last_sweep: Instant,
+}
+
+impl EventLoop {
+	/// Creates an event loop accepting connections from the given listener. Connections that do not send a complete
+	/// request within idle_timeout are closed, as is the oldest one when max_waiting connections are waiting
+	pub(crate) fn new(
+		listener: TcpListener,
+		metrics: Arc<Metrics>,
+		log_errors: Arc<Mutex<dyn Write + Send>>,
+		idle_timeout: Option<Duration>,
+		max_waiting: Option<usize>,
+	) -> Result<EventLoop, std::io::Error> {
+		listener.set_nonblocking(true)?;
+		let epoll = epoll_create()?;
+
+		let event_loop = EventLoop {
+			epoll,
+			listener,
+			connections: HashMap::new(),
+			accepted: VecDeque::new(),
+			next_id: 0,
+			metrics,
+			log_errors,
+			idle_timeout,
+			max_waiting,
+			last_sweep: Instant::now(),
+		};
+		event_loop.control(
+			libc::EPOLL_CTL_ADD,
+			event_loop.listener.as_raw_fd(),
+			LISTENER,
+		)?;
+
+		Ok(event_loop)
+	}
+
+	/// Waits up to timeout for new connections or data and returns the connections that received a complete request.
+	/// The returned streams are still in non-blocking mode.
+	pub(crate) fn poll(&mut self, timeout: Duration) -> Result<Vec<Incoming>, std::io::Error> {
+		let mut events = [libc::epoll_event { events: 0, u64: 0 }; MAX_EVENTS];
+		let mut ready = Vec::new();
+		let count = match epoll_wait(self.epoll, &mut events, timeout) {
+			Ok(c) => c,
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => return Ok(ready),
+			Err(e) => return Err(e),
+		};
+
+		for event in &events[..count] {
+			let token = event.u64;
+			if token == LISTENER {
+				self.accept();
+			} else if let Some(incoming) = self.read(token as RawFd) {
+				ready.push(incoming);
+			}
+		}
+
+		self.close_idle();
+
+		Ok(ready)
+	}
+
+	fn accept(&mut self) {
+		loop {
+			let stream = match self.listener.accept() {
+				Ok((s, _)) => s,
+				Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+				Err(e) => {
+					log(
+						&self.log_errors,
+						format!("Incoming connection error: {}", e),
+					);
+					return;
+				}
+			};
+
+			let fd = stream.as_raw_fd();
+			let registered = stream
+				.set_nonblocking(true)
+				.and_then(|_| self.control(libc::EPOLL_CTL_ADD, fd, fd as u64));
+			if let Err(e) = registered {
+				log(
+					&self.log_errors,
+					format!("Cannot watch incoming connection: {}", e),
+				);
+				continue;
+			}
+
+			if self
+				.max_waiting
+				.is_some_and(|max| self.connections.len() >= max)
+			{
+				self.close_oldest();
+			}
+
+			let id = self.next_id;
+			self.next_id += 1;
+			self.accepted.push_back((fd, id));
+			self.connections.insert(
+				fd,
+				Incoming {
+					stream,
+					data: Vec::new(),
+					received: Instant::now(),
+					time: unix_time(),
+					connection: self.metrics.waiting_connection(),
+					id,
+				},
+			);
+		}
+	}
+
+	/// Closes the connection that was accepted first of those still waiting for their request
+	fn close_oldest(&mut self) {
+		while let Some((fd, id)) = self.accepted.pop_front() {
+			if self.connections.get(&fd).is_some_and(|c| c.id == id) {
+				self.remove(fd);
+				return;
+			}
+		}
+	}
+
+	/// Reads everything available from the connection. Returns the connection if its request is complete now
+	fn read(&mut self, fd: RawFd) -> Option<Incoming> {
+		let incoming = self.connections.get_mut(&fd)?;
+		let mut buffer = [0; 8192];
+		let mut closed = false;
+
+		while incoming.data.len() <= MAX_HEAD_SIZE + MAX_BUFFERED_BODY {
+			match incoming.stream.read(&mut buffer) {
+				Ok(0) => {
+					closed = true;
+					break;
+				}
+				Ok(read) => {
+					if incoming.data.is_empty() {
+						incoming.received = Instant::now();
+						incoming.time = unix_time();
+					}
+					incoming.data.extend_from_slice(&buffer[..read]);
+				}
+				Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+				Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+				Err(_) => {
+					closed = true;
+					break;
+				}
+			}
+		}
+
+		match is_complete(&incoming.data) {
+			Ok(true) => {
+				let mut incoming = self.remove(fd)?;
+				incoming.connection = self.metrics.connection();
+				Some(incoming)
+			}
+			Ok(false) if !closed => None,
+			Ok(false) => {
+				self.remove(fd);
+				None
+			}
+			Err(e) => {
+				log(&self.log_errors, format!("x: Invalid Request: {}", e));
+				// Nothing was sent on the connection yet, so the short response fits into its buffer
+				let _ = incoming.stream.write_all(
+					format!(
+						"HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+						e.code, e.status
+					)
+					.as_bytes(),
+				);
+				self.remove(fd);
+				None
+			}
+		}
+	}
+
+	/// Closes connections that did not send a complete request in time. Checked at most once per second
+	fn close_idle(&mut self) {
+		let timeout = match self.idle_timeout {
+			Some(t) => t,
+			None => return,
+		};
+		if self.last_sweep.elapsed() < Duration::from_secs(1) {
+			return;
+		}
+		self.last_sweep = Instant::now();
+
+		let idle: Vec<RawFd> = self
+			.connections
+			.iter()
+			.filter(|(_, c)| c.received.elapsed() > timeout)
+			.map(|(fd, _)| *fd)
+			.collect();
+		for fd in idle {
+			self.remove(fd);
+		}
+	}
+
+	/// Stops watching the connection and returns it
+	fn remove(&mut self, fd: RawFd) -> Option<Incoming> {
+		let _ = self.control(libc::EPOLL_CTL_DEL, fd, 0);
+		let incoming = self.connections.remove(&fd);
+		// Entries of removed connections are skipped when the oldest one is closed, they are dropped once they make
+		// up most of the queue
+		if self.accepted.len() > 2 * self.connections.len() + 64 {
+			let connections = &self.connections;
+			self.accepted
+				.retain(|(fd, id)| connections.get(fd).is_some_and(|c| c.id == *id));
+		}
+		incoming
+	}
+
+	fn control(&self, op: i32, fd: RawFd, token: u64) -> Result<(), std::io::Error> {
+		let mut event = libc::epoll_event {
+			events: (libc::EPOLLIN | libc::EPOLLRDHUP) as u32,
+			u64: token,
+		};
+		epoll_ctl(self.epoll, op, fd, &mut event)
+	}
+}
+
+impl Drop for EventLoop {
+	fn drop(&mut self) {
+		close(self.epoll);
+	}
+}
+
+/// Returns true if the data contains the complete header and, unless it is too large to buffer, the body
+fn is_complete(data: &[u8]) -> Result<bool, super::Error> {
+	let header_length = match head_end(data) {
+		Some((_, l)) => l,
+		None if data.len() > MAX_HEAD_SIZE => {
+			return Err(super::Error::new(431, "Request header too large"))
+		}
+		None => return Ok(false),
+	};
+
+	let body_length = content_length(&data[..header_length]);
+	Ok(body_length > MAX_BUFFERED_BODY || data.len() - header_length >= body_length)
+}
+
+/// Returns the value of the Content-Length header in the given request header, 0 if missing or invalid
+fn content_length(head: &[u8]) -> usize {
+	for line in head.split(|c| *c == b'\n') {
+		let line = String::from_utf8_lossy(line);
+		if let Some(p) = line.find(':') {
+			if line[..p].trim().eq_ignore_ascii_case("Content-Length") {
+				return line[p + 1..].trim().parse().unwrap_or(0);
+			}
+		}
+	}
+	0
+}
+
+/// Converts the return value of a libc function into an error if it is negative
+fn check(result: i32) -> Result<i32, std::io::Error> {
+	if result < 0 {
+		Err(std::io::Error::last_os_error())
+	} else {
+		Ok(result)
+	}
+}
+
+// There is no epoll API in the standard library, so it is called through libc
+
+/// Creates an epoll instance that is closed on exec
+#[allow(unsafe_code)]
+fn epoll_create() -> Result<RawFd, std::io::Error> {
+	// SAFETY: epoll_create1 takes no pointers, failure is reported through the return value
+	check(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })
+}
+
+/// Adds, modifies or removes the watched file descriptor fd
+#[allow(unsafe_code)]
+fn epoll_ctl(
+	epoll: RawFd,
+	op: i32,
+	fd: RawFd,
+	event: &mut libc::epoll_event,
+) -> Result<(), std::io::Error> {
+	// SAFETY: event is a valid reference for the duration of the call and the kernel copies it
+	check(unsafe { libc::epoll_ctl(epoll, op, fd, event) })?;
+	Ok(())
+}
+
+/// Waits up to timeout for events and stores them at the start of events. Returns the number of events stored
+#[allow(unsafe_code)]
+fn epoll_wait(
+	epoll: RawFd,
+	events: &mut [libc::epoll_event],
+	timeout: Duration,
+) -> Result<usize, std::io::Error> {
+	let max_events = std::cmp::min(events.len(), i32::MAX as usize) as i32;
+	let timeout = std::cmp::min(timeout.as_millis(), i32::MAX as u128) as i32;
+	// SAFETY: the kernel writes at most max_events entries, which all fit into the mutably borrowed slice
+	let count =
+		check(unsafe { libc::epoll_wait(epoll, events.as_mut_ptr(), max_events, timeout) })?;
+	Ok(count as usize)
+}
+
+/// Closes a file descriptor not owned by any standard library type
+#[allow(unsafe_code)]
+fn close(fd: RawFd) {
+	// SAFETY: the caller owns fd and does not use it afterwards, errors on close are not actionable
+	unsafe {
+		libc::close(fd);
+	}
+}
@@ -0,0 +1,251 @@
+use super::{Request, Response};
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Interval in which keep-alive comments are sent by default
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// A single Server-Sent Event
+///
+/// # Example
+///
+/// ```
+/// use mi::http::Event;
+/// let event = Event::new("line 1\nline 2").event("update").id("42");
+///
+/// assert_eq!(event.to_string(), "event: update\nid: 42\ndata: line 1\ndata: line 2\n\n");
+///
+/// // Line breaks in the data cannot start other fields
+/// let event = Event::new("x\revent: foo\r\nid: 1").event("up\rdate");
+/// assert_eq!(event.to_string(), "event: update\ndata: x\ndata: event: foo\ndata: id: 1\n\n");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+	/// The event type, clients receive events without type as "message"
+	pub event: Option<String>,
+	/// The event ID, sent back by reconnecting clients in the Last-Event-ID header
+	pub id: Option<String>,
+	/// The reconnection time in milliseconds the client should use from now on
+	pub retry: Option<u64>,
+	/// The event data, may contain multiple lines
+	pub data: String,
+}
+
+impl Event {
+	/// Creates an event with the given data
+	pub fn new(data: &str) -> Event {
+		Event {
+			data: String::from(data),
+			..Event::default()
+		}
+	}
+
+	/// Sets the event type
+	pub fn event(mut self, event: &str) -> Event {
+		self.event = Some(String::from(event));
+		self
+	}
+
+	/// Sets the event ID
+	pub fn id(mut self, id: &str) -> Event {
+		self.id = Some(String::from(id));
+		self
+	}
+
+	/// Sets the reconnection time in milliseconds
+	pub fn retry(mut self, retry: u64) -> Event {
+		self.retry = Some(retry);
+		self
+	}
+}
+
+impl std::fmt::Display for Event {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		// Line breaks would end the field early, so they are removed from single-line fields
+		let single_line = |s: &str| s.replace(['\r', '\n', '\0'], "");
+
+		if let Some(event) = &self.event {
+			writeln!(f, "event: {}", single_line(event))?;
+		}
+		if let Some(id) = &self.id {
+			writeln!(f, "id: {}", single_line(id))?;
+		}
+		if let Some(retry) = self.retry {
+			writeln!(f, "retry: {}", retry)?;
+		}
+		// A line ends at "\r\n", "\r" or "\n", each line of the data becomes a field of its own
+		for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+			writeln!(f, "data: {}", line)?;
+		}
+		writeln!(f)
+	}
+}
+
+/// A stream of Server-Sent Events (text/event-stream) created from a [Response]. Events are written to the client
+/// immediately. Comments are sent periodically to keep the connection open through proxies, failing writes mark the
+/// stream as closed.
+///
+/// The stream can be cloned to send events from other threads. An EventStream occupies one of the server's worker
+/// threads as long as the handler keeps it open.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::*;
+///
+/// let mut server = Server::new();
+/// server.handle(|req| req.uri == "/events", |req, res| {
+///     let events = match EventStream::new(req, res) {
+///         Ok(e) => e,
+///         Err(_) => return,
+///     };
+///
+///     let mut id: u64 = match events.last_event_id() {
+///         Some(last) => last.parse().unwrap_or(0),
+///         None => 0,
+///     };
+///
+///     while !events.is_closed() {
+///         id += 1;
+///         let _ = events.send_event(&Event::new("tick").id(&id.to_string()));
+///         std::thread::sleep(std::time::Duration::from_secs(1));
+///     }
+/// });
+/// ```
+#[derive(Clone)]
+pub struct EventStream {
+	stream: Arc<Mutex<TcpStream>>,
+	closed: Arc<AtomicBool>,
+	last_event_id: Option<String>,
+	// Dropping the last sender stops the keep-alive thread
+	_keep_alive: Option<Sender<()>>,
+}
+
+impl EventStream {
+	/// Sends the event stream headers for the given request and returns the stream. Keep-alive comments are sent every
+	/// 15 seconds.
+	pub fn new(req: &Request, res: Response) -> Result<EventStream, std::io::Error> {
+		EventStream::with_keep_alive(req, res, Some(DEFAULT_KEEP_ALIVE))
+	}
+
+	/// Sends the event stream headers for the given request and returns the stream. Keep-alive comments are sent in the
+	/// given interval, None disables them.
+	pub fn with_keep_alive(
+		req: &Request,
+		mut res: Response,
+		keep_alive: Option<Duration>,
+	) -> Result<EventStream, std::io::Error> {
+		res.status_code = 200;
+		res.headers.set("Content-Type", "text/event-stream");
+		res.headers.set("Cache-Control", "no-cache");
+		// Ask reverse proxies like nginx not to buffer the stream
+		res.headers.set("X-Accel-Buffering", "no");
+
+		let stream = res.upgrade()?;
+		stream.set_nodelay(true)?;
+
+		let stream = Arc::new(Mutex::new(stream));
+		let closed = Arc::new(AtomicBool::new(false));
+
+		let keep_alive = keep_alive.map(|interval| {
+			let (stop, stopped) = channel::<()>();
+			let stream = stream.clone();
+			let closed = closed.clone();
+
+			std::thread::spawn(move || {
+				while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+					if write_to(&stream, &closed, b": keep-alive\n\n").is_err() {
+						break;
+					}
+				}
+			});
+
+			stop
+		});
+
+		Ok(EventStream {
+			stream,
+			closed,
+			last_event_id: req.headers.get("Last-Event-ID").map(String::from),
+			_keep_alive: keep_alive,
+		})
+	}
+
+	/// Returns the ID of the last event a reconnecting client received, as sent in the Last-Event-ID header
+	pub fn last_event_id(&self) -> Option<&str> {
+		self.last_event_id.as_deref()
+	}
+
+	/// Returns true once a write failed because the client disconnected
+	pub fn is_closed(&self) -> bool {
+		self.closed.load(Ordering::SeqCst)
+	}
+
+	/// Sends an unnamed event with the given data
+	pub fn send(&self, data: &str) -> Result<(), std::io::Error> {
+		self.send_event(&Event::new(data))
+	}
+
+	/// Sends the given event
+	pub fn send_event(&self, event: &Event) -> Result<(), std::io::Error> {
+		write_to(&self.stream, &self.closed, event.to_string().as_bytes())
+	}
+
+	/// Sets the reconnection time of the client in milliseconds
+	pub fn retry(&self, retry: u64) -> Result<(), std::io::Error> {
+		write_to(
+			&self.stream,
+			&self.closed,
+			format!("retry: {}\n\n", retry).as_bytes(),
+		)
+	}
+
+	/// Sends a comment, which is ignored by clients
+	pub fn comment(&self, text: &str) -> Result<(), std::io::Error> {
+		let mut data = String::new();
+		for line in text.split('\n') {
+			data.push_str(": ");
+			data.push_str(line.trim_end_matches('\r'));
+			data.push('\n');
+		}
+		data.push('\n');
+		write_to(&self.stream, &self.closed, data.as_bytes())
+	}
+
+	/// Closes the connection to the client
+	pub fn close(&self) {
+		self.closed.store(true, Ordering::SeqCst);
+		if let Ok(s) = self.stream.lock() {
+			let _ = s.shutdown(std::net::Shutdown::Both);
+		}
+	}
+}
+
+/// Writes data to the shared stream, marking it as closed if the write fails
+fn write_to(
+	stream: &Mutex<TcpStream>,
+	closed: &AtomicBool,
+	data: &[u8],
+) -> Result<(), std::io::Error> {
+	if closed.load(Ordering::SeqCst) {
+		return Err(std::io::Error::new(
+			std::io::ErrorKind::NotConnected,
+			"Event stream closed",
+		));
+	}
+
+	let mut s = match stream.lock() {
+		Ok(s) => s,
+		Err(e) => e.into_inner(),
+	};
+
+	let result = s.write_all(data).and_then(|_| s.flush());
+	if result.is_err() {
+		closed.store(true, Ordering::SeqCst);
+	}
+	result
+}
@@ -1,122 +1,201 @@
+use super::methods;
+use super::util::{datetime_parts, html_escape, human_size, percent_decode, percent_encode};
+use super::webdav;
 use crate::{log_error, log_info};
-use std::collections::HashMap;
+use serde::Serialize;
+use std::cmp::Ordering;
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Methods answered by a [FileHandler]
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// Methods answered by a writable [FileHandler]
+const ALLOWED_METHODS_WRITABLE: &str =
+	"GET, HEAD, OPTIONS, PUT, DELETE, MKCOL, PROPFIND, PROPPATCH, MOVE, COPY";
+
+/// Maximum size of PROPPATCH request bodies
+const MAX_PROPPATCH_SIZE: usize = 1024 * 1024;
+
+/// A request path mapped to the file system
+struct Target {
+	/// Normalized path relative to the URI prefix, ending with a slash if the requested path did
+	uri_path: String,
+	/// Segments of the normalized path
+	segments: Vec<String>,
+	/// Location in the file system
+	path: PathBuf,
+}
+
+/// Determines how a [FileHandler] treats symbolic links below its root directory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymlinkPolicy {
+	/// Symbolic links are followed wherever they point to
+	Follow,
+	/// Symbolic links are followed as long as their target is located within the root directory
+	FollowWithinRoot,
+	/// Requests for paths that contain a symbolic link are rejected
+	Deny,
+}
+
+/// Determines the Cache-Control header a [FileHandler] sends along with served files
+#[derive(Debug, Clone, PartialEq)]
+pub enum CachePolicy {
+	/// No Cache-Control header is sent
+	Unset,
+	/// Clients must revalidate the file before using a cached copy ("no-cache")
+	NoCache,
+	/// Clients must not store the file at all ("no-store")
+	NoStore,
+	/// Clients may cache the file for the given number of seconds ("public, max-age=...")
+	MaxAge(u32),
+	/// The file never changes under its URL and may be cached for a year ("public, max-age=31536000, immutable")
+	Immutable,
+	/// The given value is sent as Cache-Control header
+	Custom(String),
+}
+
+impl CachePolicy {
+	fn header_value(&self) -> Option<String> {
+		match self {
+			CachePolicy::Unset => None,
+			CachePolicy::NoCache => Some(String::from("no-cache")),
+			CachePolicy::NoStore => Some(String::from("no-store")),
+			CachePolicy::MaxAge(s) => Some(format!("public, max-age={}", s)),
+			CachePolicy::Immutable => Some(String::from("public, max-age=31536000, immutable")),
+			CachePolicy::Custom(v) => Some(v.clone()),
+		}
+	}
+}
+
+/// A single entry of a directory listing
+#[derive(Serialize)]
+struct ListingEntry {
+	name: String,
+	#[serde(rename = "type")]
+	kind: &'static str,
+	size: u64,
+	modified: u64,
+}
+
+impl ListingEntry {
+	fn new(name: &str, metadata: &std::fs::Metadata) -> ListingEntry {
+		ListingEntry {
+			name: String::from(name),
+			kind: if metadata.is_dir() {
+				"directory"
+			} else {
+				"file"
+			},
+			size: if metadata.is_dir() { 0 } else { metadata.len() },
+			modified: metadata
+				.modified()
+				.ok()
+				.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+				.map(|d| d.as_secs())
+				.unwrap_or(0),
+		}
+	}
+}
+
+/// JSON representation of a directory listing
+#[derive(Serialize)]
+struct Listing<'a> {
+	path: &'a str,
+	entries: &'a [ListingEntry],
+}
 
 /// Default implementation for a simple file system based handler. Serves the files under the given root path for the
 /// request uri removing the given uri prefix.
-/// If list_dirs is set to true, simple directory index pages will be generated.
-/// The mime types for files are guessed from their extension. A known list of extensions is available and can be
-/// augmented by adding to the ext2mime HashMap.
-pub struct FileHandler<'a> {
+/// If list_dirs is set to true, directory index pages will be generated. Listings are sorted by name with directories
+/// first, the "sort" (name, size or mtime) and "order" (asc or desc) query parameters change the order. Clients that
+/// send "Accept: application/json" receive the listing as JSON.
+/// The mime types for files are guessed from their extension using the global [super::mime] registry, which can be
+/// augmented with custom entries.
+///
+/// Request paths are percent-decoded and normalized before they are mapped to the file system. Paths that would leave
+/// the root directory are rejected with 403, files and directories starting with a dot are not served unless
+/// serve_hidden is set to true.
+/// HEAD requests are answered with headers only, OPTIONS requests with the allowed methods and all other methods with
+/// 405 Method Not Allowed.
+///
+/// If writable is set to true, the handler additionally implements the WebDAV class 1 methods PUT, DELETE, MKCOL,
+/// PROPFIND, PROPPATCH, MOVE and COPY, so the root directory can be mounted by WebDAV clients. Uploads are written to a
+/// temporary file that replaces the target once complete. All paths, including the Destination of MOVE and COPY, are
+/// constrained to the root directory.
+///
+/// A FileHandler owns its configuration, so it can be created from runtime values and shared as
+/// `Arc<dyn RequestHandler>`. Use [FileHandler::builder] to configure more than prefix and root.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::*;
+/// use std::sync::Arc;
+///
+/// let prefix = String::from("/static");
+/// let handler = FileHandler::builder()
+///     .prefix(&prefix)
+///     .root("./public")
+///     .index_files(&["index.html", "index.htm"])
+///     .list_dirs(true)
+///     .cache_policy(CachePolicy::MaxAge(3600))
+///     .header("X-Content-Type-Options", "nosniff")
+///     .build();
+///
+/// let mut server = Server::new();
+/// server.handler(Arc::new(handler));
+/// ```
+pub struct FileHandler {
 	/// Whether or not to generate directory listings
 	pub list_dirs: bool,
-	index: Vec<&'a str>,
-	uri_prefix: &'a str,
+	/// Whether or not to serve and list files and directories whose name starts with a dot. Defaults to false
+	pub serve_hidden: bool,
+	/// How symbolic links are handled. Defaults to [SymlinkPolicy::FollowWithinRoot]
+	pub symlinks: SymlinkPolicy,
+	/// The Cache-Control header sent with files. Defaults to [CachePolicy::Unset]
+	pub cache_policy: CachePolicy,
+	/// Whether or not to accept WebDAV requests that modify the files below the root. Defaults to false
+	pub writable: bool,
+	/// File relative to the root that is served for paths that do not exist, so client-side routers of single page
+	/// applications can handle deep links. Only requests that accept HTML and whose last path segment has no file
+	/// extension fall back, missing assets are still answered with 404. Defaults to None
+	pub spa_fallback: Option<String>,
+	index: Vec<String>,
+	uri_prefix: String,
 	root: PathBuf,
-	ext2mime: HashMap<&'a str, &'a str>,
+	headers: Vec<(String, String)>,
 }
 
-impl<'a> FileHandler<'a> {
+impl FileHandler {
 	/// Returns a new [super::RequestHandler] that serves files for the URLs that start with uri_prefix relative to the given
 	/// root path.
 	pub fn new<P: AsRef<Path>>(uri_prefix: &str, root: P) -> FileHandler {
 		FileHandler {
-			uri_prefix,
+			uri_prefix: String::from(uri_prefix),
 			root: PathBuf::from(root.as_ref()),
 			list_dirs: false,
-			index: vec!["index.html"],
-			ext2mime: HashMap::new(),
+			serve_hidden: false,
+			symlinks: SymlinkPolicy::FollowWithinRoot,
+			cache_policy: CachePolicy::Unset,
+			spa_fallback: None,
+			writable: false,
+			index: vec![String::from("index.html")],
+			headers: Vec::new(),
 		}
 	}
 
-	fn mimetype_for_extension<P: AsRef<Path>>(&self, path: P) -> String {
-		let ext = match path.as_ref().extension() {
-			None => String::from(""),
-			Some(e) => {
-				let low = e.to_ascii_lowercase();
-				let ext = low.to_string_lossy().into_owned();
-				String::from(ext)
-			}
-		};
-
-		let ext = ext.as_str();
-		String::from(match ext {
-			// Default extensions
-			"jpg" => "image/jpeg",
-			"jpeg" => "image/jpeg",
-			"png" => "image/png",
-			"gif" => "image/gif",
-			"html" => "text/html",
-			"htm" => "text/html",
-			"txt" => "text/plain",
-			"css" => "text/css",
-			"json" => "application/json",
-			"js" => "application/javascript",
-			".bz" => "application/x-bzip",
-			".bz2" => "application/x-bzip2",
-			".csv" => "text/csv",
-			".eot" => "application/vnd.ms-fontobject",
-			".epub" => "application/epub+zip",
-			".gz" => "application/gzip",
-			".gif" => "image/gif",
-			".ico" => "image/vnd.microsoft.icon",
-			".ics" => "text/calendar",
-			".jar" => "application/java-archive",
-			".mjs" => "text/javascript",
-			".mp3" => "audio/mpeg",
-			".mp4" => "video/mp4",
-			".mpeg" => "video/mpeg",
-			".odp" => "application/vnd.oasis.opendocument.presentation",
-			".ods" => "application/vnd.oasis.opendocument.spreadsheet",
-			".odt" => "application/vnd.oasis.opendocument.text",
-			".oga" => "audio/ogg",
-			".ogv" => "video/ogg",
-			".ogx" => "application/ogg",
-			".opus" => "audio/opus",
-			".otf" => "font/otf",
-			".png" => "image/png",
-			".pdf" => "application/pdf			",
-			".rar" => "application/vnd.rar",
-			".rtf" => "application/rtf",
-			".svg" => "image/svg+xml",
-			".tif" => "image/tiff			",
-			".ttf" => "font/ttf",
-			".wav" => "audio/wav",
-			".weba" => "audio/webm",
-			".webm" => "video/webm",
-			".webp" => "image/webp",
-			".woff" => "font/woff",
-			".woff2" => "font/woff2",
-			".xhtml" => "application/xhtml+xml",
-			".xml" => "application/xml			",
-			".zip" => "application/zip",
-			".7z" => "application/x-7z-compressed",
-
-			// Synamic extensions
-			_ => match self.ext2mime.get(ext) {
-				Some(v) => v,
-				None => "application/octet-stream",
-			},
-		})
+	/// Returns a [FileHandlerBuilder] that serves the current directory for all URLs unless configured otherwise
+	pub fn builder() -> FileHandlerBuilder {
+		FileHandlerBuilder {
+			handler: FileHandler::new("/", "."),
+		}
 	}
 
-	fn list_dir(&self, mut res: super::Response, path: PathBuf) -> Result<(), std::io::Error> {
-		let entries = match std::fs::read_dir(&path) {
-			Ok(e) => e,
-			Err(e) => {
-				log_error!(
-					"Internal Sever Error - Cannot list directory {}: {}",
-					path.to_string_lossy(),
-					e
-				);
-				return self.serve_error(res, 500, "Internal Server Error");
-			}
-		};
-
-		res.headers.set("Content-Type", "text/html");
-
-		res.write("<!DOCTYPE html><body><ul>")?;
-		for entry in entries {
+	/// Returns the entries of the given directory that may be served according to the hidden file and symlink settings
+	fn read_entries(&self, path: &Path) -> Result<Vec<ListingEntry>, std::io::Error> {
+		let mut listing: Vec<ListingEntry> = Vec::new();
+		for entry in std::fs::read_dir(path)? {
 			let file = match entry {
 				Ok(f) => f,
 				Err(e) => {
@@ -137,19 +216,162 @@ impl<'a> FileHandler<'a> {
 				}
 			};
 
+			if !self.serve_hidden && name.starts_with('.') {
+				continue;
+			}
+
+			if self.check_symlinks(&file.path()).is_err() {
+				continue;
+			}
+
+			let metadata = match std::fs::metadata(file.path()) {
+				Ok(m) => m,
+				Err(e) => {
+					log_error!("Error reading directory index entry {}: {}", name, e);
+					continue;
+				}
+			};
+
 			log_info!(" - {}", name);
 
-			res.write("<li>")?;
-			res.write("<a href=\"")?;
-			res.write(name.replace("\"", "\\\""))?;
-			res.write("\">")?;
-			res.write(name)?;
-			res.write("</a>")?;
-			res.write("</li>")?;
+			listing.push(ListingEntry::new(name, &metadata));
 		}
-		res.write("</ul></body>")?;
-		//let _ = res.end();
-		Ok(())
+
+		Ok(listing)
+	}
+
+	/// Returns the percent-encoded absolute URI path for the given path relative to the URI prefix
+	fn href_for(&self, uri_path: &str) -> String {
+		let mut href = String::from(self.uri_prefix.trim_end_matches('/'));
+		href.push('/');
+
+		let encoded: Vec<String> = uri_path
+			.split('/')
+			.filter(|s| !s.is_empty())
+			.map(percent_encode)
+			.collect();
+		href.push_str(&encoded.join("/"));
+
+		if uri_path.ends_with('/') && !encoded.is_empty() {
+			href.push('/');
+		}
+		href
+	}
+
+	fn list_dir(
+		&self,
+		req: &super::Request,
+		mut res: super::Response,
+		path: PathBuf,
+		uri_path: &str,
+	) -> Result<(), std::io::Error> {
+		let mut listing = match self.read_entries(&path) {
+			Ok(l) => l,
+			Err(e) => {
+				log_error!(
+					"Internal Sever Error - Cannot list directory {}: {}",
+					path.to_string_lossy(),
+					e
+				);
+				return self.serve_error(res, 500, "Internal Server Error");
+			}
+		};
+
+		let parameters = req.get_query_parameters();
+		let sort = parameters.get("sort").unwrap_or("name");
+		let descending = parameters.get("order") == Some("desc");
+		listing.sort_by(|a, b| {
+			let order = match sort {
+				"size" => a.size.cmp(&b.size),
+				"mtime" => a.modified.cmp(&b.modified),
+				_ => Ordering::Equal,
+			}
+			.then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+			.then_with(|| a.name.cmp(&b.name));
+
+			let order = if descending { order.reverse() } else { order };
+
+			// Directories are always listed first
+			(b.kind == "directory")
+				.cmp(&(a.kind == "directory"))
+				.then(order)
+		});
+
+		let display_path = format!("{}/{}", self.uri_prefix.trim_end_matches('/'), uri_path);
+
+		let accepts_json = match req.headers.get("Accept") {
+			Some(accept) => accept.contains("application/json"),
+			None => false,
+		};
+
+		if accepts_json {
+			let json = serde_json::to_string(&Listing {
+				path: &display_path,
+				entries: &listing,
+			})?;
+			res.headers.set("Content-Type", "application/json");
+			res.write(json)?;
+			return res.end();
+		}
+
+		// Links are absolute so they also work for the root directory requested without a trailing slash
+		let base = self.href_for(uri_path);
+
+		let title = html_escape(&display_path);
+		res.headers.set("Content-Type", "text/html; charset=utf-8");
+		res.write("<!DOCTYPE html><html><head><meta charset=\"utf-8\">")?;
+		write!(res, "<title>Index of {}</title></head><body>", title)?;
+		write!(res, "<h1>Index of {}</h1><table><thead><tr>", title)?;
+		for (column, label) in &[("name", "Name"), ("size", "Size"), ("mtime", "Modified")] {
+			let order = if sort == *column && !descending {
+				"desc"
+			} else {
+				"asc"
+			};
+			write!(
+				res,
+				"<th><a href=\"?sort={}&amp;order={}\">{}</a></th>",
+				column, order, label
+			)?;
+		}
+		res.write("</tr></thead><tbody>")?;
+
+		if !uri_path.is_empty() {
+			let parent = &base[..base[..base.len() - 1].rfind('/').unwrap_or(0) + 1];
+			write!(
+				res,
+				"<tr><td><a href=\"{}\">../</a></td><td></td><td></td></tr>",
+				html_escape(parent)
+			)?;
+		}
+
+		for entry in &listing {
+			let is_dir = entry.kind == "directory";
+			let slash = if is_dir { "/" } else { "" };
+			let (year, month, day, hour, minute, _) = datetime_parts(entry.modified);
+			write!(
+				res,
+				"<tr><td><a href=\"{}{}{}\">{}{}</a></td><td>{}</td><td>{:04}-{:02}-{:02} {:02}:{:02}</td></tr>",
+				html_escape(&base),
+				percent_encode(&entry.name),
+				slash,
+				html_escape(&entry.name),
+				slash,
+				if is_dir {
+					String::from("-")
+				} else {
+					human_size(entry.size)
+				},
+				year,
+				month,
+				day,
+				hour,
+				minute
+			)?;
+		}
+		res.write("</tbody></table></body></html>")?;
+
+		res.end()
 	}
 
 	fn serve_error(
@@ -158,12 +380,7 @@ impl<'a> FileHandler<'a> {
 		code: u16,
 		message: &str,
 	) -> Result<(), std::io::Error> {
-		res.headers.set("Content-Type", "text/plain");
-		res.status_code = code;
-		res.status = super::util::lookup_status_str(code);
-		res.clear();
-		res.write(message)?;
-		res.end()
+		res.send_error(&super::Error::new(code, message))
 	}
 
 	fn serve_file(&self, mut res: super::Response, path: PathBuf) -> Result<(), std::io::Error> {
@@ -172,7 +389,10 @@ impl<'a> FileHandler<'a> {
 				res.status_code = 200;
 				// Find content type
 				res.headers
-					.set("Content-Type", &self.mimetype_for_extension(path));
+					.set("Content-Type", &super::mime::content_type_for_path(path));
+				if let Some(cache_control) = self.cache_policy.header_value() {
+					res.headers.set("Cache-Control", &cache_control);
+				}
 
 				// TODO: Maybe think about ading a method for writing into the stream directly for performance reasons...?
 				res.write(&data)?;
@@ -186,8 +406,77 @@ impl<'a> FileHandler<'a> {
 		}
 	}
 
+	/// Checks the given path below the root directory against the configured [SymlinkPolicy]. Returns the HTTP status
+	/// code to respond with if the path must not be served.
+	fn check_symlinks(&self, path: &Path) -> Result<(), u16> {
+		match self.symlinks {
+			SymlinkPolicy::Follow => Ok(()),
+			SymlinkPolicy::FollowWithinRoot => {
+				// Paths that do not exist (yet) are checked by their closest existing ancestor, so uploads cannot be
+				// placed outside of the root through a linked directory
+				let target = match path.ancestors().find_map(|p| p.canonicalize().ok()) {
+					Some(t) => t,
+					None => return Ok(()),
+				};
+				let root = match self.root.canonicalize() {
+					Ok(r) => r,
+					Err(_) => return Ok(()),
+				};
+
+				if target.starts_with(root) {
+					Ok(())
+				} else {
+					Err(403)
+				}
+			}
+			SymlinkPolicy::Deny => {
+				let relative = match path.strip_prefix(&self.root) {
+					Ok(r) => r,
+					Err(_) => return Err(403),
+				};
+
+				let mut current = self.root.clone();
+				for component in relative.components() {
+					current.push(component);
+					match std::fs::symlink_metadata(&current) {
+						Ok(m) if m.file_type().is_symlink() => return Err(403),
+						Ok(_) => {}
+						Err(_) => return Ok(()),
+					}
+				}
+				Ok(())
+			}
+		}
+	}
+
+	/// Returns the path of the configured single-page-application fallback file if it should be served for a request
+	/// whose path does not exist. Requests for paths with a file extension or that do not accept HTML are answered
+	/// with 404 instead.
+	fn spa_fallback_for(&self, req: &super::Request, segments: &[String]) -> Option<PathBuf> {
+		let fallback = self.spa_fallback.as_ref()?;
+
+		if let Some(last) = segments.last() {
+			if Path::new(last).extension().is_some() {
+				return None;
+			}
+		}
+
+		match req.headers.get("Accept") {
+			Some(accept) if accept.contains("text/html") => {}
+			_ => return None,
+		}
+
+		let fallback_segments = normalize_path(fallback)?;
+		Some(
+			fallback_segments
+				.iter()
+				.fold(self.root.clone(), |p, s| p.join(s)),
+		)
+	}
+
 	fn serve(
 		&self,
+		req: &super::Request,
 		res: super::Response,
 		path: PathBuf,
 		uri_path: &str,
@@ -199,13 +488,13 @@ impl<'a> FileHandler<'a> {
 			if is_dir && has_slash {
 				for f in &self.index {
 					let path = path.join(f);
-					if path.exists() {
-						return self.serve(res, path, uri_path);
+					if path.exists() && self.check_symlinks(&path).is_ok() {
+						return self.serve(req, res, path, uri_path);
 					}
 				}
 
 				if self.list_dirs {
-					return self.list_dir(res, path);
+					self.list_dir(req, res, path, uri_path)
 				} else {
 					return self.serve_error(res, 404, &format!("Not found: {}", uri_path));
 				}
@@ -224,20 +513,511 @@ impl<'a> FileHandler<'a> {
 			return self.serve_error(res, 404, &format!("Not found: {}", uri_path));
 		}
 	}
+
+	/// Maps the given request URI to a path below the root directory. Returns the status code to respond with if the
+	/// URI is invalid or must not be served.
+	fn resolve(&self, uri: &str) -> Result<Target, u16> {
+		let raw_path = match uri.strip_prefix(self.uri_prefix.as_str()) {
+			Some(p) => p,
+			None => return Err(403),
+		};
+		let raw_path = match raw_path.find(['?', '#']) {
+			Some(p) => &raw_path[..p],
+			None => raw_path,
+		};
+
+		let decoded = match percent_decode(raw_path) {
+			Some(d) => d,
+			None => return Err(400),
+		};
+
+		let segments: Vec<String> = match normalize_path(&decoded) {
+			Some(s) => s.into_iter().map(String::from).collect(),
+			None => return Err(403),
+		};
+
+		let mut uri_path = segments.join("/");
+		if decoded.ends_with('/') && !uri_path.is_empty() {
+			uri_path.push('/');
+		}
+
+		if !self.serve_hidden && segments.iter().any(|s| s.starts_with('.')) {
+			return Err(404);
+		}
+
+		let path = segments.iter().fold(self.root.clone(), |p, s| p.join(s));
+		self.check_symlinks(&path)?;
+
+		Ok(Target {
+			uri_path,
+			segments,
+			path,
+		})
+	}
+
+	/// Responds with the given error status code
+	fn serve_status(
+		&self,
+		res: super::Response,
+		code: u16,
+		uri: &str,
+	) -> Result<(), std::io::Error> {
+		if code == 404 {
+			self.serve_error(res, code, &format!("Not found: {}", uri))
+		} else {
+			self.serve_error(res, code, super::util::lookup_status_str(code))
+		}
+	}
+
+	fn copy_or_move(&self, req: &super::Request, target: &Target) -> Result<u16, u16> {
+		let destination = match req.headers.get("Destination") {
+			Some(d) => d,
+			None => return Err(400),
+		};
+
+		// The destination may be an absolute URI
+		let destination = match destination.find("://") {
+			Some(p) => {
+				let authority_and_path = &destination[p + 3..];
+				match authority_and_path.find('/') {
+					Some(q) => &authority_and_path[q..],
+					None => "/",
+				}
+			}
+			None => destination,
+		};
+
+		let destination = self.resolve(destination)?;
+		if destination.segments.is_empty() || target.segments.is_empty() {
+			return Err(403);
+		}
+
+		webdav::copy_or_move(
+			&target.path,
+			&destination.path,
+			req.headers.get("Overwrite") != Some("F"),
+			req.method == methods::MOVE,
+			// Moving a collection always includes its members, regardless of Depth
+			req.method == methods::COPY && req.headers.get("Depth") == Some("0"),
+			&|link, target| {
+				self.check_symlinks(link).is_ok() && self.check_symlinks(target).is_ok()
+			},
+		)
+	}
+
+	fn propfind(
+		&self,
+		req: &super::Request,
+		mut res: super::Response,
+		target: &Target,
+	) -> Result<(), std::io::Error> {
+		// All live properties are returned regardless of the requested ones
+		req.copy_body_to(&mut std::io::sink())?;
+
+		let metadata = match std::fs::metadata(&target.path) {
+			Ok(m) => m,
+			Err(_) => return self.serve_status(res, 404, &req.uri),
+		};
+
+		let mut uri_path = target.uri_path.clone();
+		if metadata.is_dir() && !uri_path.is_empty() && !uri_path.ends_with('/') {
+			uri_path.push('/');
+		}
+
+		let name = target.segments.last().map(|s| s.as_str()).unwrap_or("");
+		let mut entries: Vec<(String, ListingEntry)> =
+			vec![(self.href_for(&uri_path), ListingEntry::new(name, &metadata))];
+
+		if metadata.is_dir() && req.headers.get("Depth") != Some("0") {
+			let children = match self.read_entries(&target.path) {
+				Ok(c) => c,
+				Err(e) => {
+					log_error!(
+						"Internal Sever Error - Cannot list directory {}: {}",
+						target.path.to_string_lossy(),
+						e
+					);
+					return self.serve_error(res, 500, "Internal Server Error");
+				}
+			};
+
+			for child in children {
+				let slash = if child.kind == "directory" { "/" } else { "" };
+				let href = self.href_for(&format!("{}{}{}", uri_path, child.name, slash));
+				entries.push((href, child));
+			}
+		}
+
+		let resources: Vec<webdav::Resource> = entries
+			.iter()
+			.map(|(href, e)| webdav::Resource {
+				href,
+				name: &e.name,
+				is_dir: e.kind == "directory",
+				size: e.size,
+				modified: e.modified,
+			})
+			.collect();
+
+		res.status_code = 207;
+		res.headers
+			.set("Content-Type", "application/xml; charset=utf-8");
+		res.write(webdav::propfind(&resources))?;
+		res.end()
+	}
+
+	fn proppatch(
+		&self,
+		req: &super::Request,
+		mut res: super::Response,
+		target: &Target,
+	) -> Result<(), std::io::Error> {
+		match req.headers.get("Content-Length") {
+			Some(l) if l.parse::<usize>().unwrap_or(usize::MAX) > MAX_PROPPATCH_SIZE => {
+				return self.serve_status(res, 413, &req.uri);
+			}
+			_ => {}
+		}
+
+		let mut body: Vec<u8> = Vec::new();
+		req.copy_body_to(&mut body)?;
+
+		if !target.path.exists() {
+			return self.serve_status(res, 404, &req.uri);
+		}
+
+		res.status_code = 207;
+		res.headers
+			.set("Content-Type", "application/xml; charset=utf-8");
+		res.write(webdav::proppatch(
+			&String::from_utf8_lossy(&body),
+			&self.href_for(&target.uri_path),
+		))?;
+		res.end()
+	}
 }
 
-impl<'a> super::RequestHandler for FileHandler<'a> {
+impl super::RequestHandler for FileHandler {
 	fn matches(&self, req: &super::Request) -> bool {
-		req.uri.starts_with(self.uri_prefix)
+		req.uri.starts_with(&self.uri_prefix)
 	}
 
-	fn handle(&self, req: &super::Request, res: super::Response) {
-		let mut uri_path = &req.uri[self.uri_prefix.len()..];
-		while uri_path.starts_with("/") && uri_path.len() > 0 {
-			uri_path = &uri_path[1..];
+	fn route(&self) -> Option<String> {
+		Some(self.uri_prefix.clone())
+	}
+
+	fn handle(&self, req: &super::Request, mut res: super::Response) {
+		for (name, value) in &self.headers {
+			res.headers.add(name, value);
+		}
+
+		let allowed = if self.writable {
+			ALLOWED_METHODS_WRITABLE
+		} else {
+			ALLOWED_METHODS
+		};
+
+		match req.method.as_str() {
+			methods::GET | methods::HEAD => {}
+			methods::OPTIONS => {
+				res.headers.set("Allow", allowed);
+				if self.writable {
+					res.headers.set("DAV", "1");
+				}
+				let _ = res.end();
+				return;
+			}
+			methods::PUT
+			| methods::DELETE
+			| methods::MKCOL
+			| methods::PROPFIND
+			| methods::PROPPATCH
+			| methods::MOVE
+			| methods::COPY
+				if self.writable => {}
+			_ => {
+				res.headers.set("Allow", allowed);
+				let _ = self.serve_error(res, 405, "Method Not Allowed");
+				return;
+			}
 		}
 
-		let path = self.root.join(PathBuf::from(uri_path));
-		let _ = self.serve(res, path, uri_path);
+		let target = match self.resolve(&req.uri) {
+			Ok(t) => t,
+			Err(code) => {
+				let _ = self.serve_status(res, code, &req.uri);
+				return;
+			}
+		};
+
+		let result = match req.method.as_str() {
+			methods::PUT => webdav::put(req, &target.path),
+			methods::DELETE if target.segments.is_empty() => Err(403),
+			methods::DELETE => webdav::delete(&target.path),
+			methods::MKCOL => webdav::mkcol(req, &target.path),
+			methods::MOVE | methods::COPY => self.copy_or_move(req, &target),
+			methods::PROPFIND => {
+				let _ = self.propfind(req, res, &target);
+				return;
+			}
+			methods::PROPPATCH => {
+				let _ = self.proppatch(req, res, &target);
+				return;
+			}
+			_ => {
+				let mut path = target.path;
+				if !path.exists() {
+					if let Some(fallback) = self.spa_fallback_for(req, &target.segments) {
+						if let Err(code) = self.check_symlinks(&fallback) {
+							let _ = self.serve_status(res, code, &req.uri);
+							return;
+						}
+						path = fallback;
+					}
+				}
+
+				let _ = self.serve(req, res, path, &target.uri_path);
+				return;
+			}
+		};
+
+		match result {
+			Ok(code) => {
+				res.status_code = code;
+				let _ = res.end();
+			}
+			Err(code) => {
+				let _ = self.serve_status(res, code, &req.uri);
+			}
+		}
+	}
+}
+
+/// Lexically normalizes a decoded URI path into its segments. Empty and "." segments are dropped, ".." removes the
+/// previous segment. Returns None if the path would leave its root or contains NUL bytes.
+fn normalize_path(path: &str) -> Option<Vec<&str>> {
+	if path.contains('\0') {
+		return None;
+	}
+
+	let mut segments: Vec<&str> = Vec::new();
+	for segment in path.split('/') {
+		match segment {
+			"" | "." => {}
+			".." => {
+				segments.pop()?;
+			}
+			s => segments.push(s),
+		}
+	}
+
+	Some(segments)
+}
+
+/// Builder for a [FileHandler], created by [FileHandler::builder]
+pub struct FileHandlerBuilder {
+	handler: FileHandler,
+}
+
+impl FileHandlerBuilder {
+	/// Sets the URI prefix for which the handler serves files. The prefix is removed from the request path before it
+	/// is mapped to the root directory. Defaults to "/"
+	pub fn prefix(mut self, uri_prefix: &str) -> FileHandlerBuilder {
+		self.handler.uri_prefix = String::from(uri_prefix);
+		self
+	}
+
+	/// Sets the directory the files are served from. Defaults to the current directory
+	pub fn root<P: AsRef<Path>>(mut self, root: P) -> FileHandlerBuilder {
+		self.handler.root = PathBuf::from(root.as_ref());
+		self
+	}
+
+	/// Sets the file names that are served for requests of a directory, in the order they are looked up. Defaults to
+	/// "index.html"
+	pub fn index_files<S: AsRef<str>>(mut self, names: &[S]) -> FileHandlerBuilder {
+		self.handler.index = names.iter().map(|n| String::from(n.as_ref())).collect();
+		self
+	}
+
+	/// Sets whether directory listings are generated for directories without index file. Defaults to false
+	pub fn list_dirs(mut self, list_dirs: bool) -> FileHandlerBuilder {
+		self.handler.list_dirs = list_dirs;
+		self
+	}
+
+	/// Sets the Cache-Control header sent with files. Defaults to [CachePolicy::Unset]
+	pub fn cache_policy(mut self, policy: CachePolicy) -> FileHandlerBuilder {
+		self.handler.cache_policy = policy;
+		self
+	}
+
+	/// Sets whether files and directories starting with a dot are served and listed. Defaults to false
+	pub fn serve_hidden(mut self, serve_hidden: bool) -> FileHandlerBuilder {
+		self.handler.serve_hidden = serve_hidden;
+		self
+	}
+
+	/// Sets how symbolic links are handled. Defaults to [SymlinkPolicy::FollowWithinRoot]
+	pub fn symlinks(mut self, policy: SymlinkPolicy) -> FileHandlerBuilder {
+		self.handler.symlinks = policy;
+		self
+	}
+
+	/// Enables the single page application mode: the given file relative to the root is served for HTML requests of
+	/// paths that do not exist and have no file extension
+	pub fn spa_fallback(mut self, file: &str) -> FileHandlerBuilder {
+		self.handler.spa_fallback = Some(String::from(file));
+		self
+	}
+
+	/// Sets whether WebDAV requests that modify the files below the root are accepted. Defaults to false
+	pub fn writable(mut self, writable: bool) -> FileHandlerBuilder {
+		self.handler.writable = writable;
+		self
+	}
+
+	/// Adds a header that is sent with every response of the handler
+	pub fn header(mut self, name: &str, value: &str) -> FileHandlerBuilder {
+		self.handler
+			.headers
+			.push((String::from(name), String::from(value)));
+		self
+	}
+
+	/// Returns the configured [FileHandler]
+	pub fn build(self) -> FileHandler {
+		self.handler
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::http::Server;
+	use std::io::{Read, Write};
+	use std::net::TcpStream;
+	use std::sync::Arc;
+	use std::time::Duration;
+
+	/// Creates a root directory with a public file, hidden entries and a secret file next to it
+	fn create_root(name: &str) -> PathBuf {
+		let base = std::env::temp_dir().join(format!("mi-files-{}-{}", name, std::process::id()));
+		let root = base.join("root");
+		std::fs::create_dir_all(root.join(".git")).unwrap();
+		std::fs::write(base.join("secret.txt"), "secret").unwrap();
+		std::fs::write(root.join("public.txt"), "public").unwrap();
+		std::fs::write(root.join(".env"), "hidden").unwrap();
+		std::fs::write(root.join(".git/config"), "hidden").unwrap();
+		root
+	}
+
+	/// Starts a server with the given handler and waits until it accepts connections
+	fn start(port: u16, handler: FileHandler) {
+		std::thread::spawn(move || {
+			let mut server = Server::new();
+			server.handler(Arc::new(handler));
+			server.listen(port).unwrap();
+		});
+
+		let start = std::time::Instant::now();
+		while TcpStream::connect(("127.0.0.1", port)).is_err() {
+			assert!(start.elapsed() < Duration::from_secs(5));
+			std::thread::sleep(Duration::from_millis(10));
+		}
+	}
+
+	/// Requests the path exactly as given, so it is not normalized by a client, and returns the status code
+	fn status(port: u16, path: &str) -> u16 {
+		let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+		write!(
+			stream,
+			"GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+			path
+		)
+		.unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).unwrap();
+		response
+			.split(' ')
+			.nth(1)
+			.and_then(|c| c.parse().ok())
+			.unwrap_or(0)
+	}
+
+	#[test]
+	fn paths_are_constrained_to_the_root() {
+		let root = create_root("paths");
+		start(
+			18485,
+			FileHandler::builder().prefix("/files/").root(&root).build(),
+		);
+
+		assert_eq!(status(18485, "/files/public.txt"), 200);
+		assert_eq!(status(18485, "/files/x/../public.txt"), 200);
+		assert_eq!(status(18485, "/files/../secret.txt"), 403);
+		assert_eq!(status(18485, "/files/x/../../secret.txt"), 403);
+		assert_eq!(status(18485, "/files/%2e%2e/secret.txt"), 403);
+		assert_eq!(status(18485, "/files/%2E%2E%2Fsecret.txt"), 403);
+		assert_eq!(status(18485, "/files/public.txt%00"), 403);
+	}
+
+	#[test]
+	fn hidden_segments_are_not_found() {
+		let root = create_root("hidden");
+		start(
+			18486,
+			FileHandler::builder().prefix("/files/").root(&root).build(),
+		);
+
+		assert_eq!(status(18486, "/files/.env"), 404);
+		assert_eq!(status(18486, "/files/.git/config"), 404);
+		assert_eq!(status(18486, "/files/%2eenv"), 404);
+		assert_eq!(status(18486, "/files/.git/../public.txt"), 200);
+
+		start(
+			18487,
+			FileHandler::builder()
+				.prefix("/files/")
+				.root(&root)
+				.serve_hidden(true)
+				.build(),
+		);
+		assert_eq!(status(18487, "/files/.env"), 200);
+		assert_eq!(status(18487, "/files/.git/config"), 200);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn symlinks_follow_the_policy() {
+		let root = create_root("symlinks");
+		std::os::unix::fs::symlink("public.txt", root.join("inside")).unwrap();
+		std::os::unix::fs::symlink("../secret.txt", root.join("outside")).unwrap();
+		std::os::unix::fs::symlink("..", root.join("parent")).unwrap();
+
+		let handler = |policy| {
+			FileHandler::builder()
+				.prefix("/files/")
+				.root(&root)
+				.symlinks(policy)
+				.build()
+		};
+		start(18488, handler(SymlinkPolicy::Follow));
+		start(18489, handler(SymlinkPolicy::FollowWithinRoot));
+		start(18490, handler(SymlinkPolicy::Deny));
+
+		assert_eq!(status(18488, "/files/inside"), 200);
+		assert_eq!(status(18488, "/files/outside"), 200);
+		assert_eq!(status(18488, "/files/parent/secret.txt"), 200);
+
+		assert_eq!(status(18489, "/files/inside"), 200);
+		assert_eq!(status(18489, "/files/outside"), 403);
+		assert_eq!(status(18489, "/files/parent/secret.txt"), 403);
+		assert_eq!(status(18489, "/files/parent/missing.txt"), 403);
+
+		assert_eq!(status(18490, "/files/public.txt"), 200);
+		assert_eq!(status(18490, "/files/inside"), 403);
+		assert_eq!(status(18490, "/files/outside"), 403);
+		assert_eq!(status(18490, "/files/parent/root/public.txt"), 403);
 	}
 }
@@ -1,3 +1,5 @@
+use crate::log_error;
+
 /// A combination of matcher and handler function
 pub struct Handler {
 	/// Returns true if the handling method whould be called for the given request
@@ -29,3 +31,65 @@ impl super::RequestHandler for Handler {
 		(self.handler_fn)(req, res)
 	}
 }
+
+/// A combination of matcher and handler function, where the handler function returns errors instead of writing them to
+/// the response. Errors are sent with [super::Response::send_error], so they are rendered like all other error pages
+/// of the server.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::*;
+/// use std::sync::Arc;
+///
+/// let mut server = Server::new();
+/// server.handler(Arc::new(ResultHandler::new(|r| r.uri.starts_with("/notes/"), |req, res| {
+///     let name = &req.uri["/notes/".len()..];
+///     if name.contains('/') {
+///         return Err(Error::new(400, "Invalid note name"));
+///     }
+///     let text = std::fs::read_to_string(format!("notes/{}.txt", name))?;
+///     res.w(text);
+///     Ok(())
+/// })));
+/// ```
+pub struct ResultHandler {
+	/// Returns true if the handling method whould be called for the given request
+	matcher_fn: fn(req: &super::Request) -> bool,
+
+	/// Is called for the given request if it is the first handler that matches the request
+	handler_fn: fn(&super::Request, &mut super::Response) -> Result<(), super::Error>,
+}
+
+impl ResultHandler {
+	/// Create a new ResultHandler from a matcher function and a handler function
+	pub fn new(
+		matcher_fn: fn(req: &super::Request) -> bool,
+		handler_fn: fn(&super::Request, &mut super::Response) -> Result<(), super::Error>,
+	) -> ResultHandler {
+		ResultHandler {
+			matcher_fn,
+			handler_fn,
+		}
+	}
+}
+
+impl super::RequestHandler for ResultHandler {
+	fn matches(&self, req: &super::Request) -> bool {
+		(self.matcher_fn)(req)
+	}
+
+	fn handle(&self, req: &super::Request, mut res: super::Response) {
+		let error = match (self.handler_fn)(req, &mut res) {
+			Ok(_) => return,
+			Err(e) => e,
+		};
+
+		if error.code >= 500 {
+			log_error!("Error handling {} {}: {}", req.method, req.uri, error);
+		}
+		if let Err(e) = res.send_error(&error) {
+			log_error!("Cannot send error response for {}: {}", req.uri, e);
+		}
+	}
+}
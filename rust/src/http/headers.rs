@@ -0,0 +1,290 @@
+use crate::bin::base64_decode;
+use std::fmt;
+use std::time::Duration;
+
+/// Splits a header value like "text/html; charset=utf-8" at the semicolons into its first part and its parameters.
+/// Quotes around parameter values are removed
+fn split_params(value: &str) -> (&str, Vec<(String, String)>) {
+	let mut parts = value.split(';').map(|p| p.trim());
+	let first = parts.next().unwrap_or("");
+	let params = parts
+		.filter_map(|p| {
+			let i = p.find('=')?;
+			Some((
+				p[..i].trim().to_ascii_lowercase(),
+				String::from(p[i + 1..].trim().trim_matches('"')),
+			))
+		})
+		.collect();
+	(first, params)
+}
+
+/// A media type with parameters as used in the Content-Type header, see [super::ValuesMap::content_type]
+///
+/// # Example
+///
+/// ```
+/// use mi::http::ContentType;
+/// let content_type = ContentType::parse("Text/HTML; Charset=\"UTF-8\"").unwrap();
+///
+/// assert_eq!(content_type.media_type, "text/html");
+/// assert_eq!(content_type.charset(), Some("UTF-8"));
+/// assert_eq!(content_type.to_string(), "text/html; charset=UTF-8");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentType {
+	/// Type and subtype in lower case, like "text/html"
+	pub media_type: String,
+	/// Parameters with lower case names, like ("charset", "utf-8")
+	pub params: Vec<(String, String)>,
+}
+
+impl ContentType {
+	/// Parses a media type, returns None if it has no subtype
+	pub fn parse(value: &str) -> Option<ContentType> {
+		let (media_type, params) = split_params(value);
+		let p = media_type.find('/')?;
+		if p == 0 || p == media_type.len() - 1 {
+			return None;
+		}
+
+		Some(ContentType {
+			media_type: media_type.to_ascii_lowercase(),
+			params,
+		})
+	}
+
+	/// Returns the value of the given parameter, names are compared case-insensitively
+	pub fn param(&self, name: &str) -> Option<&str> {
+		self.params
+			.iter()
+			.find(|(n, _)| n.eq_ignore_ascii_case(name))
+			.map(|(_, v)| v.as_str())
+	}
+
+	/// Returns the charset parameter
+	pub fn charset(&self) -> Option<&str> {
+		self.param("charset")
+	}
+}
+
+impl fmt::Display for ContentType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.media_type)?;
+		for (name, value) in &self.params {
+			if value.is_empty() || value.contains(|c: char| " \t;,\"()<>@:\\/[]?=".contains(c)) {
+				write!(
+					f,
+					"; {}=\"{}\"",
+					name,
+					value.replace('\\', "\\\\").replace('"', "\\\"")
+				)?;
+			} else {
+				write!(f, "; {}={}", name, value)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// The directives of a Cache-Control header, see [super::ValuesMap::cache_control]
+///
+/// # Example
+///
+/// ```
+/// use mi::http::CacheControl;
+/// use std::time::Duration;
+///
+/// let cache_control = CacheControl::parse("public, max-age=3600, no-transform");
+/// assert!(cache_control.has("no-transform"));
+/// assert_eq!(cache_control.max_age(), Some(Duration::from_secs(3600)));
+/// assert!(!cache_control.no_store());
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CacheControl {
+	/// Directive names in lower case with their values, if any
+	pub directives: Vec<(String, Option<String>)>,
+}
+
+impl CacheControl {
+	/// Parses a comma separated list of directives. Invalid parts are skipped
+	pub fn parse(value: &str) -> CacheControl {
+		let directives = value
+			.split(',')
+			.map(|d| d.trim())
+			.filter(|d| !d.is_empty())
+			.map(|d| match d.find('=') {
+				Some(p) => (
+					d[..p].trim().to_ascii_lowercase(),
+					Some(String::from(d[p + 1..].trim().trim_matches('"'))),
+				),
+				None => (d.to_ascii_lowercase(), None),
+			})
+			.collect();
+		CacheControl { directives }
+	}
+
+	/// Returns true if the directive is present
+	pub fn has(&self, name: &str) -> bool {
+		self.directives
+			.iter()
+			.any(|(n, _)| n.eq_ignore_ascii_case(name))
+	}
+
+	/// Returns the value of the directive
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.directives
+			.iter()
+			.find(|(n, _)| n.eq_ignore_ascii_case(name))
+			.and_then(|(_, v)| v.as_deref())
+	}
+
+	/// Returns the max-age directive
+	pub fn max_age(&self) -> Option<Duration> {
+		self.get("max-age")
+			.and_then(|v| v.parse().ok())
+			.map(Duration::from_secs)
+	}
+
+	/// Returns true if the no-cache directive is present
+	pub fn no_cache(&self) -> bool {
+		self.has("no-cache")
+	}
+
+	/// Returns true if the no-store directive is present
+	pub fn no_store(&self) -> bool {
+		self.has("no-store")
+	}
+}
+
+impl fmt::Display for CacheControl {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (i, (name, value)) in self.directives.iter().enumerate() {
+			if i > 0 {
+				write!(f, ", ")?;
+			}
+			match value {
+				Some(v) => write!(f, "{}={}", name, v)?,
+				None => write!(f, "{}", name)?,
+			}
+		}
+		Ok(())
+	}
+}
+
+/// The credentials of an Authorization header, see [super::ValuesMap::authorization]
+///
+/// # Example
+///
+/// ```
+/// use mi::http::Authorization;
+///
+/// match Authorization::parse("Basic YWxpY2U6dGVzdA==") {
+///     Some(Authorization::Basic { user, password }) => assert_eq!((user.as_str(), password.as_str()), ("alice", "test")),
+///     _ => panic!("Expected basic credentials"),
+/// }
+/// assert_eq!(Authorization::parse("bearer abc"), Some(Authorization::Bearer(String::from("abc"))));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Authorization {
+	/// User name and password of the Basic scheme
+	Basic {
+		/// The user name
+		user: String,
+		/// The password
+		password: String,
+	},
+	/// Token of the Bearer scheme
+	Bearer(String),
+	/// Credentials of any other scheme
+	Other {
+		/// The authentication scheme as sent by the client
+		scheme: String,
+		/// Everything after the scheme
+		credentials: String,
+	},
+}
+
+impl Authorization {
+	/// Parses the value of an Authorization header. Returns None if it has no credentials or Basic credentials are not
+	/// valid Base64 encoded UTF-8 containing a colon
+	pub fn parse(value: &str) -> Option<Authorization> {
+		let value = value.trim();
+		let p = value.find(' ')?;
+		let (scheme, credentials) = (&value[..p], value[p + 1..].trim());
+
+		if scheme.eq_ignore_ascii_case("Basic") {
+			let decoded = String::from_utf8(base64_decode(credentials)?).ok()?;
+			let p = decoded.find(':')?;
+			Some(Authorization::Basic {
+				user: String::from(&decoded[..p]),
+				password: String::from(&decoded[p + 1..]),
+			})
+		} else if scheme.eq_ignore_ascii_case("Bearer") {
+			Some(Authorization::Bearer(String::from(credentials)))
+		} else {
+			Some(Authorization::Other {
+				scheme: String::from(scheme),
+				credentials: String::from(credentials),
+			})
+		}
+	}
+}
+
+/// Host name and port of a Host header, see [super::ValuesMap::host]
+///
+/// # Example
+///
+/// ```
+/// use mi::http::Host;
+///
+/// let host = Host::parse("Example.test:8080").unwrap();
+/// assert_eq!((host.name.as_str(), host.port), ("example.test", Some(8080)));
+///
+/// let host = Host::parse("[::1]").unwrap();
+/// assert_eq!((host.name.as_str(), host.port), ("[::1]", None));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Host {
+	/// The host name in lower case, IPv6 addresses in brackets
+	pub name: String,
+	/// The port, if given
+	pub port: Option<u16>,
+}
+
+impl Host {
+	/// Parses a host with optional port. Returns None if the name is empty or the port is invalid
+	pub fn parse(value: &str) -> Option<Host> {
+		let value = value.trim();
+		// IPv6 addresses contain colons themselves
+		let name_end = if value.starts_with('[') {
+			value.find(']')? + 1
+		} else {
+			value.find(':').unwrap_or(value.len())
+		};
+
+		let (name, port) = value.split_at(name_end);
+		if name.is_empty() {
+			return None;
+		}
+		let port = match port.strip_prefix(':') {
+			Some(p) => Some(p.parse().ok()?),
+			None if port.is_empty() => None,
+			None => return None,
+		};
+
+		Some(Host {
+			name: name.to_ascii_lowercase(),
+			port,
+		})
+	}
+}
+
+impl fmt::Display for Host {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.port {
+			Some(port) => write!(f, "{}:{}", self.name, port),
+			None => write!(f, "{}", self.name),
+		}
+	}
+}
@@ -0,0 +1,844 @@
+use lazy_static::lazy_static;
+use std::collections::{HashMap, VecDeque};
+
+/// Default and maximum size of the dynamic table of the decoder, as announced in the SETTINGS_HEADER_TABLE_SIZE setting
+pub const TABLE_SIZE: usize = 4096;
+
+/// Entries of the static table (RFC 7541, Appendix A). Index 1 is the first entry
+const STATIC_TABLE: [(&str, &str); 61] = [
+	(":authority", ""),
+	(":method", "GET"),
+	(":method", "POST"),
+	(":path", "/"),
+	(":path", "/index.html"),
+	(":scheme", "http"),
+	(":scheme", "https"),
+	(":status", "200"),
+	(":status", "204"),
+	(":status", "206"),
+	(":status", "304"),
+	(":status", "400"),
+	(":status", "404"),
+	(":status", "500"),
+	("accept-charset", ""),
+	("accept-encoding", "gzip, deflate"),
+	("accept-language", ""),
+	("accept-ranges", ""),
+	("accept", ""),
+	("access-control-allow-origin", ""),
+	("age", ""),
+	("allow", ""),
+	("authorization", ""),
+	("cache-control", ""),
+	("content-disposition", ""),
+	("content-encoding", ""),
+	("content-language", ""),
+	("content-length", ""),
+	("content-location", ""),
+	("content-range", ""),
+	("content-type", ""),
+	("cookie", ""),
+	("date", ""),
+	("etag", ""),
+	("expect", ""),
+	("expires", ""),
+	("from", ""),
+	("host", ""),
+	("if-match", ""),
+	("if-modified-since", ""),
+	("if-none-match", ""),
+	("if-range", ""),
+	("if-unmodified-since", ""),
+	("last-modified", ""),
+	("link", ""),
+	("location", ""),
+	("max-forwards", ""),
+	("proxy-authenticate", ""),
+	("proxy-authorization", ""),
+	("range", ""),
+	("referer", ""),
+	("refresh", ""),
+	("retry-after", ""),
+	("server", ""),
+	("set-cookie", ""),
+	("strict-transport-security", ""),
+	("transfer-encoding", ""),
+	("user-agent", ""),
+	("vary", ""),
+	("via", ""),
+	("www-authenticate", ""),
+];
+
+/// Huffman codes and their lengths in bits for all bytes and the end of string symbol (RFC 7541, Appendix B)
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+	(0x1ff8, 13),
+	(0x7fffd8, 23),
+	(0xfffffe2, 28),
+	(0xfffffe3, 28),
+	(0xfffffe4, 28),
+	(0xfffffe5, 28),
+	(0xfffffe6, 28),
+	(0xfffffe7, 28),
+	(0xfffffe8, 28),
+	(0xffffea, 24),
+	(0x3ffffffc, 30),
+	(0xfffffe9, 28),
+	(0xfffffea, 28),
+	(0x3ffffffd, 30),
+	(0xfffffeb, 28),
+	(0xfffffec, 28),
+	(0xfffffed, 28),
+	(0xfffffee, 28),
+	(0xfffffef, 28),
+	(0xffffff0, 28),
+	(0xffffff1, 28),
+	(0xffffff2, 28),
+	(0x3ffffffe, 30),
+	(0xffffff3, 28),
+	(0xffffff4, 28),
+	(0xffffff5, 28),
+	(0xffffff6, 28),
+	(0xffffff7, 28),
+	(0xffffff8, 28),
+	(0xffffff9, 28),
+	(0xffffffa, 28),
+	(0xffffffb, 28),
+	(0x14, 6),
+	(0x3f8, 10),
+	(0x3f9, 10),
+	(0xffa, 12),
+	(0x1ff9, 13),
+	(0x15, 6),
+	(0xf8, 8),
+	(0x7fa, 11),
+	(0x3fa, 10),
+	(0x3fb, 10),
+	(0xf9, 8),
+	(0x7fb, 11),
+	(0xfa, 8),
+	(0x16, 6),
+	(0x17, 6),
+	(0x18, 6),
+	(0x0, 5),
+	(0x1, 5),
+	(0x2, 5),
+	(0x19, 6),
+	(0x1a, 6),
+	(0x1b, 6),
+	(0x1c, 6),
+	(0x1d, 6),
+	(0x1e, 6),
+	(0x1f, 6),
+	(0x5c, 7),
+	(0xfb, 8),
+	(0x7ffc, 15),
+	(0x20, 6),
+	(0xffb, 12),
+	(0x3fc, 10),
+	(0x1ffa, 13),
+	(0x21, 6),
+	(0x5d, 7),
+	(0x5e, 7),
+	(0x5f, 7),
+	(0x60, 7),
+	(0x61, 7),
+	(0x62, 7),
+	(0x63, 7),
+	(0x64, 7),
+	(0x65, 7),
+	(0x66, 7),
+	(0x67, 7),
+	(0x68, 7),
+	(0x69, 7),
+	(0x6a, 7),
+	(0x6b, 7),
+	(0x6c, 7),
+	(0x6d, 7),
+	(0x6e, 7),
+	(0x6f, 7),
+	(0x70, 7),
+	(0x71, 7),
+	(0x72, 7),
+	(0xfc, 8),
+	(0x73, 7),
+	(0xfd, 8),
+	(0x1ffb, 13),
+	(0x7fff0, 19),
+	(0x1ffc, 13),
+	(0x3ffc, 14),
+	(0x22, 6),
+	(0x7ffd, 15),
+	(0x3, 5),
+	(0x23, 6),
+	(0x4, 5),
+	(0x24, 6),
+	(0x5, 5),
+	(0x25, 6),
+	(0x26, 6),
+	(0x27, 6),
+	(0x6, 5),
+	(0x74, 7),
+	(0x75, 7),
+	(0x28, 6),
+	(0x29, 6),
+	(0x2a, 6),
+	(0x7, 5),
+	(0x2b, 6),
+	(0x76, 7),
+	(0x2c, 6),
+	(0x8, 5),
+	(0x9, 5),
+	(0x2d, 6),
+	(0x77, 7),
+	(0x78, 7),
+	(0x79, 7),
+	(0x7a, 7),
+	(0x7b, 7),
+	(0x7ffe, 15),
+	(0x7fc, 11),
+	(0x3ffd, 14),
+	(0x1ffd, 13),
+	(0xffffffc, 28),
+	(0xfffe6, 20),
+	(0x3fffd2, 22),
+	(0xfffe7, 20),
+	(0xfffe8, 20),
+	(0x3fffd3, 22),
+	(0x3fffd4, 22),
+	(0x3fffd5, 22),
+	(0x7fffd9, 23),
+	(0x3fffd6, 22),
+	(0x7fffda, 23),
+	(0x7fffdb, 23),
+	(0x7fffdc, 23),
+	(0x7fffdd, 23),
+	(0x7fffde, 23),
+	(0xffffeb, 24),
+	(0x7fffdf, 23),
+	(0xffffec, 24),
+	(0xffffed, 24),
+	(0x3fffd7, 22),
+	(0x7fffe0, 23),
+	(0xffffee, 24),
+	(0x7fffe1, 23),
+	(0x7fffe2, 23),
+	(0x7fffe3, 23),
+	(0x7fffe4, 23),
+	(0x1fffdc, 21),
+	(0x3fffd8, 22),
+	(0x7fffe5, 23),
+	(0x3fffd9, 22),
+	(0x7fffe6, 23),
+	(0x7fffe7, 23),
+	(0xffffef, 24),
+	(0x3fffda, 22),
+	(0x1fffdd, 21),
+	(0xfffe9, 20),
+	(0x3fffdb, 22),
+	(0x3fffdc, 22),
+	(0x7fffe8, 23),
+	(0x7fffe9, 23),
+	(0x1fffde, 21),
+	(0x7fffea, 23),
+	(0x3fffdd, 22),
+	(0x3fffde, 22),
+	(0xfffff0, 24),
+	(0x1fffdf, 21),
+	(0x3fffdf, 22),
+	(0x7fffeb, 23),
+	(0x7fffec, 23),
+	(0x1fffe0, 21),
+	(0x1fffe1, 21),
+	(0x3fffe0, 22),
+	(0x1fffe2, 21),
+	(0x7fffed, 23),
+	(0x3fffe1, 22),
+	(0x7fffee, 23),
+	(0x7fffef, 23),
+	(0xfffea, 20),
+	(0x3fffe2, 22),
+	(0x3fffe3, 22),
+	(0x3fffe4, 22),
+	(0x7ffff0, 23),
+	(0x3fffe5, 22),
+	(0x3fffe6, 22),
+	(0x7ffff1, 23),
+	(0x3ffffe0, 26),
+	(0x3ffffe1, 26),
+	(0xfffeb, 20),
+	(0x7fff1, 19),
+	(0x3fffe7, 22),
+	(0x7ffff2, 23),
+	(0x3fffe8, 22),
+	(0x1ffffec, 25),
+	(0x3ffffe2, 26),
+	(0x3ffffe3, 26),
+	(0x3ffffe4, 26),
+	(0x7ffffde, 27),
+	(0x7ffffdf, 27),
+	(0x3ffffe5, 26),
+	(0xfffff1, 24),
+	(0x1ffffed, 25),
+	(0x7fff2, 19),
+	(0x1fffe3, 21),
+	(0x3ffffe6, 26),
+	(0x7ffffe0, 27),
+	(0x7ffffe1, 27),
+	(0x3ffffe7, 26),
+	(0x7ffffe2, 27),
+	(0xfffff2, 24),
+	(0x1fffe4, 21),
+	(0x1fffe5, 21),
+	(0x3ffffe8, 26),
+	(0x3ffffe9, 26),
+	(0xffffffd, 28),
+	(0x7ffffe3, 27),
+	(0x7ffffe4, 27),
+	(0x7ffffe5, 27),
+	(0xfffec, 20),
+	(0xfffff3, 24),
+	(0xfffed, 20),
+	(0x1fffe6, 21),
+	(0x3fffe9, 22),
+	(0x1fffe7, 21),
+	(0x1fffe8, 21),
+	(0x7ffff3, 23),
+	(0x3fffea, 22),
+	(0x3fffeb, 22),
+	(0x1ffffee, 25),
+	(0x1ffffef, 25),
+	(0xfffff4, 24),
+	(0xfffff5, 24),
+	(0x3ffffea, 26),
+	(0x7ffff4, 23),
+	(0x3ffffeb, 26),
+	(0x7ffffe6, 27),
+	(0x3ffffec, 26),
+	(0x3ffffed, 26),
+	(0x7ffffe7, 27),
+	(0x7ffffe8, 27),
+	(0x7ffffe9, 27),
+	(0x7ffffea, 27),
+	(0x7ffffeb, 27),
+	(0xffffffe, 28),
+	(0x7ffffec, 27),
+	(0x7ffffed, 27),
+	(0x7ffffee, 27),
+	(0x7ffffef, 27),
+	(0x7fffff0, 27),
+	(0x3ffffee, 26),
+	(0x3fffffff, 30),
+];
+
+/// Symbol of the Huffman code that marks the end of a string, it must not appear in encoded data
+const EOS: u16 = 256;
+
+lazy_static! {
+	/// Maps code length and code to the symbol for decoding
+	static ref HUFFMAN_SYMBOLS: HashMap<(u8, u32), u16> = HUFFMAN_CODES
+		.iter()
+		.enumerate()
+		.map(|(symbol, (code, length))| ((*length, *code), symbol as u16))
+		.collect();
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Decodes HPACK header blocks (RFC 7541). One decoder is used for all header blocks a client sends on a connection,
+/// as they share the dynamic table.
+pub struct Decoder {
+	table: VecDeque<(String, String)>,
+	/// Size of the dynamic table as defined in RFC 7541, section 4.1
+	size: usize,
+	/// Current maximum size of the dynamic table, may be lowered by the encoder
+	max_size: usize,
+	/// Maximum size the decoded header list may have
+	max_list_size: usize,
+}
+
+impl Decoder {
+	/// Creates a decoder that rejects header blocks whose decoded headers are larger than max_list_size bytes
+	pub fn new(max_list_size: usize) -> Decoder {
+		Decoder {
+			table: VecDeque::new(),
+			size: 0,
+			max_size: TABLE_SIZE,
+			max_list_size,
+		}
+	}
+
+	/// Decodes a complete header block into a list of names and values in the order they were sent
+	pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, std::io::Error> {
+		let mut headers = Vec::new();
+		let mut list_size = 0;
+		let mut pos = 0;
+
+		while pos < block.len() {
+			let first = block[pos];
+			let header = if first & 0x80 != 0 {
+				// Indexed header field
+				let index = decode_integer(block, &mut pos, 7)?;
+				self.entry(index)?
+			} else if first & 0xc0 == 0x40 {
+				// Literal header field with incremental indexing
+				let header = self.literal(block, &mut pos, 6)?;
+				self.insert(header.clone());
+				header
+			} else if first & 0xe0 == 0x20 {
+				// Dynamic table size update, only allowed before the first header
+				if !headers.is_empty() {
+					return Err(invalid_data("Table size update after header field"));
+				}
+				let size = decode_integer(block, &mut pos, 5)?;
+				if size > TABLE_SIZE {
+					return Err(invalid_data(
+						"Table size update exceeds the announced maximum",
+					));
+				}
+				self.max_size = size;
+				self.evict(0);
+				continue;
+			} else {
+				// Literal header field without indexing or never indexed
+				self.literal(block, &mut pos, 4)?
+			};
+
+			list_size += header.0.len() + header.1.len() + 32;
+			if list_size > self.max_list_size {
+				return Err(invalid_data("Header list too large"));
+			}
+			headers.push(header);
+		}
+
+		Ok(headers)
+	}
+
+	/// Returns the entry at the given index of the static and dynamic table
+	fn entry(&self, index: usize) -> Result<(String, String), std::io::Error> {
+		if index == 0 {
+			return Err(invalid_data("Invalid header table index 0"));
+		}
+		if index <= STATIC_TABLE.len() {
+			let (name, value) = STATIC_TABLE[index - 1];
+			return Ok((String::from(name), String::from(value)));
+		}
+		match self.table.get(index - STATIC_TABLE.len() - 1) {
+			Some(e) => Ok(e.clone()),
+			None => Err(invalid_data("Header table index out of range")),
+		}
+	}
+
+	/// Decodes a literal header field whose name index has the given prefix length
+	fn literal(
+		&self,
+		block: &[u8],
+		pos: &mut usize,
+		prefix: u8,
+	) -> Result<(String, String), std::io::Error> {
+		let index = decode_integer(block, pos, prefix)?;
+		let name = if index == 0 {
+			decode_string(block, pos)?
+		} else {
+			self.entry(index)?.0
+		};
+		let value = decode_string(block, pos)?;
+		Ok((name, value))
+	}
+
+	fn insert(&mut self, entry: (String, String)) {
+		let size = entry.0.len() + entry.1.len() + 32;
+		self.evict(size);
+		// An entry larger than the table empties it without being added
+		if size <= self.max_size {
+			self.size += size;
+			self.table.push_front(entry);
+		}
+	}
+
+	/// Drops the oldest entries until there is room for the given number of bytes
+	fn evict(&mut self, room: usize) {
+		while self.size + room > self.max_size {
+			match self.table.pop_back() {
+				Some((name, value)) => self.size -= name.len() + value.len() + 32,
+				None => break,
+			}
+		}
+	}
+}
+
+/// Encodes a list of header names and values into a header block. Names must be lower case. The dynamic table is not
+/// used, so the encoding is independent of previous header blocks.
+pub fn encode(headers: &[(String, String)]) -> Vec<u8> {
+	let mut block = Vec::new();
+	for (name, value) in headers {
+		if let Some(i) = STATIC_TABLE
+			.iter()
+			.position(|(n, v)| n == name && v == value && !v.is_empty())
+		{
+			encode_integer(&mut block, 0x80, 7, i + 1);
+			continue;
+		}
+
+		// Literal header field without indexing
+		match STATIC_TABLE.iter().position(|(n, _)| n == name) {
+			Some(i) => encode_integer(&mut block, 0x00, 4, i + 1),
+			None => {
+				block.push(0x00);
+				encode_string(&mut block, name);
+			}
+		}
+		encode_string(&mut block, value);
+	}
+	block
+}
+
+/// Decodes an integer whose first byte has the given number of prefix bits (RFC 7541, section 5.1)
+fn decode_integer(block: &[u8], pos: &mut usize, prefix: u8) -> Result<usize, std::io::Error> {
+	let max_prefix = (1usize << prefix) - 1;
+	let mut value = match block.get(*pos) {
+		Some(b) => *b as usize & max_prefix,
+		None => return Err(invalid_data("Header block ends within an integer")),
+	};
+	*pos += 1;
+	if value < max_prefix {
+		return Ok(value);
+	}
+
+	let mut shift = 0;
+	loop {
+		let byte = match block.get(*pos) {
+			Some(b) => *b,
+			None => return Err(invalid_data("Header block ends within an integer")),
+		};
+		*pos += 1;
+		if shift > 28 {
+			return Err(invalid_data("Integer too large"));
+		}
+		value += ((byte & 0x7f) as usize) << shift;
+		shift += 7;
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+	}
+}
+
+fn encode_integer(block: &mut Vec<u8>, flags: u8, prefix: u8, value: usize) {
+	let max_prefix = (1usize << prefix) - 1;
+	if value < max_prefix {
+		block.push(flags | value as u8);
+		return;
+	}
+
+	block.push(flags | max_prefix as u8);
+	let mut rest = value - max_prefix;
+	while rest >= 0x80 {
+		block.push((rest & 0x7f) as u8 | 0x80);
+		rest >>= 7;
+	}
+	block.push(rest as u8);
+}
+
+/// Decodes a string literal, which may be Huffman encoded (RFC 7541, section 5.2)
+fn decode_string(block: &[u8], pos: &mut usize) -> Result<String, std::io::Error> {
+	let huffman = match block.get(*pos) {
+		Some(b) => b & 0x80 != 0,
+		None => return Err(invalid_data("Header block ends before string")),
+	};
+	let length = decode_integer(block, pos, 7)?;
+	if block.len() - *pos < length {
+		return Err(invalid_data("Header block ends within string"));
+	}
+	let data = &block[*pos..*pos + length];
+	*pos += length;
+
+	let bytes = if huffman {
+		huffman_decode(data)?
+	} else {
+		Vec::from(data)
+	};
+	match String::from_utf8(bytes) {
+		Ok(s) => Ok(s),
+		Err(_) => Err(invalid_data("Header field is not valid UTF-8")),
+	}
+}
+
+/// Writes a string literal, Huffman encoded if that is shorter
+fn encode_string(block: &mut Vec<u8>, value: &str) {
+	let bits: usize = value
+		.bytes()
+		.map(|b| HUFFMAN_CODES[b as usize].1 as usize)
+		.sum();
+	let huffman_length = bits.div_ceil(8);
+	if huffman_length >= value.len() {
+		encode_integer(block, 0x00, 7, value.len());
+		block.extend_from_slice(value.as_bytes());
+		return;
+	}
+
+	encode_integer(block, 0x80, 7, huffman_length);
+	let mut buffer: u64 = 0;
+	let mut buffered = 0;
+	for b in value.bytes() {
+		let (code, length) = HUFFMAN_CODES[b as usize];
+		buffer = (buffer << length) | code as u64;
+		buffered += length as u32;
+		while buffered >= 8 {
+			buffered -= 8;
+			block.push((buffer >> buffered) as u8);
+		}
+	}
+	if buffered > 0 {
+		// Pad with the most significant bits of the end of string code, which are all ones
+		block.push(((buffer << (8 - buffered)) as u8) | (0xff >> buffered));
+	}
+}
+
+fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+	let mut decoded = Vec::with_capacity(data.len() * 8 / 5);
+	let mut code: u32 = 0;
+	let mut length: u8 = 0;
+
+	for byte in data {
+		for bit in (0..8).rev() {
+			code = (code << 1) | ((*byte >> bit) & 1) as u32;
+			length += 1;
+			if let Some(symbol) = HUFFMAN_SYMBOLS.get(&(length, code)) {
+				if *symbol == EOS {
+					return Err(invalid_data(
+						"End of string symbol in Huffman encoded string",
+					));
+				}
+				decoded.push(*symbol as u8);
+				code = 0;
+				length = 0;
+			} else if length >= 30 {
+				return Err(invalid_data("Invalid Huffman code"));
+			}
+		}
+	}
+
+	// The remaining bits must be a prefix of the end of string code, so all ones and shorter than a byte
+	if length > 7 || code != (1 << length) - 1 {
+		return Err(invalid_data("Invalid Huffman padding"));
+	}
+
+	Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hex(s: &str) -> Vec<u8> {
+		(0..s.len())
+			.step_by(2)
+			.map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+			.collect()
+	}
+
+	fn list(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+		headers
+			.iter()
+			.map(|(n, v)| (String::from(*n), String::from(*v)))
+			.collect()
+	}
+
+	/// A header block in hex, the header list it encodes and the size of the dynamic table afterwards
+	type Example<'a> = (&'a str, &'a [(&'a str, &'a str)], usize);
+
+	/// Decodes the blocks one after another with the same decoder and compares the headers and the size of the
+	/// dynamic table after each block
+	fn check(decoder: &mut Decoder, blocks: &[Example]) {
+		for (block, headers, size) in blocks {
+			assert_eq!(decoder.decode(&hex(block)).unwrap(), list(headers));
+			assert_eq!(decoder.size, *size);
+		}
+	}
+
+	// RFC 7541, Appendix C.2
+	#[test]
+	fn header_field_representations() {
+		check(
+			&mut Decoder::new(TABLE_SIZE),
+			&[(
+				"400a637573746f6d2d6b65790d637573746f6d2d686561646572",
+				&[("custom-key", "custom-header")],
+				55,
+			)],
+		);
+		check(
+			&mut Decoder::new(TABLE_SIZE),
+			&[(
+				"040c2f73616d706c652f70617468",
+				&[(":path", "/sample/path")],
+				0,
+			)],
+		);
+		check(
+			&mut Decoder::new(TABLE_SIZE),
+			&[(
+				"100870617373776f726406736563726574",
+				&[("password", "secret")],
+				0,
+			)],
+		);
+		check(
+			&mut Decoder::new(TABLE_SIZE),
+			&[("82", &[(":method", "GET")], 0)],
+		);
+	}
+
+	// RFC 7541, Appendix C.3 and C.4
+	#[test]
+	fn requests() {
+		let first = [
+			(":method", "GET"),
+			(":scheme", "http"),
+			(":path", "/"),
+			(":authority", "www.example.com"),
+		];
+		let second = [
+			(":method", "GET"),
+			(":scheme", "http"),
+			(":path", "/"),
+			(":authority", "www.example.com"),
+			("cache-control", "no-cache"),
+		];
+		let third = [
+			(":method", "GET"),
+			(":scheme", "https"),
+			(":path", "/index.html"),
+			(":authority", "www.example.com"),
+			("custom-key", "custom-value"),
+		];
+
+		check(
+			&mut Decoder::new(TABLE_SIZE),
+			&[
+				("828684410f7777772e6578616d706c652e636f6d", &first, 57),
+				("828684be58086e6f2d6361636865", &second, 110),
+				(
+					"828785bf400a637573746f6d2d6b65790c637573746f6d2d76616c7565",
+					&third,
+					164,
+				),
+			],
+		);
+		check(
+			&mut Decoder::new(TABLE_SIZE),
+			&[
+				("828684418cf1e3c2e5f23a6ba0ab90f4ff", &first, 57),
+				("828684be5886a8eb10649cbf", &second, 110),
+				(
+					"828785bf408825a849e95ba97d7f8925a849e95bb8e8b4bf",
+					&third,
+					164,
+				),
+			],
+		);
+	}
+
+	// RFC 7541, Appendix C.5 and C.6, which use a dynamic table of 256 bytes
+	#[test]
+	fn responses_with_eviction() {
+		let first = [
+			(":status", "302"),
+			("cache-control", "private"),
+			("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+			("location", "https://www.example.com"),
+		];
+		let second = [
+			(":status", "307"),
+			("cache-control", "private"),
+			("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+			("location", "https://www.example.com"),
+		];
+		let third = [
+			(":status", "200"),
+			("cache-control", "private"),
+			("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+			("location", "https://www.example.com"),
+			("content-encoding", "gzip"),
+			(
+				"set-cookie",
+				"foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1",
+			),
+		];
+		// The examples assume the table size was set to 256 before, here it is set by a table size update
+		let resize = "3fe101";
+
+		check(
+			&mut Decoder::new(TABLE_SIZE),
+			&[
+				(
+					&format!(
+						"{}{}",
+						resize,
+						"4803333032580770726976617465611d4d6f6e2c203231204f637420323031332032303a31333a323120474d54\
+						6e1768747470733a2f2f7777772e6578616d706c652e636f6d"
+					),
+					&first,
+					222,
+				),
+				("4803333037c1c0bf", &second, 222),
+				(
+					"88c1611d4d6f6e2c203231204f637420323031332032303a31333a323220474d54c05a04677a69707738666f6f3d4153\
+					444a4b48514b425a584f5157454f50495541585157454f49553b206d61782d6167653d333630303b2076657273696f6e3d31",
+					&third,
+					215,
+				),
+			],
+		);
+		check(
+			&mut Decoder::new(TABLE_SIZE),
+			&[
+				(
+					&format!(
+						"{}{}",
+						resize,
+						"488264025885aec3771a4b6196d07abe941054d444a8200595040b8166e082a62d1bff6e919d29ad171863c78f0b\
+						97c8e9ae82ae43d3"
+					),
+					&first,
+					222,
+				),
+				("4883640effc1c0bf", &second, 222),
+				(
+					"88c16196d07abe941054d444a8200595040b8166e084a62d1bffc05a839bd9ab77ad94e7821dd7f2e6c7b335dfdfcd5b\
+					3960d5af27087f3672c1ab270fb5291f9587316065c003ed4ee5b1063d5007",
+					&third,
+					215,
+				),
+			],
+		);
+	}
+
+	#[test]
+	fn encoded_headers_are_decoded_again() {
+		let headers = list(&[
+			(":status", "200"),
+			("content-type", "text/html; charset=utf-8"),
+			("content-length", "1234"),
+			("x-custom", "Ünïcödé value"),
+			("cache-control", ""),
+			("x-long", &"a".repeat(300)),
+		]);
+		let block = encode(&headers);
+		assert_eq!(Decoder::new(TABLE_SIZE).decode(&block).unwrap(), headers);
+	}
+
+	#[test]
+	fn invalid_blocks_are_rejected() {
+		let mut decoder = Decoder::new(TABLE_SIZE);
+		// Index 0, index beyond the tables, a string longer than the block and a table size above the maximum
+		for block in ["80", "ff00", "0003616263", "3fe21f"] {
+			assert!(decoder.decode(&hex(block)).is_err(), "{}", block);
+		}
+		// A Huffman string padded with a zero bit
+		assert!(decoder.decode(&hex("00018a0161")).is_err());
+		// The header list is larger than allowed
+		assert!(Decoder::new(40).decode(&hex("82868441")).is_err());
+	}
+}
@@ -0,0 +1,1231 @@
+use super::body::{HOP_BY_HOP_HEADERS, MAX_HEAD_SIZE};
+use super::hpack;
+use super::metrics::Gauge;
+use super::server::Dispatcher;
+use super::util::{log, unix_time};
+use super::{Error, Request, Response, ValuesMap};
+use crate::bin::base64_decode;
+use std::collections::{HashMap, HashSet};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The connection preface every client sends first (RFC 7540, section 3.5)
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// Frame types
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// Frame flags
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+// Settings
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+// Error codes
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const INTERNAL_ERROR: u32 = 0x2;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+
+/// Default maximum frame size, which is also the largest frame accepted from clients
+const FRAME_SIZE: usize = 16384;
+
+/// Flow control window of new streams and connections unless changed by a setting
+const DEFAULT_WINDOW: i64 = 65535;
+
+/// Largest possible flow control window
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+/// Flow control window announced to clients for each stream and for the connection
+const STREAM_WINDOW: u32 = 1024 * 1024;
+
+/// Maximum number of streams a client may have open at the same time
+const MAX_STREAMS: usize = 100;
+
+/// Request bodies are received completely before the handler is called. Larger bodies are answered with 413
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// Maximum number of request body bytes buffered for all streams of a connection together. Streams that would exceed
+/// it are refused
+const MAX_BUFFERED: usize = MAX_BODY;
+
+/// Returns true if the request is the start of the connection preface of a client that knows the server speaks HTTP/2
+pub(crate) fn is_prior_knowledge(req: &Request) -> bool {
+	req.method == "PRI" && req.uri == "*" && req.http_version == "HTTP/2.0"
+}
+
+/// Returns true if the request asks to switch to HTTP/2 over cleartext. Requests with a body are served as HTTP/1.1
+pub(crate) fn is_upgrade(req: &Request) -> bool {
+	let has_token = |header: &str, token: &str| {
+		req.headers
+			.get(header)
+			.unwrap_or("")
+			.split(',')
+			.any(|t| t.trim().eq_ignore_ascii_case(token))
+	};
+
+	req.http_version == "HTTP/1.1"
+		&& has_token("Upgrade", "h2c")
+		&& has_token("Connection", "HTTP2-Settings")
+		&& req.headers.get("HTTP2-Settings").is_some()
+		&& req.headers.get("Transfer-Encoding").is_none()
+		&& req.headers.get("Content-Length").unwrap_or("0").trim() == "0"
+}
+
+/// An error that ends the connection with a GOAWAY frame
+struct ConnectionError {
+	code: u32,
+	message: String,
+}
+
+impl ConnectionError {
+	fn new(code: u32, message: &str) -> ConnectionError {
+		ConnectionError {
+			code,
+			message: String::from(message),
+		}
+	}
+}
+
+impl From<std::io::Error> for ConnectionError {
+	fn from(e: std::io::Error) -> ConnectionError {
+		ConnectionError::new(INTERNAL_ERROR, &e.to_string())
+	}
+}
+
+struct Frame {
+	kind: u8,
+	flags: u8,
+	stream: u32,
+	payload: Vec<u8>,
+}
+
+impl Frame {
+	/// Returns the payload without padding, for frame types that may be padded
+	fn unpadded(&self) -> Result<&[u8], ConnectionError> {
+		if self.flags & PADDED == 0 {
+			return Ok(&self.payload);
+		}
+		let padding = match self.payload.first() {
+			Some(p) => *p as usize,
+			None => return Err(ConnectionError::new(FRAME_SIZE_ERROR, "Missing pad length")),
+		};
+		if padding >= self.payload.len() {
+			return Err(ConnectionError::new(
+				PROTOCOL_ERROR,
+				"Padding exceeds frame",
+			));
+		}
+		Ok(&self.payload[1..self.payload.len() - padding])
+	}
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> Result<Frame, std::io::Error> {
+	let mut head = [0; 9];
+	reader.read_exact(&mut head)?;
+
+	let length = (head[0] as usize) << 16 | (head[1] as usize) << 8 | head[2] as usize;
+	if length > FRAME_SIZE {
+		return Err(std::io::Error::new(
+			std::io::ErrorKind::InvalidData,
+			"Frame larger than the maximum frame size",
+		));
+	}
+	let mut payload = vec![0; length];
+	reader.read_exact(&mut payload)?;
+
+	Ok(Frame {
+		kind: head[3],
+		flags: head[4],
+		stream: u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff,
+		payload,
+	})
+}
+
+fn frame_bytes(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+	let length = payload.len();
+	let mut frame = Vec::with_capacity(9 + length);
+	frame.extend_from_slice(&[(length >> 16) as u8, (length >> 8) as u8, length as u8]);
+	frame.push(kind);
+	frame.push(flags);
+	frame.extend_from_slice(&stream.to_be_bytes());
+	frame.extend_from_slice(payload);
+	frame
+}
+
+/// The sending side of a connection, shared by the responses of all streams
+struct Output {
+	stream: TcpStream,
+	/// Flow control window of the connection
+	window: i64,
+	/// Flow control windows of the streams whose response is not finished
+	windows: HashMap<u32, i64>,
+	/// Window of new streams, set by the client
+	initial_window: i64,
+	/// Largest frame the client accepts
+	max_frame_size: usize,
+	closed: bool,
+}
+
+impl Output {
+	fn write_frame(
+		&mut self,
+		kind: u8,
+		flags: u8,
+		stream: u32,
+		payload: &[u8],
+	) -> Result<(), std::io::Error> {
+		self.stream
+			.write_all(&frame_bytes(kind, flags, stream, payload))
+	}
+}
+
+/// State of a connection shared by the thread reading from it and the responses writing to it
+struct Connection {
+	output: Mutex<Output>,
+	/// Notified when flow control windows grow or streams are closed
+	window_changed: Condvar,
+}
+
+impl Connection {
+	fn output(&self) -> MutexGuard<'_, Output> {
+		match self.output.lock() {
+			Ok(o) => o,
+			Err(e) => e.into_inner(),
+		}
+	}
+
+	fn write_frame(&self, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+		let _ = self.output().write_frame(kind, flags, stream, payload);
+	}
+
+	fn reset(&self, stream: u32, code: u32) {
+		let mut output = self.output();
+		let _ = output.write_frame(RST_STREAM, 0, stream, &code.to_be_bytes());
+		output.windows.remove(&stream);
+		self.window_changed.notify_all();
+	}
+
+	fn close(&self) {
+		let mut output = self.output();
+		output.closed = true;
+		let _ = output.stream.shutdown(std::net::Shutdown::Both);
+		self.window_changed.notify_all();
+	}
+}
+
+/// Writes the response of a single stream, see [Response]
+pub(crate) struct StreamWriter {
+	connection: Arc<Connection>,
+	id: u32,
+	/// How long to wait for the client to open the flow control window
+	pub(crate) write_timeout: Option<Duration>,
+	/// Whether the client is still sending a request body that is not going to be read, it is told to stop once the
+	/// response is complete
+	discard_request: bool,
+}
+
+impl StreamWriter {
+	/// Sends the status and headers. Returns the number of bytes sent
+	pub(crate) fn send_headers(
+		&mut self,
+		status: u16,
+		headers: &ValuesMap,
+	) -> Result<u64, std::io::Error> {
+		let mut fields = vec![(String::from(":status"), status.to_string())];
+		for (name, values) in headers.all() {
+			let name = name.to_ascii_lowercase();
+			if HOP_BY_HOP_HEADERS
+				.iter()
+				.any(|h| h.eq_ignore_ascii_case(&name))
+			{
+				continue;
+			}
+			for value in values {
+				fields.push((name.clone(), value.clone()));
+			}
+		}
+		let block = hpack::encode(&fields);
+
+		let mut output = self.connection.output();
+		self.check_open(&output)?;
+
+		// The header block must not be interrupted by other frames
+		let mut chunks = block.chunks(output.max_frame_size).peekable();
+		let mut kind = HEADERS;
+		let mut sent = 0;
+		while let Some(chunk) = chunks.next() {
+			let flags = if chunks.peek().is_none() {
+				END_HEADERS
+			} else {
+				0
+			};
+			output.write_frame(kind, flags, self.id, chunk)?;
+			sent += 9 + chunk.len() as u64;
+			kind = CONTINUATION;
+		}
+		if block.is_empty() {
+			output.write_frame(HEADERS, END_HEADERS, self.id, &[])?;
+			sent += 9;
+		}
+
+		Ok(sent)
+	}
+
+	/// Sends body data, waiting for the client to open the flow control window as needed
+	pub(crate) fn send_data(
+		&mut self,
+		data: &[u8],
+		end_stream: bool,
+	) -> Result<(), std::io::Error> {
+		let mut output = self.connection.output();
+		let timeout = self.write_timeout;
+		let mut rest = data;
+
+		loop {
+			self.check_open(&output)?;
+			let stream_window = output.windows.get(&self.id).copied().unwrap_or(0);
+			let available = std::cmp::min(output.window, stream_window);
+
+			if rest.is_empty() || available > 0 {
+				let length = std::cmp::min(
+					rest.len(),
+					std::cmp::min(available.max(0) as usize, output.max_frame_size),
+				);
+				let last = length == rest.len();
+				let flags = if last && end_stream { END_STREAM } else { 0 };
+				output.write_frame(DATA, flags, self.id, &rest[..length])?;
+
+				output.window -= length as i64;
+				if let Some(w) = output.windows.get_mut(&self.id) {
+					*w -= length as i64;
+				}
+				rest = &rest[length..];
+				if last {
+					return Ok(());
+				}
+				continue;
+			}
+
+			output = match timeout {
+				Some(t) => {
+					let (o, result) = match self.connection.window_changed.wait_timeout(output, t) {
+						Ok(r) => r,
+						Err(e) => e.into_inner(),
+					};
+					if result.timed_out() {
+						return Err(std::io::Error::new(
+							std::io::ErrorKind::TimedOut,
+							"Client did not open the flow control window",
+						));
+					}
+					o
+				}
+				None => match self.connection.window_changed.wait(output) {
+					Ok(o) => o,
+					Err(e) => e.into_inner(),
+				},
+			};
+		}
+	}
+
+	/// Ends the stream after the response was sent completely
+	pub(crate) fn finish(&mut self) -> Result<(), std::io::Error> {
+		self.send_data(&[], true)?;
+		if self.discard_request {
+			self.connection.reset(self.id, NO_ERROR);
+		} else {
+			self.connection.output().windows.remove(&self.id);
+		}
+		Ok(())
+	}
+
+	/// Cancels the stream, e.g. because the response could not be completed
+	pub(crate) fn reset(&self) {
+		self.connection.reset(self.id, INTERNAL_ERROR);
+	}
+
+	/// Refuses the stream without a response, the client may send the request again
+	pub(crate) fn refuse(&self) {
+		self.connection.reset(self.id, REFUSED_STREAM);
+	}
+
+	fn check_open(&self, output: &Output) -> Result<(), std::io::Error> {
+		if output.closed {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::NotConnected,
+				"Connection closed",
+			));
+		}
+		if !output.windows.contains_key(&self.id) {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::ConnectionReset,
+				"Stream was reset by the client",
+			));
+		}
+		Ok(())
+	}
+}
+
+/// A stream whose request is still being received
+struct IncomingStream {
+	headers: Vec<(String, String)>,
+	header_size: usize,
+	body: Vec<u8>,
+	/// How much data the client may still send on the stream
+	window: i64,
+	received: Instant,
+	time: u64,
+}
+
+/// The receiving side of a connection, run by a thread of its own
+struct Session {
+	connection: Arc<Connection>,
+	input: BufReader<std::io::Chain<std::io::Cursor<Vec<u8>>, TcpStream>>,
+	decoder: hpack::Decoder,
+	streams: HashMap<u32, IncomingStream>,
+	/// Size of the bodies of all streams in streams
+	buffered: usize,
+	/// Streams whose request body is dropped while their response is sent
+	discarded: HashSet<u32>,
+	/// How much data the client may still send on the connection
+	window: i64,
+	last_stream: u32,
+	peer_addr: Option<std::net::SocketAddr>,
+	dispatcher: Dispatcher,
+}
+
+/// Takes over the connection of the given request, which is either the start of the connection preface or a request
+/// to upgrade to HTTP/2, and serves it as HTTP/2 connection on a thread of its own, which http2_connection counts.
+/// Requests are handed to the dispatcher once they were received completely.
+pub(crate) fn serve(
+	req: Request,
+	received: Instant,
+	time: u64,
+	dispatcher: Dispatcher,
+	connection: Gauge,
+	http2_connection: Gauge,
+) -> Result<(), std::io::Error> {
+	let stream = req.clone_stream()?;
+	let reader = req.clone_stream()?;
+	// Frames are written whole, delaying the last small frame of a flow control window only stalls the stream
+	stream.set_nodelay(true)?;
+	let upgrade = !is_prior_knowledge(&req);
+
+	let pending = Vec::from(req.buffered_body());
+	let (preface, settings) = if upgrade {
+		let settings = req
+			.headers
+			.get("HTTP2-Settings")
+			.unwrap_or("")
+			.trim()
+			.replace('-', "+")
+			.replace('_', "/");
+		let settings = match base64_decode(&settings) {
+			Some(s) => s,
+			None => {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::InvalidData,
+					"Invalid HTTP2-Settings header",
+				))
+			}
+		};
+		(PREFACE, settings)
+	} else {
+		// The start of the preface up to the first empty line was parsed as request already
+		(&PREFACE[18..], Vec::new())
+	};
+
+	let peer_addr = req.peer_addr();
+	let connection_state = Arc::new(Connection {
+		output: Mutex::new(Output {
+			stream,
+			window: DEFAULT_WINDOW,
+			windows: HashMap::new(),
+			initial_window: DEFAULT_WINDOW,
+			max_frame_size: FRAME_SIZE,
+			closed: false,
+		}),
+		window_changed: Condvar::new(),
+	});
+
+	let mut session = Session {
+		connection: connection_state,
+		input: BufReader::new(std::io::Cursor::new(pending).chain(reader)),
+		decoder: hpack::Decoder::new(MAX_HEAD_SIZE),
+		streams: HashMap::new(),
+		buffered: 0,
+		discarded: HashSet::new(),
+		window: STREAM_WINDOW as i64,
+		last_stream: 0,
+		peer_addr,
+		dispatcher,
+	};
+
+	std::thread::spawn(move || {
+		let (_connection, _http2_connection) = (connection, http2_connection);
+		let result = session.start(upgrade, preface, &settings).and_then(|_| {
+			if upgrade {
+				// The upgrade request becomes stream 1, which is half-closed as its request is complete
+				session.open_stream(1);
+				session.last_stream = 1;
+				session.dispatch_request(req, 1, received, time);
+			}
+			session.run()
+		});
+
+		match result {
+			Ok(()) => {}
+			Err(e) => {
+				if e.code != NO_ERROR {
+					log(
+						&session.dispatcher.log_errors,
+						format!("HTTP/2 connection error: {}", e.message),
+					);
+				}
+				session.connection.write_frame(
+					GOAWAY,
+					0,
+					0,
+					&[
+						&session.last_stream.to_be_bytes()[..],
+						&e.code.to_be_bytes()[..],
+					]
+					.concat(),
+				);
+				session.connection.close();
+			}
+		}
+	});
+
+	Ok(())
+}
+
+/// Opens a receive window to its full size again once half of it is used up, which needs fewer frames than opening it
+/// for every DATA frame. Returns the increment to send to the client
+fn refill(window: &mut i64) -> Option<u32> {
+	if *window > STREAM_WINDOW as i64 / 2 {
+		return None;
+	}
+	let increment = STREAM_WINDOW as i64 - *window;
+	*window = STREAM_WINDOW as i64;
+	Some(increment as u32)
+}
+
+/// Refuses the connection of a client with prior knowledge without serving any stream, because the server is at its
+/// limits. The frames are written with the given timeout
+pub(crate) fn refuse(req: &Request, timeout: Duration) {
+	let mut stream = match req.clone_stream() {
+		Ok(s) => s,
+		Err(_) => return,
+	};
+	let _ = stream.set_write_timeout(Some(timeout));
+
+	let goaway = [&0u32.to_be_bytes()[..], &REFUSED_STREAM.to_be_bytes()[..]].concat();
+	let frames = [
+		frame_bytes(SETTINGS, 0, 0, &[]),
+		frame_bytes(GOAWAY, 0, 0, &goaway),
+	]
+	.concat();
+	let _ = stream.write_all(&frames);
+	let _ = stream.shutdown(std::net::Shutdown::Both);
+}
+
+impl Session {
+	/// Accepts the upgrade if the connection was upgraded, reads the rest of the client's connection preface and
+	/// exchanges settings
+	fn start(
+		&mut self,
+		upgrade: bool,
+		preface: &[u8],
+		settings: &[u8],
+	) -> Result<(), ConnectionError> {
+		if upgrade {
+			self.connection.output().stream.write_all(
+				b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n",
+			)?;
+		}
+
+		let mut announced = Vec::new();
+		for (id, value) in [
+			(SETTINGS_MAX_CONCURRENT_STREAMS, MAX_STREAMS as u32),
+			(SETTINGS_INITIAL_WINDOW_SIZE, STREAM_WINDOW),
+			(SETTINGS_MAX_HEADER_LIST_SIZE, MAX_HEAD_SIZE as u32),
+			(SETTINGS_ENABLE_PUSH, 0),
+		] {
+			announced.extend_from_slice(&id.to_be_bytes());
+			announced.extend_from_slice(&value.to_be_bytes());
+		}
+		self.connection.write_frame(SETTINGS, 0, 0, &announced);
+		// The window of the connection can only be changed by WINDOW_UPDATE frames
+		let increment = STREAM_WINDOW - DEFAULT_WINDOW as u32;
+		self.connection
+			.write_frame(WINDOW_UPDATE, 0, 0, &increment.to_be_bytes());
+
+		let mut received = vec![0; preface.len()];
+		self.input.read_exact(&mut received)?;
+		if received != preface {
+			return Err(ConnectionError::new(
+				PROTOCOL_ERROR,
+				"Invalid connection preface",
+			));
+		}
+
+		// Settings sent in the HTTP2-Settings header apply as if sent in a SETTINGS frame, without acknowledgement
+		self.apply_settings(settings)
+	}
+
+	fn run(&mut self) -> Result<(), ConnectionError> {
+		loop {
+			let frame = match read_frame(&mut self.input) {
+				Ok(f) => f,
+				Err(e) => match e.kind() {
+					std::io::ErrorKind::UnexpectedEof => return Ok(()),
+					// Idle connections are closed after the read timeout, unless responses are still being sent
+					std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+						if self.connection.output().windows.is_empty() {
+							return Err(ConnectionError::new(NO_ERROR, "Idle timeout"));
+						}
+						continue;
+					}
+					std::io::ErrorKind::InvalidData => {
+						return Err(ConnectionError::new(FRAME_SIZE_ERROR, &e.to_string()))
+					}
+					_ => return Ok(()),
+				},
+			};
+
+			match frame.kind {
+				DATA => self.on_data(frame)?,
+				HEADERS => self.on_headers(frame)?,
+				PRIORITY => {}
+				RST_STREAM => {
+					if frame.stream == 0 || frame.payload.len() != 4 {
+						return Err(ConnectionError::new(
+							PROTOCOL_ERROR,
+							"Invalid RST_STREAM frame",
+						));
+					}
+					self.remove_stream(frame.stream);
+					let mut output = self.connection.output();
+					output.windows.remove(&frame.stream);
+					self.connection.window_changed.notify_all();
+				}
+				SETTINGS => {
+					if frame.stream != 0 {
+						return Err(ConnectionError::new(
+							PROTOCOL_ERROR,
+							"SETTINGS frame on a stream",
+						));
+					}
+					if frame.flags & ACK == 0 {
+						self.apply_settings(&frame.payload)?;
+						self.connection.write_frame(SETTINGS, ACK, 0, &[]);
+					}
+				}
+				PUSH_PROMISE => {
+					return Err(ConnectionError::new(
+						PROTOCOL_ERROR,
+						"Clients must not push",
+					))
+				}
+				PING => {
+					if frame.stream != 0 || frame.payload.len() != 8 {
+						return Err(ConnectionError::new(PROTOCOL_ERROR, "Invalid PING frame"));
+					}
+					if frame.flags & ACK == 0 {
+						self.connection.write_frame(PING, ACK, 0, &frame.payload);
+					}
+				}
+				// Streams that are already being handled still get their responses
+				GOAWAY => return Ok(()),
+				WINDOW_UPDATE => self.on_window_update(frame)?,
+				CONTINUATION => {
+					return Err(ConnectionError::new(
+						PROTOCOL_ERROR,
+						"Unexpected CONTINUATION frame",
+					))
+				}
+				// Frames of unknown types are ignored
+				_ => {}
+			}
+		}
+	}
+
+	fn apply_settings(&mut self, payload: &[u8]) -> Result<(), ConnectionError> {
+		if !payload.len().is_multiple_of(6) {
+			return Err(ConnectionError::new(
+				FRAME_SIZE_ERROR,
+				"Invalid SETTINGS frame",
+			));
+		}
+
+		let mut output = self.connection.output();
+		for setting in payload.chunks(6) {
+			let id = u16::from_be_bytes([setting[0], setting[1]]);
+			let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+			match id {
+				SETTINGS_INITIAL_WINDOW_SIZE => {
+					if value as i64 > MAX_WINDOW {
+						return Err(ConnectionError::new(FLOW_CONTROL_ERROR, "Window too large"));
+					}
+					let delta = value as i64 - output.initial_window;
+					output.initial_window = value as i64;
+					for window in output.windows.values_mut() {
+						*window += delta;
+					}
+				}
+				SETTINGS_MAX_FRAME_SIZE => {
+					if !(FRAME_SIZE..=16_777_215).contains(&(value as usize)) {
+						return Err(ConnectionError::new(PROTOCOL_ERROR, "Invalid frame size"));
+					}
+					output.max_frame_size = value as usize;
+				}
+				SETTINGS_ENABLE_PUSH if value > 1 => {
+					return Err(ConnectionError::new(PROTOCOL_ERROR, "Invalid push setting"));
+				}
+				// The encoder does not use the dynamic table, so its size does not matter. Streams are never pushed and
+				// the limits of the client are not reached by single responses
+				_ => {}
+			}
+		}
+		self.connection.window_changed.notify_all();
+
+		Ok(())
+	}
+
+	fn on_window_update(&mut self, frame: Frame) -> Result<(), ConnectionError> {
+		if frame.payload.len() != 4 {
+			return Err(ConnectionError::new(
+				FRAME_SIZE_ERROR,
+				"Invalid WINDOW_UPDATE frame",
+			));
+		}
+		let p = &frame.payload;
+		let increment = (u32::from_be_bytes([p[0], p[1], p[2], p[3]]) & 0x7fff_ffff) as i64;
+
+		let mut output = self.connection.output();
+		if frame.stream == 0 {
+			if increment == 0 {
+				return Err(ConnectionError::new(
+					PROTOCOL_ERROR,
+					"Window update without increment",
+				));
+			}
+			if output.window + increment > MAX_WINDOW {
+				return Err(ConnectionError::new(
+					FLOW_CONTROL_ERROR,
+					"Invalid window update",
+				));
+			}
+			output.window += increment;
+		} else if let Some(window) = output.windows.get_mut(&frame.stream) {
+			if increment == 0 || *window + increment > MAX_WINDOW {
+				let code = if increment == 0 {
+					PROTOCOL_ERROR
+				} else {
+					FLOW_CONTROL_ERROR
+				};
+				drop(output);
+				self.connection.reset(frame.stream, code);
+				return Ok(());
+			}
+			*window += increment;
+		}
+		self.connection.window_changed.notify_all();
+
+		Ok(())
+	}
+
+	fn on_headers(&mut self, frame: Frame) -> Result<(), ConnectionError> {
+		let id = frame.stream;
+		let end_stream = frame.flags & END_STREAM != 0;
+		let mut block = Vec::from(frame.unpadded()?);
+		if frame.flags & PRIORITY_FLAG != 0 {
+			if block.len() < 5 {
+				return Err(ConnectionError::new(
+					FRAME_SIZE_ERROR,
+					"Invalid HEADERS frame",
+				));
+			}
+			block.drain(..5);
+		}
+
+		// The header block continues in CONTINUATION frames of the same stream until END_HEADERS
+		let mut flags = frame.flags;
+		while flags & END_HEADERS == 0 {
+			let next = read_frame(&mut self.input)?;
+			if next.kind != CONTINUATION || next.stream != id {
+				return Err(ConnectionError::new(
+					PROTOCOL_ERROR,
+					"Expected CONTINUATION frame",
+				));
+			}
+			if block.len() + next.payload.len() > MAX_HEAD_SIZE {
+				return Err(ConnectionError::new(
+					PROTOCOL_ERROR,
+					"Header block too large",
+				));
+			}
+			block.extend_from_slice(&next.payload);
+			flags = next.flags;
+		}
+
+		let headers = match self.decoder.decode(&block) {
+			Ok(h) => h,
+			Err(e) => return Err(ConnectionError::new(COMPRESSION_ERROR, &e.to_string())),
+		};
+
+		if self.streams.contains_key(&id) {
+			// Trailers, which are not passed on
+			if !end_stream {
+				return Err(ConnectionError::new(
+					PROTOCOL_ERROR,
+					"Trailers without END_STREAM",
+				));
+			}
+			self.dispatch(id);
+			return Ok(());
+		}
+
+		if id == 0 || id.is_multiple_of(2) || id <= self.last_stream {
+			return Err(ConnectionError::new(
+				PROTOCOL_ERROR,
+				"Invalid stream identifier",
+			));
+		}
+		self.last_stream = id;
+
+		if self.connection.output().windows.len() >= MAX_STREAMS {
+			self.connection.reset(id, REFUSED_STREAM);
+			return Ok(());
+		}
+
+		self.open_stream(id);
+		self.streams.insert(
+			id,
+			IncomingStream {
+				headers,
+				header_size: block.len(),
+				body: Vec::new(),
+				window: STREAM_WINDOW as i64,
+				received: Instant::now(),
+				time: unix_time(),
+			},
+		);
+		if end_stream {
+			self.dispatch(id);
+		}
+
+		Ok(())
+	}
+
+	fn on_data(&mut self, frame: Frame) -> Result<(), ConnectionError> {
+		let id = frame.stream;
+		if id == 0 {
+			return Err(ConnectionError::new(
+				PROTOCOL_ERROR,
+				"DATA frame without stream",
+			));
+		}
+		let data = frame.unpadded()?;
+
+		// The whole frame counts against the windows, padding included
+		let length = frame.payload.len() as i64;
+		if length > self.window {
+			return Err(ConnectionError::new(
+				FLOW_CONTROL_ERROR,
+				"Flow control window of the connection exceeded",
+			));
+		}
+		self.window -= length;
+		// Buffered data is limited per connection, so the window is opened again as soon as the data is received
+		if let Some(increment) = refill(&mut self.window) {
+			self.connection
+				.write_frame(WINDOW_UPDATE, 0, 0, &increment.to_be_bytes());
+		}
+
+		let stream = match self.streams.get_mut(&id) {
+			Some(s) => s,
+			None => {
+				if id > self.last_stream {
+					return Err(ConnectionError::new(
+						PROTOCOL_ERROR,
+						"DATA frame on idle stream",
+					));
+				}
+				// Streams that were reset or refused may still receive frames that were in flight
+				if !self.connection.output().windows.contains_key(&id)
+					|| self.discarded.contains(&id)
+				{
+					return Ok(());
+				}
+				self.connection.reset(id, STREAM_CLOSED);
+				return Ok(());
+			}
+		};
+
+		if length > stream.window {
+			self.remove_stream(id);
+			self.connection.reset(id, FLOW_CONTROL_ERROR);
+			return Ok(());
+		}
+		stream.window -= length;
+
+		if stream.body.len() + data.len() > MAX_BODY {
+			// The response may have to wait for the client to open its window, which this thread has to receive
+			let stream = self.remove_stream(id).unwrap();
+			let req = self.request(&stream, Vec::new());
+			let res = self.response(&req, id, true);
+			{
+				let output = self.connection.output();
+				self.discarded.retain(|s| output.windows.contains_key(s));
+			}
+			self.discarded.insert(id);
+			self.dispatcher.dispatch_error(
+				req,
+				res,
+				stream.received,
+				stream.time,
+				Error::new(413, "Request body too large"),
+			);
+			return Ok(());
+		}
+		if self.buffered + data.len() > MAX_BUFFERED {
+			// The bodies of other streams take up the buffer, the client may retry the request once they are handled
+			self.remove_stream(id);
+			self.connection.reset(id, REFUSED_STREAM);
+			return Ok(());
+		}
+		stream.body.extend_from_slice(data);
+		self.buffered += data.len();
+
+		if frame.flags & END_STREAM != 0 {
+			self.dispatch(id);
+		} else if let Some(increment) = refill(&mut stream.window) {
+			self.connection
+				.write_frame(WINDOW_UPDATE, 0, id, &increment.to_be_bytes());
+		}
+
+		Ok(())
+	}
+
+	/// Stops receiving the request of the stream and returns it
+	fn remove_stream(&mut self, id: u32) -> Option<IncomingStream> {
+		let stream = self.streams.remove(&id)?;
+		self.buffered -= stream.body.len();
+		Some(stream)
+	}
+
+	fn open_stream(&mut self, id: u32) {
+		let mut output = self.connection.output();
+		let window = output.initial_window;
+		output.windows.insert(id, window);
+	}
+
+	/// Returns a response to the request that is sent on the given stream. With discard_request set the stream is reset
+	/// after the response, as the rest of the request is not read
+	fn response(&self, req: &Request, id: u32, discard_request: bool) -> Response {
+		let write_timeout = self
+			.connection
+			.output()
+			.stream
+			.write_timeout()
+			.unwrap_or(None);
+		let mut res = Response::new_for_http2(
+			StreamWriter {
+				connection: self.connection.clone(),
+				id,
+				write_timeout,
+				discard_request,
+			},
+			req,
+			self.dispatcher.log_errors.clone(),
+		);
+		res.set_error_renderer(self.dispatcher.error_renderer.clone());
+		res
+	}
+
+	/// Creates a request from the header fields and body of a stream
+	fn request(&self, stream: &IncomingStream, body: Vec<u8>) -> Request {
+		let mut method = String::new();
+		let mut path = String::new();
+		let mut authority = None;
+
+		let mut headers = ValuesMap::new();
+		headers.case_handling = true;
+		let mut cookies = Vec::new();
+		for (name, value) in &stream.headers {
+			match name.as_str() {
+				":method" => method = value.clone(),
+				":path" => path = value.clone(),
+				":authority" => authority = Some(value.as_str()),
+				":scheme" => {}
+				// Cookies may be split into several fields, which are joined again for HTTP/1.1 handlers
+				"cookie" => cookies.push(value.as_str()),
+				_ => headers.add(name, value),
+			}
+		}
+		if !cookies.is_empty() {
+			headers.set("Cookie", &cookies.join("; "));
+		}
+		if let Some(authority) = authority {
+			if headers.get("Host").is_none() {
+				headers.set("Host", authority);
+			}
+		}
+
+		Request::from_parts(
+			method,
+			path,
+			"HTTP/2.0",
+			headers,
+			body,
+			stream.header_size,
+			self.peer_addr,
+		)
+	}
+
+	/// Hands the complete request of the stream to its handler
+	fn dispatch(&mut self, id: u32) {
+		let mut stream = match self.remove_stream(id) {
+			Some(s) => s,
+			None => return,
+		};
+
+		let has_pseudo_header = |name: &str| {
+			stream
+				.headers
+				.iter()
+				.any(|(n, v)| n == name && !v.is_empty())
+		};
+		if !has_pseudo_header(":method") || !has_pseudo_header(":path") {
+			self.connection.reset(id, PROTOCOL_ERROR);
+			return;
+		}
+
+		let body = std::mem::take(&mut stream.body);
+		let req = self.request(&stream, body);
+		self.dispatch_request(req, id, stream.received, stream.time);
+	}
+
+	fn dispatch_request(&mut self, req: Request, id: u32, received: Instant, time: u64) {
+		let res = self.response(&req, id, false);
+		self.dispatcher.dispatch(req, res, received, time, None);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::http::{Handler, Server};
+
+	fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+		headers
+			.iter()
+			.find(|(n, _)| n == name)
+			.map(|(_, v)| v.as_str())
+	}
+
+	/// Starts a server with a handler that echoes the request path and body and connects to it
+	fn connect(port: u16) -> TcpStream {
+		std::thread::spawn(move || {
+			let mut server = Server::new();
+			server.num_threads = 2;
+			server.handler(Arc::new(Handler::new(
+				|_| true,
+				|req, mut res| {
+					let mut body = String::new();
+					let _ = req.body_reader().read_to_string(&mut body);
+					res.w(format!("{} {}", req.uri, body))
+				},
+			)));
+			server.listen(port).unwrap();
+		});
+
+		let start = Instant::now();
+		let stream = loop {
+			match TcpStream::connect(("127.0.0.1", port)) {
+				Ok(s) => break s,
+				Err(e) if start.elapsed() > Duration::from_secs(5) => panic!("{}", e),
+				Err(_) => std::thread::sleep(Duration::from_millis(10)),
+			}
+		};
+		stream
+			.set_read_timeout(Some(Duration::from_secs(5)))
+			.unwrap();
+		stream
+	}
+
+	#[test]
+	fn prior_knowledge_exchange() {
+		let mut stream = connect(18474);
+
+		let request = [
+			(":method", "POST"),
+			(":scheme", "http"),
+			(":path", "/echo"),
+			(":authority", "localhost"),
+			("content-type", "text/plain"),
+		]
+		.iter()
+		.map(|(n, v)| (String::from(*n), String::from(*v)))
+		.collect::<Vec<_>>();
+		let mut out = Vec::from(PREFACE);
+		out.extend(frame_bytes(SETTINGS, 0, 0, &[]));
+		out.extend(frame_bytes(
+			HEADERS,
+			END_HEADERS,
+			1,
+			&hpack::encode(&request),
+		));
+		out.extend(frame_bytes(DATA, END_STREAM, 1, b"hello"));
+		stream.write_all(&out).unwrap();
+
+		let mut reader = BufReader::new(stream.try_clone().unwrap());
+		let first = read_frame(&mut reader).unwrap();
+		assert_eq!((first.kind, first.flags, first.stream), (SETTINGS, 0, 0));
+		stream
+			.write_all(&frame_bytes(SETTINGS, ACK, 0, &[]))
+			.unwrap();
+
+		let mut decoder = hpack::Decoder::new(hpack::TABLE_SIZE);
+		let (mut settings_acked, mut headers, mut body) = (false, None, Vec::new());
+		loop {
+			let frame = read_frame(&mut reader).unwrap();
+			match frame.kind {
+				SETTINGS => settings_acked |= frame.flags & ACK != 0,
+				HEADERS => {
+					assert_eq!((frame.stream, frame.flags & END_HEADERS), (1, END_HEADERS));
+					headers = Some(decoder.decode(&frame.payload).unwrap());
+				}
+				DATA => {
+					assert_eq!(frame.stream, 1);
+					body.extend_from_slice(&frame.payload);
+					if frame.flags & END_STREAM != 0 {
+						break;
+					}
+				}
+				WINDOW_UPDATE | PING => {}
+				kind => panic!("Unexpected frame type {}", kind),
+			}
+		}
+
+		assert!(settings_acked);
+		let headers = headers.expect("Response headers");
+		assert_eq!(header(&headers, ":status"), Some("200"));
+		assert_eq!(header(&headers, "content-length"), Some("11"));
+		assert_eq!(body, b"/echo hello");
+
+		stream
+			.write_all(&frame_bytes(GOAWAY, 0, 0, &[0, 0, 0, 1, 0, 0, 0, 0]))
+			.unwrap();
+	}
+
+	#[test]
+	fn large_body_is_refused_without_blocking_the_connection() {
+		let mut stream = connect(18476);
+		let request = [
+			(":method", "POST"),
+			(":scheme", "http"),
+			(":path", "/upload"),
+			(":authority", "localhost"),
+		]
+		.iter()
+		.map(|(n, v)| (String::from(*n), String::from(*v)))
+		.collect::<Vec<_>>();
+
+		// The server may not send any response data before the window is opened
+		let mut settings = SETTINGS_INITIAL_WINDOW_SIZE.to_be_bytes().to_vec();
+		settings.extend(0u32.to_be_bytes());
+		let mut out = Vec::from(PREFACE);
+		out.extend(frame_bytes(SETTINGS, 0, 0, &settings));
+		out.extend(frame_bytes(
+			HEADERS,
+			END_HEADERS,
+			1,
+			&hpack::encode(&request),
+		));
+		stream.write_all(&out).unwrap();
+
+		// The server opens its windows as fast as data arrives, so the client does not need to wait
+		let mut reader = BufReader::new(stream.try_clone().unwrap());
+		let writer = std::thread::spawn(move || {
+			let data = frame_bytes(DATA, 0, 1, &[b'x'; FRAME_SIZE]);
+			for _ in 0..MAX_BODY / FRAME_SIZE + 2 {
+				stream.write_all(&data).unwrap();
+			}
+			stream
+				.write_all(&frame_bytes(PING, 0, 0, b"12345678"))
+				.unwrap();
+			stream
+		});
+
+		let mut decoder = hpack::Decoder::new(hpack::TABLE_SIZE);
+		let (mut status, mut ping_acked) = (None, false);
+		while status.is_none() || !ping_acked {
+			let frame = read_frame(&mut reader).unwrap();
+			match frame.kind {
+				HEADERS => {
+					status = header(&decoder.decode(&frame.payload).unwrap(), ":status")
+						.map(String::from)
+				}
+				PING => ping_acked = frame.flags & ACK != 0,
+				SETTINGS | WINDOW_UPDATE => {}
+				kind => panic!("Unexpected frame type {}", kind),
+			}
+		}
+		assert_eq!(status.as_deref(), Some("413"));
+
+		let mut stream = writer.join().unwrap();
+		stream
+			.write_all(&frame_bytes(
+				WINDOW_UPDATE,
+				0,
+				1,
+				&(1u32 << 20).to_be_bytes(),
+			))
+			.unwrap();
+		let mut ended = false;
+		loop {
+			let frame = read_frame(&mut reader).unwrap();
+			match frame.kind {
+				DATA => ended |= frame.flags & END_STREAM != 0,
+				RST_STREAM => {
+					assert!(ended);
+					assert_eq!(
+						(frame.stream, &frame.payload[..]),
+						(1, &NO_ERROR.to_be_bytes()[..])
+					);
+					break;
+				}
+				SETTINGS | WINDOW_UPDATE => {}
+				kind => panic!("Unexpected frame type {}", kind),
+			}
+		}
+	}
+}
@@ -16,3 +16,13 @@ pub const CONNECT: &'static str = "CONNECT";
 pub const TRACE: &'static str = "TRACE";
 /// The PATCH method is used to apply partial modifications to a resource.
 pub const PATCH: &'static str = "PATCH";
+/// The WebDAV MKCOL method creates a new collection (directory) at the specified location.
+pub const MKCOL: &str = "MKCOL";
+/// The WebDAV PROPFIND method retrieves properties of the specified resource and, depending on the Depth header, its members.
+pub const PROPFIND: &str = "PROPFIND";
+/// The WebDAV PROPPATCH method sets or removes properties of the specified resource.
+pub const PROPPATCH: &str = "PROPPATCH";
+/// The WebDAV MOVE method moves the specified resource to the location given in the Destination header.
+pub const MOVE: &str = "MOVE";
+/// The WebDAV COPY method copies the specified resource to the location given in the Destination header.
+pub const COPY: &str = "COPY";
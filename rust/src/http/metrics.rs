@@ -0,0 +1,389 @@
+use super::{methods, Request, RequestHandler, Response};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use threadpool::ThreadPool;
+
+/// Upper bounds in seconds of the request duration histogram buckets
+const DURATION_BUCKETS: [f64; 11] = [
+	0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Methods that are used as label as-is, all others are counted as "OTHER"
+const KNOWN_METHODS: [&str; 14] = [
+	methods::GET,
+	methods::POST,
+	methods::PUT,
+	methods::DELETE,
+	methods::HEAD,
+	methods::OPTIONS,
+	methods::CONNECT,
+	methods::TRACE,
+	methods::PATCH,
+	methods::MKCOL,
+	methods::PROPFIND,
+	methods::PROPPATCH,
+	methods::MOVE,
+	methods::COPY,
+];
+
+#[derive(Default)]
+struct Histogram {
+	buckets: [u64; DURATION_BUCKETS.len()],
+	count: u64,
+	sum: f64,
+}
+
+impl Histogram {
+	fn observe(&mut self, seconds: f64) {
+		for (i, bound) in DURATION_BUCKETS.iter().enumerate() {
+			if seconds <= *bound {
+				self.buckets[i] += 1;
+			}
+		}
+		self.count += 1;
+		self.sum += seconds;
+	}
+}
+
+/// Per-request metrics, keyed by their labels
+#[derive(Default)]
+struct RequestMetrics {
+	/// Request counts by method, route and status
+	counts: BTreeMap<(String, String, u16), u64>,
+	/// Request durations by method and route
+	durations: BTreeMap<(String, String), Histogram>,
+}
+
+/// Metrics collected by a [super::Server], rendered in the Prometheus text exposition format by a [MetricsHandler].
+///
+/// Requests are labeled with their method, the route of the handler that answered them and the status code. The route
+/// is the one returned by [RequestHandler::route], or "handler_N" for the Nth registered handler if it does not
+/// provide one, and "none" for requests no handler matched.
+pub struct Metrics {
+	requests: Mutex<RequestMetrics>,
+	in_flight: AtomicI64,
+	connections: AtomicI64,
+	waiting_connections: AtomicI64,
+	http2_connections: AtomicI64,
+	received_bytes: AtomicU64,
+	sent_bytes: AtomicU64,
+	panics: AtomicU64,
+	pool: Mutex<Option<ThreadPool>>,
+}
+
+impl Metrics {
+	/// Creates a new set of metrics with all values at zero
+	pub fn new() -> Metrics {
+		Metrics {
+			requests: Mutex::new(RequestMetrics::default()),
+			in_flight: AtomicI64::new(0),
+			connections: AtomicI64::new(0),
+			waiting_connections: AtomicI64::new(0),
+			http2_connections: AtomicI64::new(0),
+			received_bytes: AtomicU64::new(0),
+			sent_bytes: AtomicU64::new(0),
+			panics: AtomicU64::new(0),
+			pool: Mutex::new(None),
+		}
+	}
+
+	/// Sets the thread pool whose queue depth and active threads are reported
+	pub(crate) fn set_pool(&self, pool: &ThreadPool) {
+		if let Ok(mut p) = self.pool.lock() {
+			*p = Some(pool.clone());
+		}
+	}
+
+	/// Counts an open connection until the returned guard is dropped
+	pub(crate) fn connection(self: &Arc<Self>) -> Gauge {
+		Gauge::increment(self.clone(), |m| &m.connections)
+	}
+
+	/// Returns the number of open connections
+	pub(crate) fn connections(&self) -> i64 {
+		self.connections.load(Ordering::Relaxed)
+	}
+
+	/// Counts a connection the event loop holds until its request arrived, until the returned guard is dropped
+	#[cfg(target_os = "linux")]
+	pub(crate) fn waiting_connection(self: &Arc<Self>) -> Gauge {
+		Gauge::increment(self.clone(), |m| &m.waiting_connections)
+	}
+
+	/// Counts an HTTP/2 connection until the returned guard is dropped
+	pub(crate) fn http2_connection(self: &Arc<Self>) -> Gauge {
+		Gauge::increment(self.clone(), |m| &m.http2_connections)
+	}
+
+	/// Returns the number of HTTP/2 connections
+	pub(crate) fn http2_connections(&self) -> i64 {
+		self.http2_connections.load(Ordering::Relaxed)
+	}
+
+	/// Counts a request in flight until the returned guard is dropped
+	pub(crate) fn in_flight(self: &Arc<Self>) -> Gauge {
+		Gauge::increment(self.clone(), |m| &m.in_flight)
+	}
+
+	/// Records a completed request
+	pub(crate) fn record(
+		&self,
+		method: &str,
+		route: &str,
+		status: u16,
+		duration: Duration,
+		received: u64,
+		sent: u64,
+	) {
+		self.received_bytes.fetch_add(received, Ordering::Relaxed);
+		self.sent_bytes.fetch_add(sent, Ordering::Relaxed);
+
+		let method = match KNOWN_METHODS.iter().find(|m| **m == method) {
+			Some(m) => String::from(*m),
+			None => String::from("OTHER"),
+		};
+
+		let mut requests = match self.requests.lock() {
+			Ok(r) => r,
+			Err(e) => e.into_inner(),
+		};
+		*requests
+			.counts
+			.entry((method.clone(), String::from(route), status))
+			.or_insert(0) += 1;
+		requests
+			.durations
+			.entry((method, String::from(route)))
+			.or_default()
+			.observe(duration.as_secs_f64());
+	}
+
+	/// Counts a handler that panicked
+	pub(crate) fn record_panic(&self) {
+		self.panics.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Returns the number of handlers that panicked
+	pub fn panics(&self) -> u64 {
+		self.panics.load(Ordering::Relaxed)
+	}
+
+	/// Returns all metrics in the Prometheus text exposition format
+	pub fn render(&self) -> String {
+		let mut out = String::new();
+
+		{
+			let requests = match self.requests.lock() {
+				Ok(r) => r,
+				Err(e) => e.into_inner(),
+			};
+
+			out.push_str("# HELP mi_http_requests_total Number of completed HTTP requests.\n");
+			out.push_str("# TYPE mi_http_requests_total counter\n");
+			for ((method, route, status), count) in &requests.counts {
+				let _ = writeln!(
+					out,
+					"mi_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+					method,
+					escape_label(route),
+					status,
+					count
+				);
+			}
+
+			out.push_str(
+				"# HELP mi_http_request_duration_seconds Time from accepting the connection until the handler returned.\n",
+			);
+			out.push_str("# TYPE mi_http_request_duration_seconds histogram\n");
+			for ((method, route), histogram) in &requests.durations {
+				let labels = format!("method=\"{}\",route=\"{}\"", method, escape_label(route));
+				for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets.iter()) {
+					let _ = writeln!(
+						out,
+						"mi_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+						labels, bound, count
+					);
+				}
+				let _ = writeln!(
+					out,
+					"mi_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+					labels, histogram.count
+				);
+				let _ = writeln!(
+					out,
+					"mi_http_request_duration_seconds_sum{{{}}} {}",
+					labels, histogram.sum
+				);
+				let _ = writeln!(
+					out,
+					"mi_http_request_duration_seconds_count{{{}}} {}",
+					labels, histogram.count
+				);
+			}
+		}
+
+		let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+			let _ = writeln!(out, "# HELP {} {}", name, help);
+			let _ = writeln!(out, "# TYPE {} {}", name, kind);
+			let _ = writeln!(out, "{} {}", name, value);
+		};
+
+		metric(
+			"mi_http_requests_in_flight",
+			"gauge",
+			"Number of requests currently queued or being handled.",
+			self.in_flight.load(Ordering::Relaxed).to_string(),
+		);
+		metric(
+			"mi_http_connections_active",
+			"gauge",
+			"Number of open client connections.",
+			self.connections.load(Ordering::Relaxed).to_string(),
+		);
+		metric(
+			"mi_http_connections_waiting",
+			"gauge",
+			"Number of connections the event loop holds until their request arrived.",
+			self.waiting_connections.load(Ordering::Relaxed).to_string(),
+		);
+		metric(
+			"mi_http2_connections_active",
+			"gauge",
+			"Number of open HTTP/2 connections, each served by a thread of its own.",
+			self.http2_connections.load(Ordering::Relaxed).to_string(),
+		);
+		metric(
+			"mi_http_received_bytes_total",
+			"counter",
+			"Size of request heads and declared request bodies in bytes.",
+			self.received_bytes.load(Ordering::Relaxed).to_string(),
+		);
+		metric(
+			"mi_http_sent_bytes_total",
+			"counter",
+			"Size of sent response heads and bodies in bytes.",
+			self.sent_bytes.load(Ordering::Relaxed).to_string(),
+		);
+
+		metric(
+			"mi_http_handler_panics_total",
+			"counter",
+			"Number of requests whose handler panicked.",
+			self.panics().to_string(),
+		);
+
+		let (queued, active, threads) = match self.pool.lock() {
+			Ok(p) => match p.as_ref() {
+				Some(p) => (p.queued_count(), p.active_count(), p.max_count()),
+				None => (0, 0, 0),
+			},
+			Err(_) => (0, 0, 0),
+		};
+		metric(
+			"mi_threadpool_queued_jobs",
+			"gauge",
+			"Number of requests waiting for a worker thread.",
+			queued.to_string(),
+		);
+		metric(
+			"mi_threadpool_active_threads",
+			"gauge",
+			"Number of worker threads currently handling a request.",
+			active.to_string(),
+		);
+		metric(
+			"mi_threadpool_threads",
+			"gauge",
+			"Number of worker threads.",
+			threads.to_string(),
+		);
+
+		out
+	}
+}
+
+impl Default for Metrics {
+	fn default() -> Self {
+		Metrics::new()
+	}
+}
+
+/// Decrements a gauge of [Metrics] when dropped
+pub(crate) struct Gauge {
+	metrics: Arc<Metrics>,
+	gauge: fn(&Metrics) -> &AtomicI64,
+}
+
+impl Gauge {
+	fn increment(metrics: Arc<Metrics>, gauge: fn(&Metrics) -> &AtomicI64) -> Gauge {
+		gauge(&metrics).fetch_add(1, Ordering::Relaxed);
+		Gauge { metrics, gauge }
+	}
+}
+
+impl Drop for Gauge {
+	fn drop(&mut self) {
+		(self.gauge)(&self.metrics).fetch_sub(1, Ordering::Relaxed);
+	}
+}
+
+/// A [RequestHandler] that serves the metrics of a server at the given path.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::*;
+/// use std::sync::Arc;
+///
+/// let mut server = Server::new();
+/// server.handler(Arc::new(MetricsHandler::new("/metrics", server.metrics())));
+/// ```
+pub struct MetricsHandler {
+	path: String,
+	metrics: Arc<Metrics>,
+}
+
+impl MetricsHandler {
+	/// Creates a handler that serves the given metrics for GET requests to path
+	pub fn new(path: &str, metrics: Arc<Metrics>) -> MetricsHandler {
+		MetricsHandler {
+			path: String::from(path),
+			metrics,
+		}
+	}
+}
+
+impl RequestHandler for MetricsHandler {
+	fn matches(&self, req: &Request) -> bool {
+		let path = &req.uri[..req.uri.find('?').unwrap_or(req.uri.len())];
+		path == self.path
+	}
+
+	fn handle(&self, req: &Request, mut res: Response) {
+		if req.method != methods::GET && req.method != methods::HEAD {
+			res.headers.set("Allow", "GET, HEAD");
+			let _ = res.send_error(&super::Error::new(405, "Only GET and HEAD are allowed"));
+			return;
+		}
+
+		res.headers
+			.set("Content-Type", "text/plain; version=0.0.4; charset=utf-8");
+		res.headers.set("Cache-Control", "no-store");
+		res.w(self.metrics.render());
+	}
+
+	fn route(&self) -> Option<String> {
+		Some(self.path.clone())
+	}
+}
+
+/// Escapes backslashes, double quotes and line breaks in label values
+fn escape_label(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
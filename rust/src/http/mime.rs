@@ -0,0 +1,244 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+
+/// The type used for extensions that are not registered
+pub const DEFAULT_TYPE: &str = "application/octet-stream";
+
+/// Built-in mapping of file extensions to MIME types
+const BUILTIN_TYPES: &[(&str, &str)] = &[
+	// Text
+	("html", "text/html"),
+	("htm", "text/html"),
+	("css", "text/css"),
+	("csv", "text/csv"),
+	("ics", "text/calendar"),
+	("js", "text/javascript"),
+	("mjs", "text/javascript"),
+	("md", "text/markdown"),
+	("txt", "text/plain"),
+	("vtt", "text/vtt"),
+	// Application
+	("7z", "application/x-7z-compressed"),
+	("bz", "application/x-bzip"),
+	("bz2", "application/x-bzip2"),
+	("epub", "application/epub+zip"),
+	("gz", "application/gzip"),
+	("jar", "application/java-archive"),
+	("json", "application/json"),
+	("jsonld", "application/ld+json"),
+	("map", "application/json"),
+	("odp", "application/vnd.oasis.opendocument.presentation"),
+	("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+	("odt", "application/vnd.oasis.opendocument.text"),
+	("ogx", "application/ogg"),
+	("pdf", "application/pdf"),
+	("rar", "application/vnd.rar"),
+	("rtf", "application/rtf"),
+	("tar", "application/x-tar"),
+	("wasm", "application/wasm"),
+	("webmanifest", "application/manifest+json"),
+	("xhtml", "application/xhtml+xml"),
+	("xml", "application/xml"),
+	("zip", "application/zip"),
+	// Fonts
+	("eot", "application/vnd.ms-fontobject"),
+	("otf", "font/otf"),
+	("ttf", "font/ttf"),
+	("woff", "font/woff"),
+	("woff2", "font/woff2"),
+	// Images
+	("avif", "image/avif"),
+	("bmp", "image/bmp"),
+	("gif", "image/gif"),
+	("ico", "image/vnd.microsoft.icon"),
+	("jpeg", "image/jpeg"),
+	("jpg", "image/jpeg"),
+	("png", "image/png"),
+	("svg", "image/svg+xml"),
+	("tif", "image/tiff"),
+	("tiff", "image/tiff"),
+	("webp", "image/webp"),
+	// Audio
+	("aac", "audio/aac"),
+	("flac", "audio/flac"),
+	("mp3", "audio/mpeg"),
+	("oga", "audio/ogg"),
+	("opus", "audio/opus"),
+	("wav", "audio/wav"),
+	("weba", "audio/webm"),
+	// Video
+	("mp4", "video/mp4"),
+	("mpeg", "video/mpeg"),
+	("ogv", "video/ogg"),
+	("webm", "video/webm"),
+];
+
+lazy_static! {
+	static ref REGISTRY: RwLock<MimeTypes> = RwLock::new(MimeTypes::new());
+}
+
+/// A mapping of file extensions to MIME types. Extensions are matched case-insensitively and without their leading
+/// dot.
+///
+/// Besides creating own instances, a global registry shared by [super::FileHandler] and other handlers can be used
+/// via the functions of this module.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::mime::MimeTypes;
+/// let mut types = MimeTypes::new();
+/// types.register(".GPX", "application/gpx+xml");
+///
+/// assert_eq!(types.content_type("gpx"), "application/gpx+xml");
+/// assert_eq!(types.content_type_for_path("notes/readme.TXT"), "text/plain; charset=utf-8");
+/// assert_eq!(types.content_type_for_path("unknown.xyz"), "application/octet-stream");
+/// ```
+#[derive(Clone)]
+pub struct MimeTypes {
+	types: HashMap<String, String>,
+}
+
+impl MimeTypes {
+	/// Creates a new registry containing the built-in types
+	pub fn new() -> MimeTypes {
+		let mut types = MimeTypes::empty();
+		for (ext, mime) in BUILTIN_TYPES {
+			types.register(ext, mime);
+		}
+		types
+	}
+
+	/// Creates a new registry without any types
+	pub fn empty() -> MimeTypes {
+		MimeTypes {
+			types: HashMap::new(),
+		}
+	}
+
+	/// Registers the given MIME type for an extension, replacing any previous entry
+	pub fn register(&mut self, extension: &str, mime_type: &str) {
+		let ext = extension
+			.trim()
+			.trim_start_matches('.')
+			.to_ascii_lowercase();
+		if ext.is_empty() {
+			return;
+		}
+		self.types.insert(ext, String::from(mime_type.trim()));
+	}
+
+	/// Registers all entries from a string in the format of /etc/mime.types: every line contains a MIME type followed
+	/// by its extensions, separated by whitespace. Empty lines and lines starting with "#" are ignored.
+	/// Returns the number of registered extensions.
+	pub fn load_str(&mut self, data: &str) -> usize {
+		let mut count = 0;
+		for line in data.lines() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			let mut parts = line.split_whitespace();
+			let mime_type = match parts.next() {
+				Some(m) if m.contains('/') => m,
+				_ => continue,
+			};
+
+			for ext in parts {
+				self.register(ext, mime_type);
+				count += 1;
+			}
+		}
+		count
+	}
+
+	/// Registers all entries from a file in the format of /etc/mime.types. Returns the number of registered extensions.
+	pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, std::io::Error> {
+		let data = std::fs::read_to_string(path)?;
+		Ok(self.load_str(&data))
+	}
+
+	/// Returns the MIME type registered for the given extension without any parameters
+	pub fn get(&self, extension: &str) -> Option<&str> {
+		let ext = extension.trim_start_matches('.').to_ascii_lowercase();
+		self.types.get(&ext).map(|m| m.as_str())
+	}
+
+	/// Returns the Content-Type header value for the given extension. Text types get a UTF-8 charset parameter and
+	/// unknown extensions are mapped to [DEFAULT_TYPE].
+	pub fn content_type(&self, extension: &str) -> String {
+		match self.get(extension) {
+			Some(m) => with_charset(m),
+			None => String::from(DEFAULT_TYPE),
+		}
+	}
+
+	/// Returns the Content-Type header value for the extension of the given path
+	pub fn content_type_for_path<P: AsRef<Path>>(&self, path: P) -> String {
+		match path.as_ref().extension() {
+			Some(ext) => self.content_type(&ext.to_string_lossy()),
+			None => String::from(DEFAULT_TYPE),
+		}
+	}
+}
+
+impl Default for MimeTypes {
+	fn default() -> Self {
+		MimeTypes::new()
+	}
+}
+
+fn with_charset(mime_type: &str) -> String {
+	if mime_type.starts_with("text/") && !mime_type.contains("charset=") {
+		format!("{}; charset=utf-8", mime_type)
+	} else {
+		String::from(mime_type)
+	}
+}
+
+/// Registers the given MIME type for an extension in the global registry
+pub fn register(extension: &str, mime_type: &str) {
+	match REGISTRY.write() {
+		Ok(mut r) => r.register(extension, mime_type),
+		Err(e) => e.into_inner().register(extension, mime_type),
+	}
+}
+
+/// Registers all entries from a file in the format of /etc/mime.types in the global registry. Returns the number of
+/// registered extensions.
+pub fn load_file<P: AsRef<Path>>(path: P) -> Result<usize, std::io::Error> {
+	let data = std::fs::read_to_string(path)?;
+	Ok(match REGISTRY.write() {
+		Ok(mut r) => r.load_str(&data),
+		Err(e) => e.into_inner().load_str(&data),
+	})
+}
+
+/// Returns the Content-Type header value for the given extension from the global registry
+pub fn content_type(extension: &str) -> String {
+	match REGISTRY.read() {
+		Ok(r) => r.content_type(extension),
+		Err(e) => e.into_inner().content_type(extension),
+	}
+}
+
+/// Returns the Content-Type header value for the extension of the given path from the global registry
+///
+/// # Example
+///
+/// ```
+/// use mi::http::mime;
+/// mime::register("gpx", "application/gpx+xml");
+///
+/// assert_eq!(mime::content_type_for_path("/tracks/run.gpx"), "application/gpx+xml");
+/// assert_eq!(mime::content_type_for_path("/index.html"), "text/html; charset=utf-8");
+/// ```
+pub fn content_type_for_path<P: AsRef<Path>>(path: P) -> String {
+	match REGISTRY.read() {
+		Ok(r) => r.content_type_for_path(path),
+		Err(e) => e.into_inner().content_type_for_path(path),
+	}
+}
@@ -3,28 +3,62 @@
 /// Container for HTTP method constants
 pub mod methods;
 
+/// Registry for mapping file extensions to MIME types
+pub mod mime;
+
 // Modules for file management purposes
+mod accesslog;
+mod auth;
+mod body;
+mod client;
+mod cors;
 mod error;
+#[cfg(target_os = "linux")]
+mod eventloop;
+mod eventstream;
 mod filehandler;
 mod handler;
+mod headers;
+mod hpack;
+mod http2;
+mod metrics;
+mod negotiate;
+mod proxy;
+mod ratelimit;
 mod request;
 mod response;
 mod server;
 mod traits;
 mod valuesmap;
+mod vhosts;
+mod webdav;
+mod websocket;
 
 // Public structs
-pub use error::Error;
-pub use filehandler::FileHandler;
-pub use handler::Handler;
-pub use request::Request;
+pub use accesslog::{AccessLogFormat, LogField};
+pub use auth::{AuthHandler, Htpasswd};
+pub use client::{Client, ClientResponse};
+pub use cors::CorsHandler;
+pub use error::{render_error, Error, ErrorRenderer};
+pub use eventstream::{Event, EventStream};
+pub use filehandler::{CachePolicy, FileHandler, FileHandlerBuilder, SymlinkPolicy};
+pub use handler::{Handler, ResultHandler};
+pub use headers::{Authorization, CacheControl, ContentType, Host};
+pub use metrics::{Metrics, MetricsHandler};
+pub use proxy::{ProxyHandler, Upstream};
+pub use ratelimit::{ClientKey, RateLimiter};
+pub use request::{BodyReader, Request};
 pub use response::Response;
 pub use server::Server;
 pub use traits::RequestHandler;
 pub use valuesmap::ValuesMap;
+pub use vhosts::VirtualHosts;
+pub use websocket::{Message, WebSocket, WebSocketHandler, WebSocketSender};
 
 // Public functions
-pub use util::lookup_status_str;
+pub use util::{
+	format_http_date, lookup_status_str, parse_http_date, percent_decode, percent_encode,
+};
 
 // Private API
 mod util;
@@ -0,0 +1,166 @@
+use super::ContentType;
+
+/// A range of values the client accepts, with its quality
+struct Preference<'a> {
+	range: &'a str,
+	/// Parameters of a media range besides q
+	params: Vec<(&'a str, &'a str)>,
+	q: f32,
+}
+
+/// Parses a header value like "text/html, application/json;q=0.9, */*;q=0.1" into its ranges. Ranges without valid
+/// q-value have quality 1
+fn parse(header: &str) -> Vec<Preference<'_>> {
+	let mut preferences = Vec::new();
+	for item in header.split(',') {
+		let mut parts = item.split(';').map(|p| p.trim());
+		let range = parts.next().unwrap_or("");
+		if range.is_empty() {
+			continue;
+		}
+
+		let mut preference = Preference {
+			range,
+			params: Vec::new(),
+			q: 1.0,
+		};
+		for param in parts {
+			let (name, value) = match param.find('=') {
+				Some(p) => (param[..p].trim(), param[p + 1..].trim().trim_matches('"')),
+				None => (param, ""),
+			};
+			if name.eq_ignore_ascii_case("q") {
+				preference.q = value.parse::<f32>().unwrap_or(1.0).clamp(0.0, 1.0);
+				// Parameters after q are accept extensions, not part of the range
+				break;
+			}
+			preference.params.push((name, value));
+		}
+		preferences.push(preference);
+	}
+	preferences
+}
+
+/// Returns the offer with the highest quality according to the header, the first one if several are equally good. An
+/// offer gets the quality of the most specific range matching it, matches returns the specificity of a range for an
+/// offer or None if it does not match. Offers no range matches get the quality unmatched. Without header every offer
+/// is acceptable.
+fn best<'a>(
+	header: Option<&str>,
+	offered: &[&'a str],
+	matches: fn(&Preference, &str) -> Option<usize>,
+	unmatched: fn(&str) -> f32,
+) -> Option<&'a str> {
+	let header = match header {
+		Some(h) => h,
+		None => return offered.first().copied(),
+	};
+	let preferences = parse(header);
+
+	let mut best: Option<(&str, f32)> = None;
+	for offer in offered {
+		let mut quality: Option<(usize, f32)> = None;
+		for preference in &preferences {
+			if let Some(specificity) = matches(preference, offer) {
+				if quality.is_none_or(|(s, _)| specificity > s) {
+					quality = Some((specificity, preference.q));
+				}
+			}
+		}
+
+		let q = quality.map_or_else(|| unmatched(offer), |(_, q)| q);
+		if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+			best = Some((offer, q));
+		}
+	}
+	best.map(|(offer, _)| offer)
+}
+
+fn media_type_matches(preference: &Preference, offer: &str) -> Option<usize> {
+	let offer = ContentType::parse(offer)?;
+	let range = preference.range.to_ascii_lowercase();
+	let main = &offer.media_type[..offer.media_type.find('/')?];
+	let specificity = if range == "*/*" {
+		0
+	} else if range.strip_suffix("/*") == Some(main) {
+		1
+	} else if range == offer.media_type {
+		2
+	} else {
+		return None;
+	};
+
+	// Parameters of the range must be present in the offer
+	for (name, value) in &preference.params {
+		if !offer
+			.param(name)
+			.is_some_and(|v| v.eq_ignore_ascii_case(value))
+		{
+			return None;
+		}
+	}
+	Some(specificity + preference.params.len())
+}
+
+fn language_matches(preference: &Preference, offer: &str) -> Option<usize> {
+	let range = preference.range;
+	if range == "*" {
+		return Some(0);
+	}
+
+	// A range matches the language tag itself and its more specific variants, "en" matches "en-US"
+	let (offer, range) = (offer.as_bytes(), range.as_bytes());
+	let matches = offer.len() >= range.len()
+		&& offer[..range.len()].eq_ignore_ascii_case(range)
+		&& (offer.len() == range.len() || offer[range.len()] == b'-');
+	if matches {
+		Some(range.len())
+	} else {
+		None
+	}
+}
+
+fn token_matches(preference: &Preference, offer: &str) -> Option<usize> {
+	if preference.range == "*" {
+		Some(0)
+	} else if preference.range.eq_ignore_ascii_case(offer) {
+		Some(1)
+	} else {
+		None
+	}
+}
+
+fn not_acceptable(_: &str) -> f32 {
+	0.0
+}
+
+/// The identity encoding is acceptable unless it is excluded explicitly
+fn identity_acceptable(offer: &str) -> f32 {
+	if offer.eq_ignore_ascii_case("identity") {
+		1.0
+	} else {
+		0.0
+	}
+}
+
+/// Returns the offered media type that is preferred according to the given Accept header. Offers may have parameters
+/// like "text/html; charset=utf-8"
+pub(crate) fn media_type<'a>(accept: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+	best(accept, offered, media_type_matches, not_acceptable)
+}
+
+/// Returns the offered language tag that is preferred according to the given Accept-Language header
+pub(crate) fn language<'a>(accept_language: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+	best(accept_language, offered, language_matches, not_acceptable)
+}
+
+/// Returns the offered charset that is preferred according to the given Accept-Charset header
+pub(crate) fn charset<'a>(accept_charset: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+	best(accept_charset, offered, token_matches, not_acceptable)
+}
+
+/// Returns the offered content coding that is preferred according to the given Accept-Encoding header. "identity" is
+/// acceptable unless the header excludes it with "identity;q=0" or "*;q=0"
+pub(crate) fn encoding<'a>(accept_encoding: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+	best(accept_encoding, offered, token_matches, identity_acceptable)
+}
@@ -0,0 +1,501 @@
+use super::body::{copy_chunked, is_chunked, read_head, ChunkedReader, HOP_BY_HOP_HEADERS};
+use super::{methods, Request, Response};
+use crate::log_error;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Location of the backend a [ProxyHandler] forwards requests to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Upstream {
+	/// A TCP address in the form "host:port"
+	Tcp(String),
+	/// The path of a Unix domain socket
+	#[cfg(unix)]
+	Unix(PathBuf),
+}
+
+/// Connection to an [Upstream]
+enum Connection {
+	Tcp(TcpStream),
+	#[cfg(unix)]
+	Unix(UnixStream),
+}
+
+impl Connection {
+	fn open(upstream: &Upstream, timeout: Duration) -> Result<Connection, std::io::Error> {
+		let connection = match upstream {
+			Upstream::Tcp(address) => {
+				let mut last_error = std::io::Error::new(
+					std::io::ErrorKind::NotFound,
+					format!("Cannot resolve {}", address),
+				);
+
+				let mut connected = None;
+				for addr in address.to_socket_addrs()? {
+					match TcpStream::connect_timeout(&addr, timeout) {
+						Ok(s) => {
+							connected = Some(s);
+							break;
+						}
+						Err(e) => last_error = e,
+					}
+				}
+
+				match connected {
+					Some(s) => Connection::Tcp(s),
+					None => return Err(last_error),
+				}
+			}
+			#[cfg(unix)]
+			Upstream::Unix(path) => Connection::Unix(UnixStream::connect(path)?),
+		};
+
+		match &connection {
+			Connection::Tcp(s) => {
+				s.set_read_timeout(Some(timeout))?;
+				s.set_write_timeout(Some(timeout))?;
+			}
+			#[cfg(unix)]
+			Connection::Unix(s) => {
+				s.set_read_timeout(Some(timeout))?;
+				s.set_write_timeout(Some(timeout))?;
+			}
+		}
+
+		Ok(connection)
+	}
+}
+
+impl Read for Connection {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+		match self {
+			Connection::Tcp(s) => s.read(buf),
+			#[cfg(unix)]
+			Connection::Unix(s) => s.read(buf),
+		}
+	}
+}
+
+impl Write for Connection {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+		match self {
+			Connection::Tcp(s) => s.write(buf),
+			#[cfg(unix)]
+			Connection::Unix(s) => s.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> Result<(), std::io::Error> {
+		match self {
+			Connection::Tcp(s) => s.flush(),
+			#[cfg(unix)]
+			Connection::Unix(s) => s.flush(),
+		}
+	}
+}
+
+/// A [super::RequestHandler] that forwards requests starting with a URI prefix to a backend server and streams its
+/// response back to the client.
+///
+/// Hop-by-hop headers are removed in both directions and X-Forwarded-For, X-Forwarded-Proto and X-Forwarded-Host are
+/// added to the forwarded request. If the backend cannot be reached or sends an invalid response, the client receives
+/// 502 Bad Gateway, if it does not answer in time 504 Gateway Timeout.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::*;
+/// use std::sync::Arc;
+///
+/// let mut api = ProxyHandler::new("/api", "127.0.0.1:8081");
+/// api.strip_prefix = true;
+///
+/// let mut server = Server::new();
+/// server.handler(Arc::new(api));
+/// #[cfg(unix)]
+/// server.handler(Arc::new(ProxyHandler::unix("/app", "/run/app.sock")));
+/// ```
+pub struct ProxyHandler {
+	/// Whether or not to remove the URI prefix from the path sent to the backend. Defaults to false
+	pub strip_prefix: bool,
+	/// Timeout for connecting to the backend and for each read or write while exchanging data with it. Defaults to 30
+	/// seconds
+	pub timeout: Duration,
+	uri_prefix: String,
+	upstream: Upstream,
+}
+
+impl ProxyHandler {
+	/// Returns a handler that forwards requests for URLs starting with uri_prefix to the given "host:port" address
+	pub fn new(uri_prefix: &str, address: &str) -> ProxyHandler {
+		ProxyHandler::for_upstream(uri_prefix, Upstream::Tcp(String::from(address)))
+	}
+
+	/// Returns a handler that forwards requests for URLs starting with uri_prefix to the given Unix domain socket
+	#[cfg(unix)]
+	pub fn unix<P: AsRef<Path>>(uri_prefix: &str, socket: P) -> ProxyHandler {
+		ProxyHandler::for_upstream(uri_prefix, Upstream::Unix(PathBuf::from(socket.as_ref())))
+	}
+
+	/// Returns a handler that forwards requests for URLs starting with uri_prefix to the given [Upstream]
+	pub fn for_upstream(uri_prefix: &str, upstream: Upstream) -> ProxyHandler {
+		ProxyHandler {
+			strip_prefix: false,
+			timeout: Duration::from_secs(30),
+			uri_prefix: String::from(uri_prefix),
+			upstream,
+		}
+	}
+
+	/// Returns the request head to send to the backend
+	fn upstream_head(&self, req: &Request) -> String {
+		let mut path = req.uri.as_str();
+		if self.strip_prefix {
+			path = &path[self.uri_prefix.len()..];
+		}
+
+		let mut head = if path.starts_with('/') {
+			format!("{} {} HTTP/1.1\r\n", req.method, path)
+		} else {
+			format!("{} /{} HTTP/1.1\r\n", req.method, path)
+		};
+
+		// Chunked bodies are decoded and sent chunked again, a Content-Length sent along would not match
+		let chunked = is_chunked(&req.headers);
+		let connection_tokens = connection_tokens(req.headers.get_all("Connection"));
+		for (name, values) in req.headers.all() {
+			if is_hop_by_hop(name, &connection_tokens)
+				|| name.starts_with("X-Forwarded-")
+				|| (chunked && name.eq_ignore_ascii_case("Content-Length"))
+			{
+				continue;
+			}
+
+			for value in values {
+				head.push_str(&format!("{}: {}\r\n", name, value));
+			}
+		}
+
+		let host = req.headers.get("Host");
+		if host.is_none() {
+			match &self.upstream {
+				Upstream::Tcp(address) => head.push_str(&format!("Host: {}\r\n", address)),
+				#[cfg(unix)]
+				Upstream::Unix(_) => head.push_str("Host: localhost\r\n"),
+			}
+		}
+
+		let mut forwarded_for: Vec<String> = match req.headers.get_all("X-Forwarded-For") {
+			Some(values) => values.clone(),
+			None => Vec::new(),
+		};
+		if let Some(addr) = req.peer_addr() {
+			forwarded_for.push(addr.ip().to_string());
+		}
+		if !forwarded_for.is_empty() {
+			head.push_str(&format!(
+				"X-Forwarded-For: {}\r\n",
+				forwarded_for.join(", ")
+			));
+		}
+		head.push_str("X-Forwarded-Proto: http\r\n");
+		if let Some(host) = host {
+			head.push_str(&format!("X-Forwarded-Host: {}\r\n", host));
+		}
+
+		if chunked {
+			head.push_str("Transfer-Encoding: chunked\r\n");
+		}
+
+		// Connections to the backend are not reused
+		head.push_str("Connection: close\r\n\r\n");
+		head
+	}
+
+	/// Forwards the request and streams the response. Errors that happen before the response headers were sent are
+	/// returned as status code.
+	fn forward(&self, req: &Request, res: &mut Response) -> Result<(), u16> {
+		let mut connection = match Connection::open(&self.upstream, self.timeout) {
+			Ok(c) => c,
+			Err(e) => {
+				log_error!("Cannot connect to upstream {:?}: {}", self.upstream, e);
+				return Err(status_for(&e));
+			}
+		};
+
+		let sent = connection
+			.write_all(self.upstream_head(req).as_bytes())
+			.and_then(|_| {
+				if is_chunked(&req.headers) {
+					copy_chunked(&mut req.body_reader(), &mut connection)
+				} else {
+					req.copy_body_to(&mut connection)
+				}
+			})
+			.and_then(|_| connection.flush());
+		if let Err(e) = sent {
+			log_error!("Cannot send request to upstream {:?}: {}", self.upstream, e);
+			return Err(status_for(&e));
+		}
+
+		let mut reader = BufReader::new(connection);
+		let (code, headers) = loop {
+			let (status_line, headers) = match read_head(&mut reader) {
+				Ok(h) => h,
+				Err(e) => {
+					log_error!("Invalid response from upstream {:?}: {}", self.upstream, e);
+					return Err(status_for(&e));
+				}
+			};
+
+			let code: u16 = match status_line.split(' ').nth(1).map(|c| c.parse()) {
+				Some(Ok(c)) if (100..600).contains(&c) => c,
+				_ => {
+					log_error!(
+						"Invalid status line from upstream {:?}: {}",
+						self.upstream,
+						status_line
+					);
+					return Err(502);
+				}
+			};
+
+			// Interim responses like 100 Continue are not forwarded
+			if code >= 200 {
+				break (code, headers);
+			}
+		};
+
+		let has_body = req.method != methods::HEAD && code != 204 && code != 304;
+		let chunked = is_chunked(&headers);
+		let mut length = None;
+		let mut body: Box<dyn Read> = if !has_body {
+			Box::new(std::io::empty())
+		} else if chunked {
+			Box::new(ChunkedReader::new(reader))
+		} else {
+			match headers.get("Content-Length").map(|l| l.parse::<u64>()) {
+				Some(Ok(l)) => {
+					length = Some(l);
+					Box::new(reader.take(l))
+				}
+				Some(Err(_)) => return Err(502),
+				None => Box::new(reader),
+			}
+		};
+
+		res.status_code = code;
+		let connection_tokens = connection_tokens(headers.get_all("Connection"));
+		for (name, values) in headers.all() {
+			if is_hop_by_hop(name, &connection_tokens) {
+				continue;
+			}
+			if name == "Date" || name == "Server" {
+				// The upstream values replace the ones of this server
+				res.headers.remove(name);
+			}
+			for value in values {
+				res.headers.add(name, value);
+			}
+		}
+		if chunked {
+			// The decoded body is delimited by closing the connection
+			res.headers.remove("Content-Length");
+		}
+
+		// Send the headers right away, so the upstream Content-Length is kept for HEAD and empty responses
+		if res.send().is_err() {
+			return Ok(());
+		}
+
+		let mut buffer = [0; 16 * 1024];
+		let mut received = 0;
+		loop {
+			let read = match body.read(&mut buffer) {
+				Ok(0) => break,
+				Ok(r) => r,
+				Err(e) => {
+					log_error!(
+						"Error reading response body from upstream {:?}: {}",
+						self.upstream,
+						e
+					);
+					// Ending the response normally would pass it off as complete
+					res.abort();
+					return Ok(());
+				}
+			};
+			received += read as u64;
+
+			if res.write(&buffer[..read]).and_then(|_| res.send()).is_err() {
+				// The client went away
+				return Ok(());
+			}
+		}
+
+		if length.is_some_and(|l| received < l) {
+			log_error!(
+				"Upstream {:?} closed the connection before the end of the response body",
+				self.upstream
+			);
+			res.abort();
+			return Ok(());
+		}
+
+		let _ = res.end();
+		Ok(())
+	}
+}
+
+impl super::RequestHandler for ProxyHandler {
+	fn matches(&self, req: &Request) -> bool {
+		req.uri.starts_with(&self.uri_prefix)
+	}
+
+	fn route(&self) -> Option<String> {
+		Some(self.uri_prefix.clone())
+	}
+
+	fn handle(&self, req: &Request, mut res: Response) {
+		if let Err(code) = self.forward(req, &mut res) {
+			let message = match code {
+				504 => "The upstream server did not respond in time",
+				_ => "The upstream server could not be reached",
+			};
+			let _ = res.send_error(&super::Error::new(code, message));
+		}
+	}
+}
+
+/// Returns the status code to respond with for an error while communicating with the backend
+fn status_for(e: &std::io::Error) -> u16 {
+	match e.kind() {
+		std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => 504,
+		_ => 502,
+	}
+}
+
+/// Returns the header names listed in the Connection header, which are hop-by-hop as well
+fn connection_tokens(values: Option<&Vec<String>>) -> Vec<String> {
+	match values {
+		Some(values) => values
+			.iter()
+			.flat_map(|v| v.split(','))
+			.map(|t| t.trim().to_ascii_lowercase())
+			.collect(),
+		None => Vec::new(),
+	}
+}
+
+fn is_hop_by_hop(name: &str, connection_tokens: &[String]) -> bool {
+	HOP_BY_HOP_HEADERS
+		.iter()
+		.any(|h| h.eq_ignore_ascii_case(name))
+		|| connection_tokens.contains(&name.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::http::{Client, Handler, Server};
+	use std::net::TcpListener;
+	use std::sync::Arc;
+
+	/// Starts a server with the given handler and waits until it accepts connections
+	fn start(port: u16, handler: Arc<dyn super::super::RequestHandler>) {
+		std::thread::spawn(move || {
+			let mut server = Server::new();
+			server.handler(handler);
+			server.listen(port).unwrap();
+		});
+
+		let start = std::time::Instant::now();
+		while TcpStream::connect(("127.0.0.1", port)).is_err() {
+			assert!(start.elapsed() < Duration::from_secs(5));
+			std::thread::sleep(Duration::from_millis(10));
+		}
+	}
+
+	/// Starts an upstream that answers every connection with the given response, and closes it right after
+	fn start_raw(port: u16, response: &'static str) {
+		let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+		std::thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut stream = stream.unwrap();
+				let _ = read_head(&mut BufReader::new(&stream));
+				let _ = stream.write_all(response.as_bytes());
+			}
+		});
+	}
+
+	#[test]
+	fn forwards_to_upstream() {
+		start(
+			18477,
+			Arc::new(Handler::new(
+				|_| true,
+				|req, mut res| {
+					res.headers.set("X-Upstream", "yes");
+					res.headers.set("Keep-Alive", "timeout=5");
+					res.w(format!(
+						"{} {} {}",
+						req.method,
+						req.uri,
+						req.headers.get("X-Forwarded-For").unwrap_or("-")
+					))
+				},
+			)),
+		);
+		let mut api = ProxyHandler::new("/api", "127.0.0.1:18477");
+		api.strip_prefix = true;
+		start(18478, Arc::new(api));
+
+		let client = Client::new();
+		let res = client.get("http://127.0.0.1:18478/api/hello?x=1").unwrap();
+		assert_eq!(res.status_code, 200);
+		assert_eq!(res.text(), "GET /hello?x=1 127.0.0.1");
+		assert_eq!(res.headers.get("X-Upstream"), Some("yes"));
+		assert_eq!(res.headers.get("Keep-Alive"), None);
+
+		let res = client
+			.post("http://127.0.0.1:18478/api/items", "text/plain", b"item")
+			.unwrap();
+		assert_eq!(res.text(), "POST /items 127.0.0.1");
+	}
+
+	#[test]
+	fn unreachable_upstream_is_bad_gateway() {
+		// Nothing listens on the discard port
+		start(18480, Arc::new(ProxyHandler::new("/", "127.0.0.1:9")));
+
+		let res = Client::new().get("http://127.0.0.1:18480/").unwrap();
+		assert_eq!(res.status_code, 502);
+	}
+
+	#[test]
+	fn incomplete_upstream_body_is_not_passed_off_as_complete() {
+		start_raw(
+			18481,
+			"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n",
+		);
+		start_raw(18482, "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello");
+		start(
+			18483,
+			Arc::new(ProxyHandler::new("/chunked", "127.0.0.1:18481")),
+		);
+		start(
+			18484,
+			Arc::new(ProxyHandler::new("/length", "127.0.0.1:18482")),
+		);
+
+		let client = Client::new();
+		assert!(client.get("http://127.0.0.1:18483/chunked").is_err());
+		assert!(client.get("http://127.0.0.1:18484/length").is_err());
+	}
+}
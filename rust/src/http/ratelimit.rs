@@ -0,0 +1,219 @@
+use super::{Error, Request, RequestHandler, Response};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How a [RateLimiter] identifies clients
+#[derive(Clone)]
+pub enum ClientKey {
+	/// The IP address of the connected client
+	PeerIp,
+	/// The last comma-separated value of the given request header, e.g. an API key header. Requests without the header
+	/// are identified by their IP address
+	Header(String),
+	/// The value returned by the given function
+	Custom(fn(&Request) -> String),
+}
+
+/// Token bucket of a single client
+struct Bucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+/// A [RequestHandler] that wraps another handler and limits how many requests each client may send to it. Every client
+/// has a token bucket holding up to limit tokens, which is refilled continuously over the period. Each request takes one
+/// token, requests finding an empty bucket are answered with 429 Too Many Requests and a Retry-After header.
+///
+/// All responses carry RateLimit-Limit, RateLimit-Remaining and RateLimit-Reset headers. Separate limits for different
+/// routes are created by wrapping each route's handler in its own RateLimiter.
+///
+/// At most [RateLimiter::max_clients] buckets are kept. When a new client arrives and the limit is reached, buckets that
+/// have been refilled completely are dropped first, then the ones that were idle the longest until a sixteenth of the
+/// space is free again.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::*;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let thumbnails = Arc::new(Handler::new(|r| r.uri.starts_with("/thumbs/"), |_, mut res| {
+///     res.w("expensive");
+/// }));
+///
+/// // 10 requests per minute and client
+/// let mut limited = RateLimiter::new(thumbnails, 10, Duration::from_secs(60));
+/// limited.key = ClientKey::Header(String::from("X-Api-Key"));
+///
+/// let mut server = Server::new();
+/// server.handler(Arc::new(limited));
+/// ```
+pub struct RateLimiter {
+	/// How clients are identified. Defaults to [ClientKey::PeerIp]
+	pub key: ClientKey,
+	/// Maximum number of clients to keep track of. Defaults to 10000
+	pub max_clients: usize,
+	inner: Arc<dyn RequestHandler>,
+	limit: u32,
+	period: Duration,
+	buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+	/// Wraps the given handler, allowing each client limit requests per period
+	pub fn new(inner: Arc<dyn RequestHandler>, limit: u32, period: Duration) -> RateLimiter {
+		RateLimiter {
+			key: ClientKey::PeerIp,
+			max_clients: 10000,
+			inner,
+			limit: std::cmp::max(limit, 1),
+			period,
+			buckets: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Returns the key identifying the client that sent the request
+	fn client_key(&self, req: &Request) -> String {
+		let peer_ip = || match req.peer_addr() {
+			Some(a) => a.ip().to_string(),
+			None => String::new(),
+		};
+
+		match &self.key {
+			ClientKey::PeerIp => peer_ip(),
+			ClientKey::Header(name) => {
+				// Values are appended by each hop, so the first ones are the easiest to forge
+				let value = req
+					.headers
+					.get_all(name)
+					.and_then(|values| values.last())
+					.and_then(|v| v.rsplit(',').next())
+					.map(|v| v.trim())
+					.unwrap_or("");
+				if value.is_empty() {
+					peer_ip()
+				} else {
+					String::from(value)
+				}
+			}
+			ClientKey::Custom(f) => f(req),
+		}
+	}
+
+	/// Number of tokens added per second
+	fn rate(&self) -> f64 {
+		self.limit as f64 / self.period.as_secs_f64().max(0.001)
+	}
+
+	/// Takes a token from the client's bucket. Returns whether the request is allowed and the tokens left afterwards.
+	fn take(&self, key: String, now: Instant) -> (bool, f64) {
+		let capacity = self.limit as f64;
+		let rate = self.rate();
+
+		let mut buckets = match self.buckets.lock() {
+			Ok(b) => b,
+			Err(e) => e.into_inner(),
+		};
+
+		if !buckets.contains_key(&key) && buckets.len() >= self.max_clients {
+			evict(&mut buckets, capacity, rate, now);
+		}
+
+		let bucket = buckets.entry(key).or_insert(Bucket {
+			tokens: capacity,
+			updated: now,
+		});
+
+		let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+		bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+		bucket.updated = now;
+
+		if bucket.tokens >= 1.0 {
+			bucket.tokens -= 1.0;
+			(true, bucket.tokens)
+		} else {
+			(false, bucket.tokens)
+		}
+	}
+}
+
+impl RequestHandler for RateLimiter {
+	fn matches(&self, req: &Request) -> bool {
+		self.inner.matches(req)
+	}
+
+	fn handle(&self, req: &Request, mut res: Response) {
+		let (allowed, tokens) = self.take(self.client_key(req), Instant::now());
+		let rate = self.rate();
+
+		let reset = ((self.limit as f64 - tokens) / rate).ceil() as u64;
+		res.headers.set("RateLimit-Limit", &self.limit.to_string());
+		res.headers
+			.set("RateLimit-Remaining", &(tokens.floor() as u64).to_string());
+		res.headers.set("RateLimit-Reset", &reset.to_string());
+
+		if allowed {
+			self.inner.handle(req, res);
+			return;
+		}
+
+		let retry_after = std::cmp::max(((1.0 - tokens) / rate).ceil() as u64, 1);
+		res.headers.set("Retry-After", &retry_after.to_string());
+		let _ = res.send_error(&Error::new(429, "Rate limit exceeded"));
+	}
+
+	fn route(&self) -> Option<String> {
+		self.inner.route()
+	}
+}
+
+/// Number of buckets freed at least by each eviction, relative to the number of buckets kept, so the buckets are only
+/// scanned once for many new clients
+const EVICTION_SHARE: usize = 16;
+
+/// Drops buckets that have been refilled completely, as they are equal to new ones. If that does not free enough space,
+/// the buckets that were idle the longest are dropped.
+fn evict(buckets: &mut HashMap<String, Bucket>, capacity: f64, rate: f64, now: Instant) {
+	let keep = buckets
+		.len()
+		.saturating_sub(std::cmp::max(buckets.len() / EVICTION_SHARE, 1));
+	buckets.retain(|_, b| b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < capacity);
+	if buckets.len() <= keep {
+		return;
+	}
+
+	let excess = buckets.len() - keep;
+	let mut updated: Vec<Instant> = buckets.values().map(|b| b.updated).collect();
+	let (_, oldest, _) = updated.select_nth_unstable(excess - 1);
+	let oldest = *oldest;
+	buckets.retain(|_, b| b.updated > oldest);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn evicts_idle_buckets_in_batches() {
+		let start = Instant::now();
+		let mut buckets = HashMap::new();
+		for i in 0..64u64 {
+			let tokens = if i == 5 { 10.0 } else { 0.0 };
+			let updated = start + Duration::from_secs(i);
+			buckets.insert(i.to_string(), Bucket { tokens, updated });
+		}
+
+		// The full bucket alone does not free enough space, so the oldest ones follow
+		evict(&mut buckets, 10.0, 0.0, start + Duration::from_secs(64));
+		assert_eq!(buckets.len(), 60);
+		assert!(!buckets.contains_key("5"));
+		assert!(["0", "1", "2"].iter().all(|k| !buckets.contains_key(*k)));
+		assert!(buckets.contains_key("3"));
+
+		// Refilled buckets are dropped before any other
+		evict(&mut buckets, 10.0, 1.0, start + Duration::from_secs(74));
+		assert!(buckets.is_empty());
+	}
+}
@@ -1,11 +1,15 @@
+use super::body::{is_chunked, ChunkedReader, MAX_HEAD_SIZE};
+use super::negotiate;
 use super::util::{index_of, to_lines};
 use super::util::{CR, LF, SP};
 use super::Error;
 use super::ValuesMap;
-use crate::bin::Find;
 use crate::log_error;
 use std::io::prelude::*;
+use std::io::BufReader;
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 
 /// Incoming request
 pub struct Request {
@@ -18,12 +22,19 @@ pub struct Request {
 	/// HTTP Version string sent by the client
 	pub http_version: String,
 
-	stream: TcpStream,
+	/// The connection the request was read from, None for requests received as HTTP/2 streams, whose body is complete
+	stream: Option<TcpStream>,
 	header_length: usize,
 	body_length: usize,
 	read_bytes: usize,
 	body: Vec<u8>,
+	/// Whether body contains the complete decoded body
+	body_complete: bool,
+	/// Whether a reader already read the rest of the body from the connection
+	body_consumed: AtomicBool,
 	query_parameters: ValuesMap,
+	peer_addr: Option<std::net::SocketAddr>,
+	user: Mutex<Option<String>>,
 }
 
 impl Request {
@@ -42,67 +53,56 @@ impl Request {
 
 	fn parse_data(mut stream: TcpStream) -> Result<Request, Box<dyn std::error::Error>> {
 		let mut buffer = [0; 1024];
-		let mut in_header = true;
-		let mut header_bytes: Vec<u8> = Vec::with_capacity(1024);
-		let mut body: Vec<u8> = Vec::with_capacity(1024);
-		let mut header_length: usize = 0;
-		let mut read_bytes: usize = 0;
-		while in_header {
-			let read = stream.read(&mut buffer)?;
-			read_bytes += read;
-			let data = &buffer[0..read];
+		let mut data: Vec<u8> = Vec::with_capacity(1024);
+		while head_end(&data).is_none() {
+			if data.len() > MAX_HEAD_SIZE {
+				return Err(Error::boxed(431, "Request header too large"));
+			}
 
-			in_header = match data.index_of(&[CR, LF, CR, LF], 0) {
-				Ok(p) => {
-					header_bytes.extend(&data[0..p]);
-					header_length = p + 4;
+			let read = stream.read(&mut buffer)?;
+			if read == 0 {
+				return Err(Error::boxed(400, "Connection closed before end of header"));
+			}
+			data.extend_from_slice(&buffer[0..read]);
+		}
 
-					if header_length < read {
-						// Part of the body is already in the buffer
-						body.extend(&data[header_length..]);
-					}
+		Request::from_data(stream, data)
+	}
 
-					// End of header found
-					false
-				}
-				Err(_) => match data.index_of(&[LF, LF], 0) {
-					Ok(p) => {
-						header_bytes.extend(&data[0..p]);
-						header_length = p + 2;
-
-						if header_length < read {
-							// Part of the body is already in the buffer
-							body.extend(&data[header_length..]);
-						}
-
-						// End of header found
-						false
-					}
-					Err(_) => {
-						header_bytes.extend(data);
-						// Header end not yet found
-						true
-					}
-				},
-			};
+	/// Creates a new [Request] from data that was already read from the stream and contains at least the complete
+	/// header. Bytes after the header are treated as the start of the body
+	pub(crate) fn from_data(
+		stream: TcpStream,
+		data: Vec<u8>,
+	) -> Result<Request, Box<dyn std::error::Error>> {
+		let (head_length, header_length) = match head_end(&data) {
+			Some(end) => end,
+			None => return Err(Error::boxed(400, "Incomplete request header")),
+		};
+		let header_bytes = &data[0..head_length];
+		let body = Vec::from(&data[header_length..]);
+		let read_bytes = data.len();
 
-			// TODO: Set maximum number of bytes to read when looking for the end of the header
+		let mut header_lines = to_lines(header_bytes);
+		if header_lines.is_empty() {
+			return Err(Error::boxed(400, "Missing request line"));
 		}
 
-		let mut header_lines = to_lines(&header_bytes);
-
 		let first_line = header_lines.remove(0);
+		if first_line.split(|c| c == &SP).count() < 3 {
+			return Err(Error::boxed(400, "Invalid request line"));
+		}
 
 		let mut headers = ValuesMap::new();
 		headers.case_handling = true;
 		for line in header_lines {
 			match index_of(&line, ':' as u8) {
 				Some(i) => {
-					let key = std::str::from_utf8(&line[0..i - 1]).unwrap().trim();
-					let value = std::str::from_utf8(&line[i..]).unwrap().trim();
+					let key = std::str::from_utf8(&line[0..i])?.trim();
+					let value = std::str::from_utf8(&line[i + 1..])?.trim();
 					headers.add(key, value);
 				}
-				None => panic!("Invalid Request"),
+				None => return Err(Error::boxed(400, "Invalid header line")),
 			}
 		}
 
@@ -111,45 +111,247 @@ impl Request {
 
 		let query_parameters = uri_parameters(&uri);
 
-		// Find out if somethin from the
-		let body_length = match headers.get("Content-Length") {
-			Some(s) => s.parse().or::<usize>(Ok(0usize)).unwrap(),
-			None => 0usize,
-		};
+		let body_length = headers.content_length().unwrap_or(0) as usize;
 
 		// TODO: What about trailers?
 
 		Ok(Request {
-			stream,
+			peer_addr: stream.peer_addr().ok(),
+			user: Mutex::new(None),
+			stream: Some(stream),
 			method,
 			uri,
 			http_version,
 			headers,
 			header_length,
 			body,
+			body_complete: false,
+			body_consumed: AtomicBool::new(false),
 			read_bytes,
 			body_length,
 			query_parameters,
 		})
 	}
 
-	/// Populates/Reads the request body and then returns a reference to it
+	/// Creates a request whose header and body were already received, e.g. as an HTTP/2 stream. header_length is the
+	/// size of the header as received, it is only used for statistics
+	pub(crate) fn from_parts(
+		method: String,
+		uri: String,
+		http_version: &str,
+		headers: ValuesMap,
+		body: Vec<u8>,
+		header_length: usize,
+		peer_addr: Option<std::net::SocketAddr>,
+	) -> Request {
+		Request {
+			query_parameters: uri_parameters(&uri),
+			peer_addr,
+			user: Mutex::new(None),
+			stream: None,
+			method,
+			uri,
+			http_version: String::from(http_version),
+			headers,
+			header_length,
+			read_bytes: header_length + body.len(),
+			body_length: body.len(),
+			body,
+			body_complete: true,
+			body_consumed: AtomicBool::new(false),
+		}
+	}
+
+	/// Populates/Reads the request body and then returns a reference to it. Bodies sent with chunked transfer coding are
+	/// returned decoded. Fails if the client closes the connection before the body is complete or if the body was
+	/// already read with [Request::body_reader]
 	pub fn get_body(&mut self) -> Result<&Vec<u8>, Box<dyn std::error::Error>> {
-		while self.body_length > self.read_bytes - self.header_length {
-			// Not all data was read
-			let mut buffer = [0; 1024];
-			let read = self.stream.read(&mut buffer)?;
+		if !self.body_complete {
+			if *self.body_consumed.get_mut() {
+				return Err(Error::boxed(500, "The request body was already read"));
+			}
 
-			self.body.extend_from_slice(&buffer[0..read]);
-			self.read_bytes += read;
+			let mut body = Vec::new();
+			if let Err(e) = self.body_reader().read_to_end(&mut body) {
+				return Err(Error::boxed(400, e.to_string()));
+			}
+
+			self.read_bytes = self.header_length + body.len();
+			self.body_length = body.len();
+			self.body = body;
+			self.body_complete = true;
 		}
 
 		Ok(&self.body)
 	}
 
-	/// Returns a clone of the request TcpSstream or an error if cloning fails
+	/// Returns a reader over the request body, which reads the part of the body that was not received with the header
+	/// from the connection as needed, so large uploads can be streamed to their destination without buffering them in
+	/// memory. The body is delimited by the Content-Length header or decoded from chunked transfer coding. "Expect:
+	/// 100-continue" is answered before the first read from the connection. Reading fails with
+	/// [std::io::ErrorKind::UnexpectedEof] if the client closes the connection before the body is complete.
+	///
+	/// The body can only be read from the connection once, later readers return no data.
+	///
+	/// # Example
+	///
+	/// ```
+	/// use mi::http::*;
+	/// use std::sync::Arc;
+	///
+	/// let upload = ResultHandler::new(|r| r.method == methods::PUT, |req, res| {
+	///     let mut file = std::fs::File::create("/tmp/upload")?;
+	///     let size = std::io::copy(&mut req.body_reader(), &mut file)?;
+	///     res.status_code = 201;
+	///     res.w(format!("Received {} bytes", size));
+	///     Ok(())
+	/// });
+	///
+	/// let mut server = Server::new();
+	/// server.handler(Arc::new(upload));
+	/// ```
+	pub fn body_reader(&self) -> BodyReader<'_> {
+		let stream = match &self.stream {
+			Some(s) if !self.body_complete => s,
+			// Requests received as HTTP/2 streams always have a complete body
+			_ => {
+				return BodyReader {
+					inner: Box::new(&self.body[..]),
+				}
+			}
+		};
+		if self.body_consumed.swap(true, Ordering::SeqCst) {
+			return BodyReader {
+				inner: Box::new(std::io::empty()),
+			};
+		}
+
+		let rest = ConnectionBody {
+			stream,
+			send_continue: self
+				.headers
+				.get("Expect")
+				.is_some_and(|e| e.eq_ignore_ascii_case("100-continue")),
+		};
+
+		if is_chunked(&self.headers) {
+			return BodyReader {
+				inner: Box::new(ChunkedReader::new(BufReader::new(
+					(&self.body[..]).chain(rest),
+				))),
+			};
+		}
+
+		let buffered = std::cmp::min(self.body.len(), self.body_length);
+		BodyReader {
+			inner: Box::new(LengthReader {
+				inner: (&self.body[..buffered]).chain(rest),
+				remaining: self.body_length,
+			}),
+		}
+	}
+
+	/// Writes the request body to the given writer without buffering it in memory, see [Request::body_reader]. Returns
+	/// the number of bytes written
+	pub(crate) fn copy_body_to<W: Write>(&self, w: &mut W) -> Result<u64, std::io::Error> {
+		std::io::copy(&mut self.body_reader(), w)
+	}
+
+	/// Returns the size of the request head and the declared body in bytes
+	pub(crate) fn size(&self) -> u64 {
+		(self.header_length + self.body_length) as u64
+	}
+
+	/// Returns the name of the user the request was authenticated as, see [super::AuthHandler]
+	pub fn user(&self) -> Option<String> {
+		match self.user.lock() {
+			Ok(u) => u.clone(),
+			Err(e) => e.into_inner().clone(),
+		}
+	}
+
+	/// Sets the name of the authenticated user once [super::AuthHandler] verified the credentials
+	pub(crate) fn set_user(&self, user: &str) {
+		match self.user.lock() {
+			Ok(mut u) => *u = Some(String::from(user)),
+			Err(e) => *e.into_inner() = Some(String::from(user)),
+		}
+	}
+
+	/// Returns the address of the connected client
+	pub fn peer_addr(&self) -> Option<std::net::SocketAddr> {
+		self.peer_addr
+	}
+
+	/// Returns the bytes that were received after the header but not yet consumed as body
+	pub(crate) fn buffered_body(&self) -> &[u8] {
+		&self.body
+	}
+
+	/// Returns a clone of the request TcpSstream or an error if cloning fails. Fails for requests received over HTTP/2,
+	/// as their connection is shared with other requests
 	pub fn clone_stream(&self) -> Result<TcpStream, std::io::Error> {
-		self.stream.try_clone()
+		match &self.stream {
+			Some(s) => s.try_clone(),
+			None => Err(std::io::Error::new(
+				std::io::ErrorKind::Unsupported,
+				"HTTP/2 requests do not have a connection of their own",
+			)),
+		}
+	}
+
+	/// Returns the offered media type the client prefers according to its Accept header, or None if it accepts none of
+	/// them. Wildcards like "text/*" and q-values are taken into account, offers the client likes equally are chosen in
+	/// the given order. Without Accept header the first offer is returned. The response should list "Accept" in its
+	/// Vary header.
+	///
+	/// # Example
+	///
+	/// ```
+	/// use mi::http::*;
+	///
+	/// let handler = ResultHandler::new(|r| r.uri == "/status", |req, res| {
+	///     let offered = ["text/html", "application/json"];
+	///     match req.negotiate_type(&offered) {
+	///         Some("application/json") => {
+	///             res.headers.set("Content-Type", "application/json");
+	///             res.w("{\"status\":\"ok\"}");
+	///         }
+	///         Some(_) => {
+	///             res.headers.set("Content-Type", "text/html; charset=utf-8");
+	///             res.w("<p>OK</p>");
+	///         }
+	///         None => return Err(Error::not_acceptable(&offered)),
+	///     }
+	///     res.headers.add("Vary", "Accept");
+	///     Ok(())
+	/// });
+	/// ```
+	pub fn negotiate_type<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+		negotiate::media_type(self.header_list("Accept").as_deref(), offered)
+	}
+
+	/// Returns the offered language tag the client prefers according to its Accept-Language header, see
+	/// [Request::negotiate_type]. A range like "en" matches the offers "en" and "en-US"
+	pub fn negotiate_language<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+		negotiate::language(self.header_list("Accept-Language").as_deref(), offered)
+	}
+
+	/// Returns the offered charset the client prefers according to its Accept-Charset header, see
+	/// [Request::negotiate_type]
+	pub fn negotiate_charset<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+		negotiate::charset(self.header_list("Accept-Charset").as_deref(), offered)
+	}
+
+	/// Returns the offered content coding like "gzip" the client prefers according to its Accept-Encoding header, see
+	/// [Request::negotiate_type]. "identity" is acceptable unless the client excludes it explicitly
+	pub fn negotiate_encoding<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+		negotiate::encoding(self.header_list("Accept-Encoding").as_deref(), offered)
+	}
+
+	/// Returns the values of a header that may be sent several times as one comma separated list
+	fn header_list(&self, name: &str) -> Option<String> {
+		self.headers.get_all(name).map(|values| values.join(", "))
 	}
 
 	/// Returns the query parameters as a HashMap if string vectors
@@ -158,6 +360,73 @@ impl Request {
 	}
 }
 
+/// Reader over the body of a [Request], see [Request::body_reader]
+pub struct BodyReader<'a> {
+	inner: Box<dyn Read + 'a>,
+}
+
+impl Read for BodyReader<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+		self.inner.read(buf)
+	}
+}
+
+/// The part of a request body that was not received with the header
+struct ConnectionBody<'a> {
+	stream: &'a TcpStream,
+	/// Whether the client waits for "100 Continue" before sending the body
+	send_continue: bool,
+}
+
+impl Read for ConnectionBody<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+		if self.send_continue {
+			self.send_continue = false;
+			self.stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+		}
+		self.stream.read(buf)
+	}
+}
+
+/// Reads a body delimited by Content-Length, a connection that closes before is reported as error
+struct LengthReader<R: Read> {
+	inner: R,
+	remaining: usize,
+}
+
+impl<R: Read> Read for LengthReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+		if self.remaining == 0 || buf.is_empty() {
+			return Ok(0);
+		}
+
+		let max = std::cmp::min(buf.len(), self.remaining);
+		let read = self.inner.read(&mut buf[..max])?;
+		if read == 0 {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::UnexpectedEof,
+				"Connection closed before the request body was complete",
+			));
+		}
+
+		self.remaining -= read;
+		Ok(read)
+	}
+}
+
+/// Returns the length of the header without and with the empty line that ends it, if the data contains all of it. Lines
+/// may end with CRLF or just LF.
+pub(crate) fn head_end(data: &[u8]) -> Option<(usize, usize)> {
+	let crlf = data.windows(4).position(|w| w == [CR, LF, CR, LF]);
+	let lf = data.windows(2).position(|w| w == [LF, LF]);
+	match (crlf, lf) {
+		(Some(c), Some(l)) if l < c => Some((l, l + 2)),
+		(Some(c), _) => Some((c, c + 4)),
+		(None, Some(l)) => Some((l, l + 2)),
+		(None, None) => None,
+	}
+}
+
 pub fn split_request_line(line: &[u8]) -> (String, String, String) {
 	let mut split = line
 		.splitn(3, |c| c == &SP)
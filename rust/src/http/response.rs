@@ -1,10 +1,46 @@
-use super::util::lookup_status_str;
+use super::http2::StreamWriter;
 use super::util::CRLF;
+use super::util::{lookup_status_str, unix_time};
 use super::ValuesMap;
 use std::io::prelude::*;
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
+/// Status code and number of body bytes of a [Response] as sent to the client. Shared with the server, which reads it
+/// after the handler returned.
+#[derive(Debug, Default)]
+pub(crate) struct ResponseStats {
+	status_code: AtomicU16,
+	head_bytes: AtomicU64,
+	bytes_sent: AtomicU64,
+}
+
+impl ResponseStats {
+	/// Returns the status code that was sent, 0 if the headers were never sent
+	pub(crate) fn status_code(&self) -> u16 {
+		self.status_code.load(Ordering::Relaxed)
+	}
+
+	/// Returns the number of body bytes that were sent
+	pub(crate) fn bytes_sent(&self) -> u64 {
+		self.bytes_sent.load(Ordering::Relaxed)
+	}
+
+	/// Returns the number of bytes of the status line and headers that were sent
+	pub(crate) fn head_bytes(&self) -> u64 {
+		self.head_bytes.load(Ordering::Relaxed)
+	}
+}
+
+/// Where a [Response] is written to
+enum Output {
+	/// An HTTP/1.1 connection that is used for this response only
+	Tcp(TcpStream),
+	/// A stream of an HTTP/2 connection
+	Http2(StreamWriter),
+}
+
 /// Outgoing response to an incoming [super::Request]
 /// TODO: Chunked encoding is currently not supported
 pub struct Response {
@@ -15,15 +51,19 @@ pub struct Response {
 	/// The status string to send along the status code
 	pub status: &'static str,
 
-	stream: TcpStream,
+	output: Output,
 	body: Vec<u8>,
 	header_sent: bool,
 	closed: bool,
+	head_only: bool,
+	stats: Arc<ResponseStats>,
 
 	log_error: Arc<Mutex<dyn Write + Send>>,
 
 	request_method: String,
 	request_uri: String,
+	request_accept: String,
+	error_renderer: Option<super::ErrorRenderer>,
 }
 
 impl Response {
@@ -32,17 +72,38 @@ impl Response {
 		stream: TcpStream,
 		req: &super::Request,
 		log_error: Arc<Mutex<dyn Write + Send>>,
+	) -> Response {
+		Response::with_output(Output::Tcp(stream), req, log_error)
+	}
+
+	/// Creates a new Response that is sent on the given HTTP/2 stream
+	pub(crate) fn new_for_http2(
+		stream: StreamWriter,
+		req: &super::Request,
+		log_error: Arc<Mutex<dyn Write + Send>>,
+	) -> Response {
+		Response::with_output(Output::Http2(stream), req, log_error)
+	}
+
+	fn with_output(
+		output: Output,
+		req: &super::Request,
+		log_error: Arc<Mutex<dyn Write + Send>>,
 	) -> Response {
 		Response {
-			stream,
+			output,
 			request_method: req.method.clone(),
 			request_uri: req.uri.clone(),
+			request_accept: String::from(req.headers.get("Accept").unwrap_or("")),
+			error_renderer: None,
 			headers: ValuesMap::new(),
 			body: Vec::new(),
 			status: "",
 			status_code: 200,
 			closed: false,
 			header_sent: false,
+			head_only: req.method == super::methods::HEAD,
+			stats: Arc::new(ResponseStats::default()),
 			log_error,
 		}
 	}
@@ -78,28 +139,47 @@ impl Response {
 		Ok(data.as_ref().len())
 	}
 
+	/// Returns the number of body bytes that were sent to the client so far, not counting buffered data
+	pub fn bytes_sent(&self) -> u64 {
+		self.stats.bytes_sent()
+	}
+
+	/// Returns the shared statistics of this response
+	pub(crate) fn stats(&self) -> Arc<ResponseStats> {
+		self.stats.clone()
+	}
+
 	/// Clears the currently buffered body content that was adde since the last send
 	pub fn clear(&mut self) {
 		self.body.clear();
 	}
 
 	/// Sends the headers and all currently available data in the body to the client without closing the connection.
+	/// For HEAD requests only the headers are sent and the body is discarded.
 	pub fn send(&mut self) -> Result<(), std::io::Error> {
 		if !self.header_sent {
 			self.send_headers()?;
 		}
 
-		self.stream.write(&self.body)?;
+		self.send_body()
+	}
+
+	fn send_body(&mut self) -> Result<(), std::io::Error> {
+		if !self.head_only {
+			match &mut self.output {
+				Output::Tcp(s) => s.write_all(&self.body)?,
+				Output::Http2(s) => s.send_data(&self.body, false)?,
+			}
+			self.stats
+				.bytes_sent
+				.fetch_add(self.body.len() as u64, Ordering::Relaxed);
+		}
 		self.body.clear();
 
 		Ok(())
 	}
 
 	fn send_headers(&mut self) -> Result<(), std::io::Error> {
-		let mut head: Vec<u8> = Vec::new();
-		head.extend("HTTP/1.1 ".as_bytes());
-		head.extend(format!("{} ", self.status_code).as_bytes());
-
 		if self.status.len() == 0 {
 			// Use Default status string if none is set
 			self.status = lookup_status_str(self.status_code);
@@ -116,25 +196,123 @@ impl Response {
 			);
 		}
 
-		head.extend(self.status.as_bytes());
-		head.extend(CRLF);
+		// HTTP/1.1 requires a Date header in every response from a server with a clock
+		if !self
+			.headers
+			.all()
+			.keys()
+			.any(|k| k.eq_ignore_ascii_case("Date"))
+		{
+			self.headers.set_http_date("Date", unix_time());
+		}
+
+		let head_bytes = match &mut self.output {
+			Output::Tcp(s) => {
+				let mut head: Vec<u8> = Vec::new();
+				head.extend("HTTP/1.1 ".as_bytes());
+				head.extend(format!("{} ", self.status_code).as_bytes());
+				head.extend(self.status.as_bytes());
+				head.extend(CRLF);
+
+				let mut connection_set = false;
+				for (k, vs) in self.headers.all() {
+					connection_set |= k.eq_ignore_ascii_case("Connection");
+					for v in vs {
+						head.extend(k.as_bytes());
+						head.extend(": ".as_bytes());
+						head.extend(v.as_bytes());
+						head.extend(&CRLF);
+					}
+				}
+				// The connection is closed after every response, tell clients not to reuse it
+				if !connection_set {
+					head.extend("Connection: close".as_bytes());
+					head.extend(CRLF);
+				}
+
+				head.extend(CRLF);
+
+				s.write_all(&head)?;
+				head.len() as u64
+			}
+			Output::Http2(s) => s.send_headers(self.status_code, &self.headers)?,
+		};
+
+		self.header_sent = true;
+		self.stats
+			.status_code
+			.store(self.status_code, Ordering::Relaxed);
+		self.stats.head_bytes.store(head_bytes, Ordering::Relaxed);
+
+		self.send_body()
+	}
+
+	/// Sets the function used by [Response::send_error] to render error responses
+	pub(crate) fn set_error_renderer(&mut self, renderer: super::ErrorRenderer) {
+		self.error_renderer = Some(renderer);
+	}
 
-		for (k, vs) in self.headers.all() {
-			for v in vs {
-				head.extend(k.as_bytes());
-				head.extend(": ".as_bytes());
-				head.extend(v.as_bytes());
-				head.extend(&CRLF);
+	/// Limits how long writing the response may wait for a client that does not read it
+	pub(crate) fn set_write_timeout(
+		&mut self,
+		timeout: Option<std::time::Duration>,
+	) -> Result<(), std::io::Error> {
+		match &mut self.output {
+			Output::Tcp(s) => s.set_write_timeout(timeout),
+			Output::Http2(s) => {
+				s.write_timeout = timeout;
+				Ok(())
 			}
 		}
+	}
 
-		self.stream.write(&head)?;
-		self.stream.write(&CRLF)?;
+	/// Replaces the response with one for the given error, rendered by the server's error renderer, and ends it. Headers
+	/// that were already set are kept, except for Content-Type. Fails if the headers were already sent.
+	pub fn send_error(&mut self, error: &super::Error) -> Result<(), std::io::Error> {
+		if self.closed || self.header_sent {
+			return Err(std::io::Error::other(
+				"Cannot send an error for a response that was already sent",
+			));
+		}
 
-		self.stream.write(&self.body)?;
-		self.body.clear();
+		self.status_code = error.code;
+		self.status = error.status;
+		self.headers.remove("Content-Type");
+		self.clear();
 
-		Ok(())
+		let accept = self.request_accept.clone();
+		match self.error_renderer.clone() {
+			Some(render) => render(error, &accept, self),
+			None => super::render_error(error, &accept, self),
+		}
+
+		self.end()
+	}
+
+	/// Sends the headers and hands the connection over to the caller, e.g. after switching protocols. The response is
+	/// closed afterwards and the connection is no longer ended when the response is dropped.
+	/// Fails for HTTP/2 requests, as their connection is shared with other requests.
+	pub fn upgrade(mut self) -> Result<TcpStream, std::io::Error> {
+		if self.closed || self.header_sent {
+			return Err(std::io::Error::other(
+				"Cannot upgrade a response that was already sent",
+			));
+		}
+		let mut stream = match &self.output {
+			Output::Tcp(s) => s.try_clone()?,
+			Output::Http2(_) => {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::Unsupported,
+					"Cannot upgrade an HTTP/2 stream",
+				))
+			}
+		};
+
+		self.send_headers()?;
+		self.closed = true;
+
+		stream.flush()?;
+		Ok(stream)
 	}
 
 	/// Send all remaining data and closes the connection.
@@ -161,19 +339,70 @@ impl Response {
 			self.send_headers()?;
 		}
 
-		self.stream.write(&self.body)?;
+		self.send_body()?;
 
-		self.stream.flush()?;
-		self.stream.shutdown(std::net::Shutdown::Both)?;
+		match &mut self.output {
+			Output::Tcp(s) => {
+				s.flush()?;
+				s.shutdown(std::net::Shutdown::Both)?;
+			}
+			Output::Http2(s) => s.finish()?,
+		}
 
 		Ok(())
 	}
+
+	/// Closes the connection without sending a response, HTTP/2 streams are refused so the client may retry them
+	pub(crate) fn refuse(mut self) {
+		self.closed = true;
+		match &self.output {
+			Output::Tcp(s) => {
+				let _ = s.shutdown(std::net::Shutdown::Both);
+			}
+			Output::Http2(s) => s.refuse(),
+		}
+	}
+
+	/// Ends a response that cannot be completed, e.g. because its handler panicked. If nothing was sent yet the response
+	/// is replaced by a 500 error, otherwise the connection is reset without sending the rest, so the client can tell
+	/// the response is incomplete.
+	pub(crate) fn abort(&mut self) {
+		if self.header_sent {
+			self.closed = true;
+			match &self.output {
+				Output::Tcp(s) => {
+					// A regular end of the stream would make a body without Content-Length look complete
+					if !super::util::reset_on_close(s) {
+						let _ = s.shutdown(std::net::Shutdown::Both);
+					}
+				}
+				Output::Http2(s) => s.reset(),
+			}
+			return;
+		}
+
+		// The headers set by the handler may not fit the error, and the server's renderer is not called as it could
+		// panic again while unwinding
+		self.headers = ValuesMap::new();
+		self.status_code = 500;
+		self.status = lookup_status_str(500);
+		self.clear();
+		let accept = self.request_accept.clone();
+		super::render_error(&super::Error::new(500, ""), &accept, self);
+		let _ = self.end();
+	}
 }
 
 impl Drop for Response {
 	/// Automatically end the response when is is dropped
 	fn drop(&mut self) {
-		if !self.closed {
+		if self.closed {
+			return;
+		}
+
+		if std::thread::panicking() {
+			self.abort();
+		} else {
 			match self.end() {
 				Err(e) => {
 					let mut g = self.log_error.lock().unwrap();
@@ -1,10 +1,19 @@
-use super::util::log;
+use super::accesslog::LogEntry;
+#[cfg(target_os = "linux")]
+use super::eventloop::{EventLoop, Incoming};
+use super::http2;
+use super::metrics::Gauge;
+use super::util::{log, unix_time};
+use super::Metrics;
 use crate::log_info;
+use std::any::Any;
 use std::io::prelude::*;
 use std::net::SocketAddr;
 use std::net::TcpListener;
 use std::net::TcpStream;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use threadpool::ThreadPool;
 
 /// A simple HTTP Server. Dispatches incoming requests to the given handler functions based on their associated matchers.
@@ -47,12 +56,48 @@ pub struct Server {
 	pub num_threads: usize,
 	/// Timeout duration for reading from incoming connections
 	pub read_timeout: Option<std::time::Duration>,
-	/// Writer to which to log read access. Defaults to ignored
+	/// Whether to accept HTTP/2 over cleartext connections, from clients with prior knowledge and via "Upgrade: h2c".
+	/// Request bodies of HTTP/2 streams are received completely before the handler is called. Handlers that take over
+	/// the connection, like [super::WebSocket] and [super::EventStream], fail for HTTP/2 streams. Defaults to true
+	pub http2: bool,
+	/// Whether to wait for requests with a single epoll based event loop thread instead of reading them one after
+	/// another. Clients that are slow to send their request then do not hold up the requests of others, connections are
+	/// still closed after each response. Connections count toward [Server::max_connections] once their request arrived,
+	/// until then they are limited by [Server::max_waiting_connections]. Only supported on Linux, ignored elsewhere.
+	/// Defaults to false
+	pub event_driven: bool,
+	/// Maximum number of connections the event loop holds while waiting for their request, see [Server::event_driven].
+	/// When it is reached, the connection that has been waiting the longest is closed to make room for a new one. Each
+	/// connection takes a file descriptor, so the process limit may need to be raised as well. Defaults to 4096
+	pub max_waiting_connections: Option<usize>,
+	/// Timeout duration for writing responses to clients that do not read them
+	pub write_timeout: Option<std::time::Duration>,
+	/// Maximum number of open connections. Further requests are answered with 503 Service Unavailable. Defaults to 1024
+	pub max_connections: Option<usize>,
+	/// Maximum number of requests waiting for a free thread. Further requests are answered with 503 Service Unavailable.
+	/// Defaults to 256
+	pub max_queued: Option<usize>,
+	/// Maximum number of HTTP/2 connections, each of which is served by a thread of its own. Further upgrade requests
+	/// are answered with HTTP/1.1, further clients with prior knowledge are refused. Defaults to 128
+	pub max_http2_connections: Option<usize>,
+	/// How long clients are asked to wait before retrying a request that was rejected because of the limits above.
+	/// Defaults to 1 second
+	pub retry_after: std::time::Duration,
+	/// Writer to which to log completed requests. Defaults to ignored
 	pub log_access: Arc<Mutex<dyn Write + Send>>,
+	/// Format of the lines written to log_access. Defaults to the Common Log Format
+	pub access_log_format: super::AccessLogFormat,
 	/// Writer to which to log errors. Defaults to stderr
 	pub log_errors: Arc<Mutex<dyn Write + Send>>,
+	/// Renders the bodies of error responses sent with [super::Response::send_error]. Defaults to
+	/// [super::render_error]
+	pub error_renderer: super::ErrorRenderer,
+	/// Value of the Server header added to every response, handlers may replace or remove it. None adds no Server
+	/// header. Defaults to "mi"
+	pub server_header: Option<String>,
 	running: bool,
 	handlers: Vec<Arc<dyn super::RequestHandler>>,
+	metrics: Arc<Metrics>,
 }
 
 impl Server {
@@ -61,13 +106,30 @@ impl Server {
 		Server {
 			num_threads: num_cpus::get(),
 			read_timeout: Some(std::time::Duration::new(30, 0)),
+			http2: true,
+			event_driven: false,
+			max_waiting_connections: Some(4096),
+			write_timeout: Some(std::time::Duration::new(30, 0)),
+			max_connections: Some(1024),
+			max_queued: Some(256),
+			max_http2_connections: Some(128),
+			retry_after: std::time::Duration::new(1, 0),
 			log_access: Arc::new(Mutex::new(std::io::sink())),
+			access_log_format: super::AccessLogFormat::default(),
 			log_errors: Arc::new(Mutex::new(std::io::stderr())),
+			error_renderer: Arc::new(super::render_error),
+			server_header: Some(String::from("mi")),
 			running: true,
 			handlers: Vec::new(),
+			metrics: Arc::new(Metrics::new()),
 		}
 	}
 
+	/// Returns the metrics collected by this server, which can be served with a [super::MetricsHandler]
+	pub fn metrics(&self) -> Arc<Metrics> {
+		self.metrics.clone()
+	}
+
 	/// Adds a handler function to the server along with a matcher function. A [super::RequestHandler] is created and then
 	/// added via [Server.handler].
 	/// The matcher function is used to check if it matches the request in the order they were added to the server.
@@ -111,6 +173,28 @@ impl Server {
 		self.handler(Arc::new(super::Handler::new(matcher_fn, handler_fn)));
 	}
 
+	/// Adds a handler function that returns errors to the server along with a matcher function. A
+	/// [super::ResultHandler] is created and then added via [Server.handler].
+	///
+	/// # Example
+	///
+	/// ```
+	/// use mi::http::*;
+	/// let mut server = Server::new();
+	///
+	/// server.handle_result(|r| r.uri == "/config", |_, res| {
+	///     res.w(std::fs::read_to_string("config.json")?);
+	///     Ok(())
+	/// });
+	/// ```
+	pub fn handle_result(
+		&mut self,
+		matcher_fn: fn(req: &super::Request) -> bool,
+		handler_fn: fn(&super::Request, &mut super::Response) -> Result<(), super::Error>,
+	) {
+		self.handler(Arc::new(super::ResultHandler::new(matcher_fn, handler_fn)));
+	}
+
 	/// Adds a [super::RequestHandler] to the server.
 	pub fn handler(&mut self, handler: Arc<dyn super::RequestHandler>) {
 		self.handlers.push(handler);
@@ -119,8 +203,19 @@ impl Server {
 	/// Opens the given port for listening to incoming connections. Returns an error if the port cannot be opened.
 	pub fn listen(&mut self, port: u16) -> Result<(), Box<dyn std::error::Error>> {
 		let pool = ThreadPool::new(self.num_threads);
+		self.metrics.set_pool(&pool);
 		let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port)))?;
 
+		let dispatcher = self.dispatcher(&pool);
+
+		#[cfg(target_os = "linux")]
+		if self.event_driven {
+			self.run_event_loop(&dispatcher, listener)?;
+			pool.join();
+			dispatcher.rejections.join();
+			return Ok(());
+		}
+
 		for st in listener.incoming() {
 			let stream = match st {
 				Ok(s) => s,
@@ -133,7 +228,7 @@ impl Server {
 				}
 			};
 
-			self.handle_connection(&pool, stream);
+			self.handle_connection(&dispatcher, stream);
 
 			if !self.running {
 				log_info!("Stopping Server");
@@ -142,11 +237,32 @@ impl Server {
 		}
 
 		pool.join();
+		dispatcher.rejections.join();
 
 		Ok(())
 	}
 
-	fn handle_connection(&mut self, pool: &ThreadPool, stream: TcpStream) {
+	/// Returns the [Dispatcher] that hands requests to the handlers of this server using the given pool
+	fn dispatcher(&self, pool: &ThreadPool) -> Dispatcher {
+		Dispatcher {
+			handlers: self.handlers.clone(),
+			pool: pool.clone(),
+			rejections: ThreadPool::new(1),
+			metrics: self.metrics.clone(),
+			log_access: self.log_access.clone(),
+			access_log_format: self.access_log_format.clone(),
+			log_errors: self.log_errors.clone(),
+			error_renderer: self.error_renderer.clone(),
+			server_header: self.server_header.clone(),
+			max_connections: self.max_connections,
+			max_queued: self.max_queued,
+			max_http2_connections: self.max_http2_connections,
+			retry_after: self.retry_after,
+			http2: self.http2,
+		}
+	}
+
+	fn set_timeouts(&self, stream: &TcpStream) {
 		match stream.set_read_timeout(self.read_timeout) {
 			Ok(()) => (),
 			Err(e) => log(
@@ -154,7 +270,21 @@ impl Server {
 				format!("Error setting read timeout: {}", e),
 			),
 		};
+		match stream.set_write_timeout(self.write_timeout) {
+			Ok(()) => (),
+			Err(e) => log(
+				&self.log_errors,
+				format!("Error setting write timeout: {}", e),
+			),
+		};
+	}
+
+	fn handle_connection(&mut self, dispatcher: &Dispatcher, stream: TcpStream) {
+		let received = Instant::now();
+		let time = unix_time();
+		let connection = self.metrics.connection();
 
+		self.set_timeouts(&stream);
 		let r = super::Request::from(stream);
 
 		if !r.is_ok() {
@@ -166,19 +296,171 @@ impl Server {
 		}
 
 		let req = r.unwrap();
+		dispatcher.handle(req, received, time, connection, false);
+	}
 
-		log(&self.log_access, format!("{} {}", req.method, req.uri));
+	/// Waits for requests with an [EventLoop] instead of reading them one after another
+	#[cfg(target_os = "linux")]
+	fn run_event_loop(
+		&self,
+		dispatcher: &Dispatcher,
+		listener: TcpListener,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let mut event_loop = EventLoop::new(
+			listener,
+			self.metrics.clone(),
+			self.log_errors.clone(),
+			self.read_timeout,
+			self.max_waiting_connections,
+		)?;
 
-		// Find longest matching handler
-		let mut matched_handler: Option<Arc<dyn super::RequestHandler>> = None;
-		for i in 0..self.handlers.len() {
-			let matched = self.handlers[i].matches(&req);
-			if matched {
-				matched_handler = Some(self.handlers[i].clone());
+		loop {
+			for incoming in event_loop.poll(std::time::Duration::from_secs(1))? {
+				if let Err(e) = incoming.stream.set_nonblocking(false) {
+					log(
+						&self.log_errors,
+						format!("Error switching connection to blocking mode: {}", e),
+					);
+					continue;
+				}
+				self.set_timeouts(&incoming.stream);
+				dispatcher.handle_incoming(incoming);
+			}
+
+			if !self.running {
+				log_info!("Stopping Server");
 				break;
 			}
 		}
 
+		Ok(())
+	}
+}
+
+/// How long sending a rejection may take, so clients that do not read it cannot hold up the rejection of others
+const REJECTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Maximum number of rejections waiting to be sent. Further connections are closed without a response, so a flood of
+/// connections does not pile up in the queue of rejections instead
+const MAX_REJECTIONS: usize = 64;
+
+/// Hands requests to the matching handler of a [Server] and logs them once they are handled. It can be cloned to be
+/// used by other threads, e.g. for the streams of HTTP/2 connections
+#[derive(Clone)]
+pub(crate) struct Dispatcher {
+	handlers: Vec<Arc<dyn super::RequestHandler>>,
+	pool: ThreadPool,
+	/// Sends the responses to requests that were rejected because of the limits
+	rejections: ThreadPool,
+	metrics: Arc<Metrics>,
+	log_access: Arc<Mutex<dyn Write + Send>>,
+	access_log_format: super::AccessLogFormat,
+	pub(crate) log_errors: Arc<Mutex<dyn Write + Send>>,
+	pub(crate) error_renderer: super::ErrorRenderer,
+	server_header: Option<String>,
+	max_connections: Option<usize>,
+	max_queued: Option<usize>,
+	max_http2_connections: Option<usize>,
+	retry_after: std::time::Duration,
+	http2: bool,
+}
+
+impl Dispatcher {
+	/// Returns true if the connection limit or the request queue limit is reached. The connection being handled is
+	/// already counted
+	fn is_overloaded(&self) -> bool {
+		if let Some(max) = self.max_connections {
+			if self.metrics.connections() > max as i64 {
+				return true;
+			}
+		}
+		if let Some(max) = self.max_queued {
+			if self.pool.queued_count() >= max {
+				return true;
+			}
+		}
+		false
+	}
+
+	/// Returns true if so many rejections are waiting to be sent that connections are closed without a response
+	fn rejections_full(&self) -> bool {
+		self.rejections.queued_count() >= MAX_REJECTIONS
+	}
+
+	/// Parses a request the event loop received completely and handles it on a thread of the pool, so the event loop
+	/// never waits for a client. If the server is overloaded, the request is rejected without waiting for the pool
+	#[cfg(target_os = "linux")]
+	pub(crate) fn handle_incoming(&self, incoming: Incoming) {
+		let overloaded = self.is_overloaded();
+		let dispatcher = self.clone();
+		let job = move || match super::Request::from_data(incoming.stream, incoming.data) {
+			Ok(req) => dispatcher.handle(
+				req,
+				incoming.received,
+				incoming.time,
+				incoming.connection,
+				!overloaded,
+			),
+			Err(e) => log(&dispatcher.log_errors, format!("x: Invalid Request: {}", e)),
+		};
+
+		if overloaded {
+			// Dropping the job closes the connection
+			if !self.rejections_full() {
+				self.rejections.execute(job);
+			}
+		} else {
+			self.pool.execute(job);
+		}
+	}
+
+	/// Hands a request read from a connection to the matching handler, or switches the connection to HTTP/2 if the
+	/// limits allow it. If called on a thread of the pool, the handler runs right away, otherwise it is queued
+	pub(crate) fn handle(
+		&self,
+		req: super::Request,
+		received: Instant,
+		time: u64,
+		connection: Gauge,
+		on_pool: bool,
+	) {
+		if self.http2 && (http2::is_prior_knowledge(&req) || http2::is_upgrade(&req)) {
+			let http2_connection = self.metrics.http2_connection();
+			let available = self
+				.max_http2_connections
+				.is_none_or(|max| self.metrics.http2_connections() <= max as i64);
+			if available && !self.is_overloaded() {
+				if let Err(e) = http2::serve(
+					req,
+					received,
+					time,
+					self.clone(),
+					connection,
+					http2_connection,
+				) {
+					log(
+						&self.log_errors,
+						format!("Could not switch to HTTP/2: {}", e),
+					);
+				}
+				return;
+			}
+			drop(http2_connection);
+
+			if http2::is_prior_knowledge(&req) {
+				if self.rejections_full() {
+					return;
+				}
+				// The connection preface cannot be answered with HTTP/1.1
+				self.rejections.execute(move || {
+					http2::refuse(&req, REJECTION_TIMEOUT);
+					drop(connection);
+				});
+				return;
+			}
+			// Upgrade requests are answered with HTTP/1.1 instead, as if the server did not support HTTP/2
+		}
+
 		let response_stream = match req.clone_stream() {
 			Ok(s) => s,
 			Err(e) => {
@@ -191,12 +473,198 @@ impl Server {
 		};
 
 		let res = super::Response::new_for(response_stream, &req, self.log_errors.clone());
+		if on_pool {
+			self.job(req, res, received, time, Some(connection))();
+		} else {
+			self.dispatch(req, res, received, time, Some(connection));
+		}
+	}
 
-		if matched_handler.is_some() {
-			let handler = matched_handler.unwrap();
-			pool.execute(move || handler.handle(&req, res));
+	/// Hands the request to the matching handler on a thread of the pool, or rejects it if the server is overloaded.
+	/// The connection gauge is released once the request is handled, if the connection is used for this request only
+	pub(crate) fn dispatch(
+		&self,
+		req: super::Request,
+		res: super::Response,
+		received: Instant,
+		time: u64,
+		connection: Option<Gauge>,
+	) {
+		if self.is_overloaded() {
+			self.reject(req, res, received, time, connection);
 		} else {
-			super::util::DEFAULT_HANDLER(&req, res);
+			self.pool
+				.execute(self.job(req, res, received, time, connection));
 		}
 	}
+
+	/// Answers the request with the given error on a thread of the pool instead of passing it to a handler, or rejects
+	/// it if the server is overloaded
+	pub(crate) fn dispatch_error(
+		&self,
+		req: super::Request,
+		mut res: super::Response,
+		received: Instant,
+		time: u64,
+		error: super::Error,
+	) {
+		if self.is_overloaded() {
+			self.reject(req, res, received, time, None);
+			return;
+		}
+
+		let (_, route) = self.route(&req);
+		let write_log = self.prepare(&mut res, route, received, time, None);
+		self.pool.execute(move || {
+			let _ = res.send_error(&error);
+			drop(res);
+			write_log(&req);
+		});
+	}
+
+	/// Returns the first handler matching the request and the route it is counted under, None if no handler matches
+	fn route(&self, req: &super::Request) -> (Option<Arc<dyn super::RequestHandler>>, String) {
+		for i in 0..self.handlers.len() {
+			if self.handlers[i].matches(req) {
+				let route = self.handlers[i]
+					.route()
+					.unwrap_or_else(|| format!("handler_{}", i));
+				return (Some(self.handlers[i].clone()), route);
+			}
+		}
+		(None, String::from("none"))
+	}
+
+	/// Sets the headers every response gets and returns the function that records the request once it is handled
+	fn prepare(
+		&self,
+		res: &mut super::Response,
+		route: String,
+		received: Instant,
+		time: u64,
+		connection: Option<Gauge>,
+	) -> impl FnOnce(&super::Request) + Send + 'static {
+		res.set_error_renderer(self.error_renderer.clone());
+		if let Some(server) = &self.server_header {
+			res.headers.set("Server", server);
+		}
+		let stats = res.stats();
+		let log_access = self.log_access.clone();
+		let format = self.access_log_format.clone();
+		let metrics = self.metrics.clone();
+		let in_flight = self.metrics.in_flight();
+		move |req: &super::Request| {
+			let duration = received.elapsed();
+			metrics.record(
+				&req.method,
+				&route,
+				stats.status_code(),
+				duration,
+				req.size(),
+				stats.head_bytes() + stats.bytes_sent(),
+			);
+			drop(in_flight);
+			drop(connection);
+
+			let entry = LogEntry {
+				req,
+				stats: &stats,
+				time,
+				duration,
+			};
+			write_line(&log_access, entry.format(&format));
+		}
+	}
+
+	/// Returns a job that runs the matching handler, or the default handler if none matches, and records the request
+	fn job(
+		&self,
+		req: super::Request,
+		mut res: super::Response,
+		received: Instant,
+		time: u64,
+		connection: Option<Gauge>,
+	) -> impl FnOnce() + Send + 'static {
+		let (handler, route) = self.route(&req);
+		let write_log = self.prepare(&mut res, route, received, time, connection);
+		let log_errors = self.log_errors.clone();
+		let metrics = self.metrics.clone();
+
+		move || {
+			match handler {
+				Some(handler) => {
+					// A panic drops the response while unwinding, which sends a 500 error or cuts off a started
+					// response
+					let result =
+						panic::catch_unwind(AssertUnwindSafe(|| handler.handle(&req, res)));
+					if let Err(payload) = result {
+						metrics.record_panic();
+						log(
+							&log_errors,
+							format!(
+								"Handler panicked for {} {} {}: {}",
+								req.method,
+								req.uri,
+								req.http_version,
+								panic_message(payload.as_ref())
+							),
+						);
+					}
+				}
+				None => super::util::DEFAULT_HANDLER(&req, res),
+			}
+			write_log(&req);
+		}
+	}
+
+	/// Answers the request with 503 Service Unavailable. The response is sent by a thread of its own that waits for
+	/// each client only briefly, so neither the accepting thread nor the pool is held up by clients that do not read.
+	/// If too many rejections are waiting already, the connection is closed right away
+	fn reject(
+		&self,
+		req: super::Request,
+		mut res: super::Response,
+		received: Instant,
+		time: u64,
+		connection: Option<Gauge>,
+	) {
+		if self.rejections_full() {
+			res.refuse();
+			return;
+		}
+
+		let (_, route) = self.route(&req);
+		let write_log = self.prepare(&mut res, route, received, time, connection);
+		let retry_after = std::cmp::max(self.retry_after.as_secs(), 1).to_string();
+
+		self.rejections.execute(move || {
+			// Answering right away lets clients back off instead of waiting for their turn until they time out
+			let _ = res.set_write_timeout(Some(REJECTION_TIMEOUT));
+			res.headers.set("Retry-After", &retry_after);
+			let _ = res.send_error(&super::Error::new(
+				503,
+				"Too many requests, try again later",
+			));
+			drop(res);
+			write_log(&req);
+		});
+	}
+}
+
+/// Returns the message a panic was started with
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+	if let Some(s) = payload.downcast_ref::<&str>() {
+		s
+	} else if let Some(s) = payload.downcast_ref::<String>() {
+		s
+	} else {
+		"unknown panic payload"
+	}
+}
+
+/// Writes a single line to the given log without timestamp prefix
+fn write_line(w: &Arc<Mutex<dyn Write + Send>>, line: String) {
+	if let Ok(mut g) = w.lock() {
+		let _ = writeln!(g, "{}", line);
+	}
 }
@@ -5,4 +5,10 @@ pub trait RequestHandler: Sync + Send {
 
 	/// Is called for the given request if it is the first Requesthandler that matches the request
 	fn handle(&self, req: &super::Request, res: super::Response);
+
+	/// Returns a short name for the requests this handler answers, like its URI prefix. Used to label metrics, so it
+	/// must not depend on the individual request
+	fn route(&self) -> Option<String> {
+		None
+	}
 }
@@ -11,11 +11,7 @@ pub const CRLF: [u8; 2] = [CR, LF];
 
 pub const DEFAULT_HANDLER: fn(req: &super::Request, res: super::Response) =
 	|req: &super::Request, mut res: super::Response| {
-		res.status_code = 404;
-		res.headers.set("Content-Type", "text/html");
-		let _ = res.write("Not found: ");
-		let _ = res.write(&req.uri);
-		match res.end() {
+		match res.send_error(&super::Error::new(404, format!("Not found: {}", req.uri))) {
 			Ok(_) => {}
 			Err(e) => log_error!("Error writing to response in default handler: {}", e),
 		};
@@ -36,6 +32,10 @@ pub fn to_lines(data: &[u8]) -> Vec<Vec<u8>> {
 		current_line.push(data[i]);
 	}
 
+	if !current_line.is_empty() {
+		lines.push(current_line);
+	}
+
 	lines
 }
 
@@ -137,3 +137,241 @@ pub fn log<S: AsRef<str>>(
 		Err(_) => {}
 	}
 }
+
+/// Decodes percent-encoded octets in the given string. Returns None if an escape sequence is malformed or the decoded
+/// bytes are not valid UTF-8.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::percent_decode;
+/// assert_eq!(percent_decode("/a%20b/%2e%2e").unwrap(), "/a b/..");
+/// assert_eq!(percent_decode("/%zz"), None);
+/// ```
+pub fn percent_decode(s: &str) -> Option<String> {
+	let bytes = s.as_bytes();
+	let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+
+	let mut i = 0;
+	while i < bytes.len() {
+		if bytes[i] == b'%' {
+			if i + 2 >= bytes.len()
+				|| !bytes[i + 1].is_ascii_hexdigit()
+				|| !bytes[i + 2].is_ascii_hexdigit()
+			{
+				return None;
+			}
+			let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()?;
+			decoded.push(u8::from_str_radix(hex, 16).ok()?);
+			i += 3;
+		} else {
+			decoded.push(bytes[i]);
+			i += 1;
+		}
+	}
+
+	String::from_utf8(decoded).ok()
+}
+
+/// Percent-encodes all bytes of the given string except for unreserved characters (letters, digits, "-", ".", "_"
+/// and "~").
+///
+/// # Example
+///
+/// ```
+/// use mi::http::percent_encode;
+/// assert_eq!(percent_encode("a b/ä.txt"), "a%20b%2F%C3%A4.txt");
+/// ```
+pub fn percent_encode(s: &str) -> String {
+	let mut encoded = String::with_capacity(s.len());
+	for b in s.bytes() {
+		if b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_' || b == b'~' {
+			encoded.push(b as char);
+		} else {
+			encoded.push_str(&format!("%{:02X}", b));
+		}
+	}
+	encoded
+}
+
+/// Escapes the characters that have a special meaning in HTML text and attribute values
+pub fn html_escape(s: &str) -> String {
+	let mut escaped = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&#39;"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+/// Splits the given unix timestamp into its UTC calendar parts (year, month, day, hour, minute, second)
+pub fn datetime_parts(secs: u64) -> (i64, u32, u32, u32, u32, u32) {
+	let days = (secs / 86400) as i64;
+	let rem = secs % 86400;
+
+	// Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+	(
+		year,
+		month,
+		day,
+		(rem / 3600) as u32,
+		(rem % 3600 / 60) as u32,
+		(rem % 60) as u32,
+	)
+}
+
+/// Month abbreviations used in HTTP-dates and the Common Log Format
+pub(crate) const MONTHS: [&str; 12] = [
+	"Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats the given unix timestamp as HTTP-date (RFC 7231), e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn format_http_date(secs: u64) -> String {
+	const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+	let (year, month, day, hour, minute, second) = datetime_parts(secs);
+	format!(
+		"{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+		WEEKDAYS[(secs / 86400 % 7) as usize],
+		day,
+		MONTHS[month as usize - 1],
+		year,
+		hour,
+		minute,
+		second
+	)
+}
+
+/// Parses an HTTP-date into a unix timestamp. Besides the preferred format "Sun, 06 Nov 1994 08:49:37 GMT" the
+/// obsolete RFC 850 and asctime formats are accepted, the weekday is not checked. Returns None for invalid dates and
+/// dates before 1970.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::{format_http_date, parse_http_date};
+/// assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+/// assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(784111777));
+/// assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(784111777));
+/// assert_eq!(format_http_date(784111777), "Sun, 06 Nov 1994 08:49:37 GMT");
+/// assert_eq!(parse_http_date("Sun, 31 Feb 1994 08:49:37 GMT"), None);
+/// ```
+pub fn parse_http_date(s: &str) -> Option<u64> {
+	let parts: Vec<&str> = s.split_whitespace().collect();
+	let (day, month, year, time) = match parts.as_slice() {
+		// IMF-fixdate: Sun, 06 Nov 1994 08:49:37 GMT
+		[_, day, month, year, time, "GMT"] => (*day, *month, year.parse::<i64>().ok()?, *time),
+		// RFC 850: Sunday, 06-Nov-94 08:49:37 GMT
+		[_, date, time, "GMT"] => {
+			let mut date = date.split('-');
+			let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+			let year: i64 = year.parse().ok().filter(|y| *y < 100)?;
+			// Two digit years more than 50 years in the future are in the past
+			let current = datetime_parts(unix_time()).0;
+			let year = current - current % 100 + year;
+			let year = if year > current + 50 {
+				year - 100
+			} else {
+				year
+			};
+			(day, month, year, *time)
+		}
+		// asctime: Sun Nov  6 08:49:37 1994
+		[_, month, day, time, year] => (*day, *month, year.parse::<i64>().ok()?, *time),
+		_ => return None,
+	};
+
+	let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+	let day: i64 = day.parse().ok()?;
+	let mut time = time.split(':').map(|t| t.parse::<i64>().ok());
+	let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+	if time.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+		return None;
+	}
+
+	// Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+	let days_in_month = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+	let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+	let max_day = if month == 2 && leap {
+		29
+	} else {
+		days_in_month[month as usize - 1]
+	};
+	if day < 1 || day > max_day || year < 1970 {
+		return None;
+	}
+
+	let y = if month <= 2 { year - 1 } else { year };
+	let era = y.div_euclid(400);
+	let yoe = y.rem_euclid(400);
+	let mp = if month > 2 { month - 3 } else { month + 9 };
+	let doy = (153 * mp + 2) / 5 + day - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	let days = era * 146097 + doe - 719468;
+
+	Some((days * 86400 + hour * 3600 + minute * 60 + second) as u64)
+}
+
+/// Formats a number of bytes using binary unit prefixes, e.g. "1.5 KiB"
+pub fn human_size(bytes: u64) -> String {
+	const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+	let mut size = bytes as f64;
+	let mut unit = 0;
+	while size >= 1024.0 && unit < UNITS.len() - 1 {
+		size /= 1024.0;
+		unit += 1;
+	}
+
+	if unit == 0 {
+		format!("{} {}", bytes, UNITS[0])
+	} else {
+		format!("{:.1} {}", size, UNITS[unit])
+	}
+}
+
+/// Makes closing the connection reset it instead of ending the stream normally, so clients can tell that a response
+/// delimited by the end of the connection is incomplete. Returns false if this is not supported
+#[cfg(target_os = "linux")]
+#[allow(unsafe_code)]
+pub(crate) fn reset_on_close(stream: &std::net::TcpStream) -> bool {
+	use std::os::unix::io::AsRawFd;
+
+	let linger = libc::linger {
+		l_onoff: 1,
+		l_linger: 0,
+	};
+	// SAFETY: linger is valid for the duration of the call and its size is passed along, the kernel copies it
+	let result = unsafe {
+		libc::setsockopt(
+			stream.as_raw_fd(),
+			libc::SOL_SOCKET,
+			libc::SO_LINGER,
+			(&raw const linger).cast::<libc::c_void>(),
+			size_of::<libc::linger>() as libc::socklen_t,
+		)
+	};
+	result == 0
+}
+
+/// Makes closing the connection reset it instead of ending the stream normally. Returns false if this is not supported
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn reset_on_close(_: &std::net::TcpStream) -> bool {
+	false
+}
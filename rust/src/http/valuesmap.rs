@@ -1,9 +1,12 @@
+use super::util::{format_http_date, parse_http_date};
+use super::{Authorization, CacheControl, ContentType, Host};
 use std::collections::HashMap;
 
 /// Header map for [super::Request]s and [super::Response]s
+#[derive(Debug, Clone)]
 pub struct ValuesMap {
 	/// If case_handling is set to true, header keys will be changed to the de-facto standard for headers of starting
-	/// with an upper-case letter at the beginning and after every dash.
+	/// with an upper-case letter at the beginning and after every dash, all other letters are lower-case.
 	/// For performance reasons this defaults to false for ValuesMaps used for headers in [super::Response]s and for
 	/// compatibility reasons this is set to true for ones used in incoming [super::Request]s
 	pub case_handling: bool,
@@ -42,7 +45,20 @@ impl ValuesMap {
 
 	/// Returns a vector of all values stored for the given key
 	pub fn get_all(&self, k: &str) -> Option<&Vec<String>> {
-		self.values.get(k)
+		match self.case_handling {
+			false => self.values.get(k),
+			true => self.values.get(&self.header_case(k)),
+		}
+	}
+
+	/// Removes all values stored for the given key
+	pub fn remove(&mut self, k: &str) {
+		let key = match self.case_handling {
+			false => String::from(k),
+			true => self.header_case(k),
+		};
+
+		self.values.remove(&key);
 	}
 
 	/// Sets the given header and replaces any values previously set for this key
@@ -75,6 +91,55 @@ impl ValuesMap {
 		self.values.is_empty()
 	}
 
+	/// Returns the parsed Content-Type header
+	pub fn content_type(&self) -> Option<ContentType> {
+		ContentType::parse(self.get("Content-Type")?)
+	}
+
+	/// Returns the Content-Length header, None if it is missing or not a number
+	pub fn content_length(&self) -> Option<u64> {
+		self.get("Content-Length")?.trim().parse().ok()
+	}
+
+	/// Returns the directives of all Cache-Control headers
+	pub fn cache_control(&self) -> Option<CacheControl> {
+		Some(CacheControl::parse(
+			&self.get_all("Cache-Control")?.join(", "),
+		))
+	}
+
+	/// Returns the parsed Authorization header
+	pub fn authorization(&self) -> Option<Authorization> {
+		Authorization::parse(self.get("Authorization")?)
+	}
+
+	/// Returns the parsed Host header
+	pub fn host(&self) -> Option<Host> {
+		Host::parse(self.get("Host")?)
+	}
+
+	/// Returns the date in the given header, like Date, Last-Modified or If-Modified-Since, as unix timestamp. See
+	/// [super::parse_http_date]
+	///
+	/// # Example
+	///
+	/// ```
+	/// use mi::http::ValuesMap;
+	///
+	/// let mut headers = ValuesMap::new();
+	/// headers.set_http_date("Last-Modified", 784111777);
+	/// assert_eq!(headers.get("Last-Modified"), Some("Sun, 06 Nov 1994 08:49:37 GMT"));
+	/// assert_eq!(headers.http_date("Last-Modified"), Some(784111777));
+	/// ```
+	pub fn http_date(&self, k: &str) -> Option<u64> {
+		parse_http_date(self.get(k)?)
+	}
+
+	/// Sets the given header to the unix timestamp formatted as HTTP-date
+	pub fn set_http_date(&mut self, k: &str, secs: u64) {
+		self.set(k, &format_http_date(secs));
+	}
+
 	fn header_case(&self, k: &str) -> String {
 		let mut key = String::with_capacity(k.len());
 		let mut up = true;
@@ -83,10 +148,10 @@ impl ValuesMap {
 				key.extend(c.to_uppercase());
 				up = false;
 			} else {
-				key.push(c);
-				if c == '-' {
-					up = true;
-				}
+				key.extend(c.to_lowercase());
+			}
+			if c == '-' {
+				up = true;
 			}
 		}
 		key
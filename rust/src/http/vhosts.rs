@@ -0,0 +1,150 @@
+use super::{Request, RequestHandler, Response};
+use std::sync::Arc;
+
+/// The handlers registered for a host name
+struct Host {
+	name: String,
+	handlers: Vec<Arc<dyn RequestHandler>>,
+}
+
+/// A [RequestHandler] that dispatches requests to a separate set of handlers per host, selected by the Host header.
+///
+/// Host names are matched case-insensitively and without port. Names starting with "*." match all subdomains of the
+/// given domain, exact names take precedence over wildcards and longer wildcards over shorter ones. Requests for unknown
+/// hosts are dispatched to the handlers of [VirtualHosts::default_host] if set. Within a host, the first matching handler
+/// is used just like in [super::Server].
+///
+/// HTTP/1.1 requests without Host header are answered with 400 Bad Request.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::*;
+/// use std::sync::Arc;
+///
+/// let mut hosts = VirtualHosts::new();
+/// hosts.handler("docs.example.test", Arc::new(FileHandler::new("/", "/srv/docs")));
+/// hosts.handle_fn("*.example.test", |_| true, |_, mut res| {
+///     res.w("Wildcard");
+/// });
+/// hosts.default_host = Some(String::from("docs.example.test"));
+///
+/// let mut server = Server::new();
+/// server.handler(Arc::new(hosts));
+/// ```
+pub struct VirtualHosts {
+	/// Name of the host to use for requests with unknown or empty Host header
+	pub default_host: Option<String>,
+	hosts: Vec<Host>,
+}
+
+impl VirtualHosts {
+	/// Creates a new dispatcher without any hosts
+	pub fn new() -> VirtualHosts {
+		VirtualHosts {
+			default_host: None,
+			hosts: Vec::new(),
+		}
+	}
+
+	/// Adds a [RequestHandler] for the given host name or wildcard
+	pub fn handler(&mut self, host: &str, handler: Arc<dyn RequestHandler>) {
+		let name = normalize_host(host);
+		match self.hosts.iter_mut().find(|h| h.name == name) {
+			Some(h) => h.handlers.push(handler),
+			None => self.hosts.push(Host {
+				name,
+				handlers: vec![handler],
+			}),
+		}
+	}
+
+	/// Adds a handler function along with a matcher function for the given host name or wildcard, like
+	/// [super::Server::handle] does for the whole server
+	pub fn handle_fn(
+		&mut self,
+		host: &str,
+		matcher_fn: fn(req: &Request) -> bool,
+		handler_fn: fn(&Request, Response),
+	) {
+		self.handler(host, Arc::new(super::Handler::new(matcher_fn, handler_fn)));
+	}
+
+	/// Returns the registered host the given Host header value refers to
+	fn find_host(&self, header: &str) -> Option<&Host> {
+		let name = normalize_host(header);
+
+		if !name.is_empty() {
+			if let Some(h) = self.hosts.iter().find(|h| h.name == name) {
+				return Some(h);
+			}
+
+			let wildcard = self
+				.hosts
+				.iter()
+				.filter(|h| match h.name.strip_prefix('*') {
+					Some(domain) => name.len() > domain.len() && name.ends_with(domain),
+					None => false,
+				})
+				.max_by_key(|h| h.name.len());
+			if wildcard.is_some() {
+				return wildcard;
+			}
+		}
+
+		let default = normalize_host(self.default_host.as_deref()?);
+		self.hosts.iter().find(|h| h.name == default)
+	}
+
+	/// Returns the first handler of the request's host that matches the request. Err is returned for requests that have
+	/// to be rejected.
+	fn find_handler(&self, req: &Request) -> Result<Option<&Arc<dyn RequestHandler>>, u16> {
+		let header = match req.headers.get("Host") {
+			Some(h) => h,
+			None if req.http_version == "HTTP/1.1" => return Err(400),
+			None => "",
+		};
+
+		Ok(self
+			.find_host(header)
+			.and_then(|h| h.handlers.iter().find(|handler| handler.matches(req))))
+	}
+}
+
+impl Default for VirtualHosts {
+	fn default() -> Self {
+		VirtualHosts::new()
+	}
+}
+
+impl RequestHandler for VirtualHosts {
+	fn matches(&self, req: &Request) -> bool {
+		!matches!(self.find_handler(req), Ok(None))
+	}
+
+	fn handle(&self, req: &Request, mut res: Response) {
+		match self.find_handler(req) {
+			Ok(Some(handler)) => handler.handle(req, res),
+			Ok(None) => super::util::DEFAULT_HANDLER(req, res),
+			Err(code) => {
+				let _ = res.send_error(&super::Error::new(code, "Missing Host header"));
+			}
+		}
+	}
+}
+
+/// Returns the host name in lower case without port and trailing dot
+fn normalize_host(host: &str) -> String {
+	let host = host.trim();
+	let name = if host.starts_with('[') {
+		// IPv6 addresses contain colons themselves
+		match host.find(']') {
+			Some(p) => &host[..p + 1],
+			None => host,
+		}
+	} else {
+		&host[..host.find(':').unwrap_or(host.len())]
+	};
+
+	name.trim_end_matches('.').to_ascii_lowercase()
+}
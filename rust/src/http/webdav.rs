@@ -0,0 +1,479 @@
+use super::util::{format_http_date, html_escape};
+use super::Request;
+use crate::fs::copy_recursively_no_follow;
+use crate::log_error;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counter to create unique names for temporary upload files
+static UPLOAD_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// XML declaration and opening tag of a multi-status response body
+const MULTISTATUS_START: &str =
+	"<?xml version=\"1.0\" encoding=\"utf-8\"?><D:multistatus xmlns:D=\"DAV:\"";
+
+fn internal_error<E: std::fmt::Display>(action: &str, path: &Path, e: E) -> u16 {
+	log_error!(
+		"Internal Sever Error - Cannot {} {}: {}",
+		action,
+		path.to_string_lossy(),
+		e
+	);
+	500
+}
+
+/// Stores the request body at the given path. The body is written to a temporary file in the target directory first,
+/// which then replaces the target, so readers never see partial uploads. Returns 201 if the file was created and
+/// 204 if it was replaced.
+pub fn put(req: &Request, path: &Path) -> Result<u16, u16> {
+	if path.is_dir() {
+		return Err(405);
+	}
+
+	let (parent, name) = match (path.parent(), path.file_name()) {
+		(Some(p), Some(n)) => (p, n.to_string_lossy()),
+		_ => return Err(403),
+	};
+
+	if !parent.is_dir() {
+		return Err(409);
+	}
+
+	let existed = path.exists();
+	let temp = parent.join(format!(
+		".{}.{}-{}.upload",
+		name,
+		std::process::id(),
+		UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed)
+	));
+
+	let result = std::fs::File::create(&temp).and_then(|mut file| {
+		req.copy_body_to(&mut file)?;
+		file.sync_all()
+	});
+
+	if let Err(e) = result {
+		let _ = std::fs::remove_file(&temp);
+		if e.kind() == std::io::ErrorKind::UnexpectedEof {
+			return Err(400);
+		}
+		return Err(internal_error("upload", path, e));
+	}
+
+	if let Err(e) = std::fs::rename(&temp, path) {
+		let _ = std::fs::remove_file(&temp);
+		return Err(internal_error("store upload", path, e));
+	}
+
+	Ok(if existed { 204 } else { 201 })
+}
+
+/// Deletes the file or directory (including its contents) at the given path
+pub fn delete(path: &Path) -> Result<u16, u16> {
+	let metadata = match std::fs::symlink_metadata(path) {
+		Ok(m) => m,
+		Err(_) => return Err(404),
+	};
+
+	let result = if metadata.is_dir() {
+		std::fs::remove_dir_all(path)
+	} else {
+		std::fs::remove_file(path)
+	};
+
+	match result {
+		Ok(_) => Ok(204),
+		Err(e) => Err(internal_error("delete", path, e)),
+	}
+}
+
+/// Creates a directory at the given path. Request bodies are not supported
+pub fn mkcol(req: &Request, path: &Path) -> Result<u16, u16> {
+	match req.headers.get("Content-Length") {
+		Some(l) if l != "0" => return Err(415),
+		_ => {}
+	}
+
+	if std::fs::symlink_metadata(path).is_ok() {
+		return Err(405);
+	}
+
+	match path.parent() {
+		Some(p) if p.is_dir() => {}
+		_ => return Err(409),
+	}
+
+	match std::fs::create_dir(path) {
+		Ok(_) => Ok(201),
+		Err(e) => Err(internal_error("create directory", path, e)),
+	}
+}
+
+/// Returns the path the symbolic link at link points to once it is copied to location, or None if that cannot be
+/// determined without the copy. The parent of location must be canonical or, for links inside a copied directory,
+/// below a canonical path and not exist yet
+fn relocated_target(link: &Path, location: &Path) -> Option<PathBuf> {
+	let target = std::fs::read_link(link).ok()?;
+	if target.is_absolute() {
+		return Some(target);
+	}
+
+	let mut path = PathBuf::from(location.parent()?);
+	let mut descended = false;
+	for component in target.components() {
+		match component {
+			Component::CurDir => {}
+			// Leading ".." leave directories that are real ones after the copy, later ones may leave a link
+			Component::ParentDir if !descended => {
+				if !path.pop() {
+					return None;
+				}
+			}
+			Component::Normal(name) => {
+				path.push(name);
+				descended = true;
+			}
+			_ => return None,
+		}
+	}
+	Some(path)
+}
+
+/// Returns 403 if the tree at path contains a symbolic link that allow_link rejects or a special file like a FIFO.
+/// Location is where the tree is going to be copied to
+fn check_tree(
+	path: &Path,
+	location: &Path,
+	allow_link: &dyn Fn(&Path, &Path) -> bool,
+) -> Result<(), u16> {
+	let file_type = match std::fs::symlink_metadata(path) {
+		Ok(m) => m.file_type(),
+		Err(e) => return Err(internal_error("read", path, e)),
+	};
+
+	if file_type.is_symlink() {
+		match relocated_target(path, location) {
+			Some(target) if allow_link(path, &target) => {}
+			_ => return Err(403),
+		}
+	} else if file_type.is_dir() {
+		let entries = match std::fs::read_dir(path) {
+			Ok(e) => e,
+			Err(e) => return Err(internal_error("list", path, e)),
+		};
+		for entry in entries {
+			match entry {
+				Ok(e) => check_tree(&e.path(), &location.join(e.file_name()), allow_link)?,
+				Err(e) => return Err(internal_error("list", path, e)),
+			}
+		}
+	} else if !file_type.is_file() {
+		return Err(403);
+	}
+
+	Ok(())
+}
+
+/// Copies or moves the resource at source to destination. Existing destinations are replaced if overwrite is true.
+/// With shallow set, directories are copied without their contents, moves always include them. Symbolic links are
+/// never followed, the request is rejected with 403 if allow_link returns false for a link in the source tree and the
+/// path it would point to from the destination, all other links are copied as links. Returns 201 if the destination
+/// was created and 204 if it was replaced.
+pub fn copy_or_move(
+	source: &Path,
+	destination: &Path,
+	overwrite: bool,
+	is_move: bool,
+	shallow: bool,
+	allow_link: &dyn Fn(&Path, &Path) -> bool,
+) -> Result<u16, u16> {
+	let shallow = shallow && !is_move;
+	let source_is_dir = match std::fs::symlink_metadata(source) {
+		Ok(m) => m.is_dir(),
+		Err(_) => return Err(404),
+	};
+
+	if source == destination || (source_is_dir && destination.starts_with(source)) {
+		return Err(403);
+	}
+
+	let parent = match destination.parent() {
+		Some(p) if p.is_dir() => p,
+		_ => return Err(409),
+	};
+	// Relative links are resolved from their new location, which must not go through links itself
+	let location = match (parent.canonicalize(), destination.file_name()) {
+		(Ok(parent), Some(name)) => parent.join(name),
+		(Err(e), _) => return Err(internal_error("read", parent, e)),
+		_ => return Err(403),
+	};
+	if !(shallow && source_is_dir) {
+		check_tree(source, &location, allow_link)?;
+	}
+
+	let existed = std::fs::symlink_metadata(destination).is_ok();
+	if existed {
+		if !overwrite {
+			return Err(412);
+		}
+		delete(destination)?;
+	}
+
+	let result = if is_move {
+		std::fs::rename(source, destination).or_else(|_| {
+			// Renaming fails across file systems, fall back to copy and delete
+			copy_recursively_no_follow(source, destination)?;
+			if source_is_dir {
+				std::fs::remove_dir_all(source)
+			} else {
+				std::fs::remove_file(source)
+			}
+		})
+	} else if shallow && source_is_dir {
+		std::fs::create_dir(destination)
+	} else {
+		copy_recursively_no_follow(source, destination)
+	};
+
+	match result {
+		Ok(_) => Ok(if existed { 204 } else { 201 }),
+		Err(e) => Err(internal_error(
+			if is_move { "move" } else { "copy" },
+			source,
+			e,
+		)),
+	}
+}
+
+/// Properties of a single resource in a PROPFIND response
+pub struct Resource<'a> {
+	/// Percent-encoded absolute URI path of the resource
+	pub href: &'a str,
+	/// Name of the file or directory
+	pub name: &'a str,
+	/// Whether the resource is a directory
+	pub is_dir: bool,
+	/// Size of files in bytes
+	pub size: u64,
+	/// Modification time as unix timestamp
+	pub modified: u64,
+}
+
+/// Returns the multi-status body listing the live properties of the given resources
+pub fn propfind(resources: &[Resource]) -> String {
+	let mut xml = String::from(MULTISTATUS_START);
+	xml.push('>');
+
+	for r in resources {
+		xml.push_str("<D:response><D:href>");
+		xml.push_str(&html_escape(r.href));
+		xml.push_str("</D:href><D:propstat><D:prop><D:displayname>");
+		xml.push_str(&html_escape(r.name));
+		xml.push_str("</D:displayname>");
+		if r.is_dir {
+			xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+		} else {
+			xml.push_str("<D:resourcetype/>");
+			xml.push_str(&format!(
+				"<D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>{}</D:getcontenttype>",
+				r.size,
+				html_escape(&super::mime::content_type_for_path(r.name))
+			));
+		}
+		xml.push_str(&format!(
+			"<D:getlastmodified>{}</D:getlastmodified>",
+			format_http_date(r.modified)
+		));
+		xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>");
+	}
+
+	xml.push_str("</D:multistatus>");
+	xml
+}
+
+/// Returns true if name is a valid XML name, optionally with namespace prefix. Characters outside of ASCII are
+/// accepted without checking the XML character classes, as they cannot form markup
+fn is_xml_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	let valid_start = |c: char| c.is_ascii_alphabetic() || c == '_' || c == ':' || !c.is_ascii();
+	match chars.next() {
+		Some(c) if valid_start(c) => {
+			chars.all(|c| valid_start(c) || c.is_ascii_digit() || c == '-' || c == '.')
+		}
+		_ => false,
+	}
+}
+
+/// Returns the multi-status body for a PROPPATCH request. Dead properties are not stored, so every property named in
+/// the request is reported as forbidden.
+pub fn proppatch(body: &str, href: &str) -> String {
+	let mut names: Vec<&str> = Vec::new();
+	let mut namespaces: Vec<(&str, &str)> = Vec::new();
+	let mut default_namespace: Option<&str> = None;
+
+	let mut in_prop = false;
+	let mut depth = 0;
+	for tag in body.split('<').skip(1) {
+		let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+		if tag.starts_with('?') || tag.starts_with('!') {
+			continue;
+		}
+
+		// Collect namespace declarations to repeat them in the response
+		for attribute in tag.split_whitespace().skip(1) {
+			let (key, value) = match attribute.find('=') {
+				Some(p) => (&attribute[..p], attribute[p + 1..].trim_end_matches('/')),
+				None => continue,
+			};
+			let value = value.trim_matches(|c| c == '"' || c == '\'');
+			if key == "xmlns" {
+				default_namespace.get_or_insert(value);
+			} else if let Some(prefix) = key.strip_prefix("xmlns:") {
+				if prefix != "D"
+					&& is_xml_name(prefix)
+					&& !namespaces.iter().any(|(p, _)| *p == prefix)
+				{
+					namespaces.push((prefix, value));
+				}
+			}
+		}
+
+		let closing = tag.starts_with('/');
+		let self_closing = tag.ends_with('/');
+		let name = tag
+			.trim_start_matches('/')
+			.trim_end_matches('/')
+			.split_whitespace()
+			.next()
+			.unwrap_or("");
+		let local_name = name.rsplit(':').next().unwrap_or(name);
+
+		if !in_prop {
+			if local_name == "prop" && !closing && !self_closing {
+				in_prop = true;
+				depth = 0;
+			}
+			continue;
+		}
+
+		if closing {
+			if depth == 0 {
+				in_prop = false;
+			} else {
+				depth -= 1;
+			}
+			continue;
+		}
+
+		// Names are written back as element names, which cannot be escaped
+		if depth == 0 && is_xml_name(name) {
+			names.push(name);
+		}
+		if !self_closing {
+			depth += 1;
+		}
+	}
+
+	let mut xml = String::from(MULTISTATUS_START);
+	for (prefix, uri) in &namespaces {
+		xml.push_str(&format!(" xmlns:{}=\"{}\"", prefix, html_escape(uri)));
+	}
+	xml.push_str("><D:response><D:href>");
+	xml.push_str(&html_escape(href));
+	xml.push_str("</D:href><D:propstat><D:prop>");
+	for name in names {
+		match (name.contains(':'), default_namespace) {
+			(false, Some(ns)) => {
+				xml.push_str(&format!("<{} xmlns=\"{}\"/>", name, html_escape(ns)))
+			}
+			_ => xml.push_str(&format!("<{}/>", name)),
+		}
+	}
+	xml.push_str("</D:prop><D:status>HTTP/1.1 403 Forbidden</D:status></D:propstat></D:response>");
+	xml.push_str("</D:multistatus>");
+	xml
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use super::*;
+	use std::os::unix::fs::symlink;
+
+	#[test]
+	fn links_must_stay_within_the_root_after_copying() {
+		let base = std::env::temp_dir().join(format!("mi-webdav-{}", std::process::id()));
+		let root = base.join("root");
+		std::fs::create_dir_all(root.join("a/b")).unwrap();
+		std::fs::create_dir_all(root.join("z")).unwrap();
+		std::fs::write(root.join("c"), "c").unwrap();
+		symlink("../../c", root.join("a/b/l")).unwrap();
+		symlink("../c", root.join("a/m")).unwrap();
+
+		let canonical_root = root.canonicalize().unwrap();
+		let within_root = |link: &Path, target: &Path| {
+			let inside = |p: &Path| {
+				p.ancestors()
+					.find_map(|a| a.canonicalize().ok())
+					.is_some_and(|c| c.starts_with(&canonical_root))
+			};
+			inside(link) && inside(target)
+		};
+
+		// root/z/l -> ../../c would point next to the root
+		assert_eq!(
+			copy_or_move(
+				&root.join("a/b/l"),
+				&root.join("z/l"),
+				false,
+				false,
+				false,
+				&within_root
+			),
+			Err(403)
+		);
+		assert_eq!(
+			copy_or_move(
+				&root.join("a/b"),
+				&root.join("b"),
+				false,
+				false,
+				false,
+				&within_root
+			),
+			Err(403)
+		);
+		// A move ignores shallow and checks the tree as well
+		assert_eq!(
+			copy_or_move(
+				&root.join("a/b"),
+				&root.join("x"),
+				false,
+				true,
+				true,
+				&within_root
+			),
+			Err(403)
+		);
+		assert!(root.join("a/b/l").exists());
+
+		// Copied to the same depth the links still point to root/c
+		assert_eq!(
+			copy_or_move(
+				&root.join("a"),
+				&root.join("y"),
+				false,
+				false,
+				false,
+				&within_root
+			),
+			Ok(201)
+		);
+		assert_eq!(
+			std::fs::read_link(root.join("y/b/l")).unwrap(),
+			Path::new("../../c")
+		);
+		assert_eq!(std::fs::read_to_string(root.join("y/m")).unwrap(), "c");
+
+		std::fs::remove_dir_all(base).unwrap();
+	}
+}
@@ -0,0 +1,530 @@
+use super::{Error, Request, Response};
+use crate::bin::{base64_decode, base64_encode, sha1};
+use crate::log_error;
+use std::io::prelude::*;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// GUID appended to the client key to compute the Sec-WebSocket-Accept header
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// A message received from a [WebSocket]
+#[derive(Debug, PartialEq)]
+pub enum Message {
+	/// A complete UTF-8 text message
+	Text(String),
+	/// A complete binary message
+	Binary(Vec<u8>),
+	/// The client closed the connection with the given status code (if any) and reason
+	Close(Option<u16>, String),
+}
+
+/// A single frame as read from the connection
+struct Frame {
+	fin: bool,
+	opcode: u8,
+	payload: Vec<u8>,
+}
+
+/// Sending half of a [WebSocket]. It can be cloned and moved to other threads, e.g. to push updates to clients while
+/// the [WebSocket] waits for incoming messages. Frames from different senders are never interleaved.
+#[derive(Clone)]
+pub struct WebSocketSender {
+	stream: Arc<Mutex<TcpStream>>,
+	close_sent: Arc<AtomicBool>,
+}
+
+impl WebSocketSender {
+	/// Sends a text message
+	pub fn send_text(&self, text: &str) -> Result<(), std::io::Error> {
+		self.send_frame(OPCODE_TEXT, text.as_bytes())
+	}
+
+	/// Sends a binary message
+	pub fn send_binary(&self, data: &[u8]) -> Result<(), std::io::Error> {
+		self.send_frame(OPCODE_BINARY, data)
+	}
+
+	/// Sends a ping with the given payload of up to 125 bytes. The client answers with a pong that is consumed by
+	/// [WebSocket::recv].
+	pub fn ping(&self, payload: &[u8]) -> Result<(), std::io::Error> {
+		self.send_frame(OPCODE_PING, payload)
+	}
+
+	/// Starts the closing handshake with the given status code and reason. [WebSocket::recv] returns the client's
+	/// answering close message, after that the connection is shut down.
+	pub fn close(&self, code: u16, reason: &str) -> Result<(), std::io::Error> {
+		if self.close_sent.swap(true, Ordering::SeqCst) {
+			return Ok(());
+		}
+
+		let reason = truncate_reason(reason);
+		let mut payload = Vec::with_capacity(2 + reason.len());
+		payload.extend_from_slice(&code.to_be_bytes());
+		payload.extend_from_slice(reason.as_bytes());
+
+		self.write_frame(OPCODE_CLOSE, &payload)
+	}
+
+	fn send_frame(&self, opcode: u8, payload: &[u8]) -> Result<(), std::io::Error> {
+		if self.close_sent.load(Ordering::SeqCst) {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::NotConnected,
+				"WebSocket is closing",
+			));
+		}
+
+		if opcode >= OPCODE_CLOSE && payload.len() > 125 {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				"Control frame payloads are limited to 125 bytes",
+			));
+		}
+
+		self.write_frame(opcode, payload)
+	}
+
+	fn write_frame(&self, opcode: u8, payload: &[u8]) -> Result<(), std::io::Error> {
+		let mut frame = Vec::with_capacity(payload.len() + 10);
+		frame.push(0x80 | opcode);
+
+		// Frames sent by the server are never masked
+		if payload.len() < 126 {
+			frame.push(payload.len() as u8);
+		} else if payload.len() <= u16::MAX as usize {
+			frame.push(126);
+			frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+		} else {
+			frame.push(127);
+			frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+		}
+		frame.extend_from_slice(payload);
+
+		let mut stream = match self.stream.lock() {
+			Ok(s) => s,
+			Err(e) => e.into_inner(),
+		};
+		stream.write_all(&frame)?;
+		stream.flush()
+	}
+}
+
+/// A WebSocket connection (RFC 6455) created from an upgraded [Request]. Fragmented messages are reassembled, pings
+/// are answered and the closing handshake is handled automatically.
+///
+/// A WebSocket occupies one of the server's worker threads for as long as it is open.
+///
+/// # Example
+///
+/// ```
+/// use mi::http::*;
+/// use std::sync::Arc;
+///
+/// let mut server = Server::new();
+/// server.handler(Arc::new(WebSocketHandler::new(
+///     |req| req.uri == "/echo",
+///     |_, mut ws| {
+///         while let Ok(message) = ws.recv() {
+///             match message {
+///                 Message::Text(text) => { let _ = ws.send_text(&text); }
+///                 Message::Binary(data) => { let _ = ws.send_binary(&data); }
+///                 Message::Close(_, _) => break,
+///             }
+///         }
+///     },
+/// )));
+/// ```
+pub struct WebSocket {
+	/// Maximum size of received messages in bytes. Larger messages close the connection with
+	/// [WebSocket::CLOSE_TOO_BIG]. Defaults to 16 MiB
+	pub max_message_size: usize,
+	stream: TcpStream,
+	sender: WebSocketSender,
+	pending: Vec<u8>,
+	closed: bool,
+}
+
+impl WebSocket {
+	/// Normal closure
+	pub const CLOSE_NORMAL: u16 = 1000;
+	/// The endpoint is going away, e.g. because the server shuts down
+	pub const CLOSE_GOING_AWAY: u16 = 1001;
+	/// The endpoint received a frame that violates the protocol
+	pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+	/// The endpoint received a type of data it cannot accept
+	pub const CLOSE_UNSUPPORTED: u16 = 1003;
+	/// The endpoint received a text message that is not valid UTF-8
+	pub const CLOSE_INVALID_DATA: u16 = 1007;
+	/// The endpoint received a message that violates its policy
+	pub const CLOSE_POLICY_VIOLATION: u16 = 1008;
+	/// The endpoint received a message that is too big to process
+	pub const CLOSE_TOO_BIG: u16 = 1009;
+	/// The server encountered an unexpected condition
+	pub const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+	/// Validates the WebSocket handshake of the given request and switches the connection to the WebSocket protocol.
+	/// Invalid handshakes are answered with 400 Bad Request, unsupported protocol versions with 426 Upgrade Required;
+	/// the returned [Error] describes the problem.
+	pub fn upgrade(req: &Request, mut res: Response) -> Result<WebSocket, Error> {
+		let accept = match handshake_accept(req) {
+			Ok(a) => a,
+			Err(e) => {
+				if e.code == 426 {
+					res.headers.set("Sec-WebSocket-Version", "13");
+				}
+				res.status_code = e.code;
+				res.headers.set("Content-Type", "text/plain; charset=utf-8");
+				let _ = res.write(&e.message);
+				let _ = res.end();
+				return Err(e);
+			}
+		};
+
+		res.status_code = 101;
+		res.headers.set("Upgrade", "websocket");
+		res.headers.set("Connection", "Upgrade");
+		res.headers.set("Sec-WebSocket-Accept", &accept);
+
+		let stream = match res.upgrade() {
+			Ok(s) => s,
+			Err(e) => return Err(Error::new(500, format!("Upgrade failed: {}", e))),
+		};
+
+		// WebSockets are long-lived, so the read timeout of the request does not apply
+		let _ = stream.set_read_timeout(None);
+
+		let writer = match stream.try_clone() {
+			Ok(s) => s,
+			Err(e) => return Err(Error::new(500, format!("Upgrade failed: {}", e))),
+		};
+
+		Ok(WebSocket {
+			max_message_size: 16 * 1024 * 1024,
+			stream,
+			sender: WebSocketSender {
+				stream: Arc::new(Mutex::new(writer)),
+				close_sent: Arc::new(AtomicBool::new(false)),
+			},
+			pending: Vec::from(req.buffered_body()),
+			closed: false,
+		})
+	}
+
+	/// Returns a [WebSocketSender] that can send messages to the client from other threads
+	pub fn sender(&self) -> WebSocketSender {
+		self.sender.clone()
+	}
+
+	/// Sets the timeout for waiting for incoming data in [WebSocket::recv]. Defaults to None (wait forever)
+	pub fn set_read_timeout(
+		&self,
+		timeout: Option<std::time::Duration>,
+	) -> Result<(), std::io::Error> {
+		self.stream.set_read_timeout(timeout)
+	}
+
+	/// Sends a text message
+	pub fn send_text(&self, text: &str) -> Result<(), std::io::Error> {
+		self.sender.send_text(text)
+	}
+
+	/// Sends a binary message
+	pub fn send_binary(&self, data: &[u8]) -> Result<(), std::io::Error> {
+		self.sender.send_binary(data)
+	}
+
+	/// Sends a ping with the given payload of up to 125 bytes
+	pub fn ping(&self, payload: &[u8]) -> Result<(), std::io::Error> {
+		self.sender.ping(payload)
+	}
+
+	/// Starts the closing handshake with the given status code and reason. The client's answer is returned by the next
+	/// call to [WebSocket::recv].
+	pub fn close(&self, code: u16, reason: &str) -> Result<(), std::io::Error> {
+		self.sender.close(code, reason)
+	}
+
+	/// Waits for the next complete message from the client. Control frames are handled internally. Once the client
+	/// closed the connection, [Message::Close] is returned and all further calls fail.
+	/// Protocol violations close the connection with the matching status code and return an error.
+	pub fn recv(&mut self) -> Result<Message, std::io::Error> {
+		if self.closed {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::NotConnected,
+				"WebSocket is closed",
+			));
+		}
+
+		let mut fragmented: Option<(u8, Vec<u8>)> = None;
+
+		loop {
+			let frame = self.read_frame()?;
+
+			match frame.opcode {
+				OPCODE_TEXT | OPCODE_BINARY | OPCODE_CONTINUATION => {
+					let (opcode, mut data) = match (frame.opcode, fragmented.take()) {
+						(OPCODE_CONTINUATION, Some(f)) => f,
+						(OPCODE_CONTINUATION, None) => {
+							return Err(self.fail(
+								WebSocket::CLOSE_PROTOCOL_ERROR,
+								"Unexpected continuation frame",
+							))
+						}
+						(_, Some(_)) => {
+							return Err(self.fail(
+								WebSocket::CLOSE_PROTOCOL_ERROR,
+								"Expected continuation frame",
+							))
+						}
+						(opcode, None) => (opcode, Vec::new()),
+					};
+
+					if data.len() + frame.payload.len() > self.max_message_size {
+						return Err(self.fail(WebSocket::CLOSE_TOO_BIG, "Message too big"));
+					}
+					data.extend_from_slice(&frame.payload);
+
+					if !frame.fin {
+						fragmented = Some((opcode, data));
+						continue;
+					}
+
+					if opcode == OPCODE_BINARY {
+						return Ok(Message::Binary(data));
+					}
+
+					return match String::from_utf8(data) {
+						Ok(text) => Ok(Message::Text(text)),
+						Err(_) => Err(self.fail(
+							WebSocket::CLOSE_INVALID_DATA,
+							"Text message is not valid UTF-8",
+						)),
+					};
+				}
+				OPCODE_PING => {
+					self.sender.write_frame(OPCODE_PONG, &frame.payload)?;
+				}
+				OPCODE_PONG => {}
+				OPCODE_CLOSE => return self.closed_by_client(frame.payload),
+				_ => {
+					return Err(self.fail(WebSocket::CLOSE_PROTOCOL_ERROR, "Unknown opcode"));
+				}
+			}
+		}
+	}
+
+	fn closed_by_client(&mut self, payload: Vec<u8>) -> Result<Message, std::io::Error> {
+		let (code, reason) = match payload.len() {
+			0 => (None, String::new()),
+			1 => return Err(self.fail(WebSocket::CLOSE_PROTOCOL_ERROR, "Invalid close frame")),
+			_ => {
+				let code = u16::from_be_bytes([payload[0], payload[1]]);
+				let valid_code = matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999);
+				match String::from_utf8(payload[2..].to_vec()) {
+					Ok(reason) if valid_code => (Some(code), reason),
+					Ok(_) => {
+						return Err(self.fail(WebSocket::CLOSE_PROTOCOL_ERROR, "Invalid close code"))
+					}
+					Err(_) => {
+						return Err(self.fail(
+							WebSocket::CLOSE_INVALID_DATA,
+							"Close reason is not valid UTF-8",
+						))
+					}
+				}
+			}
+		};
+
+		// Answer the close frame unless the server started the closing handshake
+		let _ = self
+			.sender
+			.close(code.unwrap_or(WebSocket::CLOSE_NORMAL), "");
+		self.shutdown();
+
+		Ok(Message::Close(code, reason))
+	}
+
+	/// Closes the connection because of a protocol violation and returns the matching error
+	fn fail(&mut self, code: u16, message: &str) -> std::io::Error {
+		log_error!("Closing WebSocket: {}", message);
+		let _ = self.sender.close(code, message);
+		self.shutdown();
+
+		std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+	}
+
+	fn shutdown(&mut self) {
+		self.closed = true;
+		let _ = self.stream.shutdown(Shutdown::Both);
+	}
+
+	fn read_frame(&mut self) -> Result<Frame, std::io::Error> {
+		let head = self.read_bytes(2)?;
+		let fin = head[0] & 0x80 != 0;
+		let opcode = head[0] & 0x0F;
+
+		if head[0] & 0x70 != 0 {
+			return Err(self.fail(WebSocket::CLOSE_PROTOCOL_ERROR, "Reserved bits set"));
+		}
+
+		// Clients must mask all frames
+		if head[1] & 0x80 == 0 {
+			return Err(self.fail(WebSocket::CLOSE_PROTOCOL_ERROR, "Unmasked frame"));
+		}
+
+		let length = match head[1] & 0x7F {
+			126 => {
+				let l = self.read_bytes(2)?;
+				u16::from_be_bytes([l[0], l[1]]) as u64
+			}
+			127 => {
+				let l = self.read_bytes(8)?;
+				let mut bytes = [0u8; 8];
+				bytes.copy_from_slice(&l);
+				u64::from_be_bytes(bytes)
+			}
+			l => l as u64,
+		};
+
+		if opcode >= OPCODE_CLOSE && (!fin || length > 125) {
+			return Err(self.fail(WebSocket::CLOSE_PROTOCOL_ERROR, "Invalid control frame"));
+		}
+
+		if length > self.max_message_size as u64 {
+			return Err(self.fail(WebSocket::CLOSE_TOO_BIG, "Message too big"));
+		}
+
+		let mask = self.read_bytes(4)?;
+		let mut payload = self.read_bytes(length as usize)?;
+		for (i, b) in payload.iter_mut().enumerate() {
+			*b ^= mask[i % 4];
+		}
+
+		Ok(Frame {
+			fin,
+			opcode,
+			payload,
+		})
+	}
+
+	fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>, std::io::Error> {
+		let mut buffer = [0; 4096];
+		while self.pending.len() < n {
+			let read = self.stream.read(&mut buffer)?;
+			if read == 0 {
+				self.closed = true;
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::UnexpectedEof,
+					"WebSocket connection closed by client",
+				));
+			}
+			self.pending.extend_from_slice(&buffer[..read]);
+		}
+
+		let rest = self.pending.split_off(n);
+		Ok(std::mem::replace(&mut self.pending, rest))
+	}
+}
+
+/// Shortens a close reason to the 123 bytes that fit into a control frame after the status code, without splitting a
+/// UTF-8 character
+fn truncate_reason(reason: &str) -> &str {
+	let mut end = reason.len().min(123);
+	while !reason.is_char_boundary(end) {
+		end -= 1;
+	}
+	&reason[..end]
+}
+
+/// Validates the handshake headers of a WebSocket upgrade request and returns the value for the
+/// Sec-WebSocket-Accept header
+fn handshake_accept(req: &Request) -> Result<String, Error> {
+	if req.method != super::methods::GET || req.http_version != "HTTP/1.1" {
+		return Err(Error::new(
+			400,
+			"WebSocket upgrades require GET with HTTP/1.1",
+		));
+	}
+
+	let has_token = |header: &str, token: &str| match req.headers.get(header) {
+		Some(v) => v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)),
+		None => false,
+	};
+
+	if !has_token("Upgrade", "websocket") || !has_token("Connection", "upgrade") {
+		return Err(Error::new(400, "Missing WebSocket upgrade headers"));
+	}
+
+	if req.headers.get("Sec-WebSocket-Version") != Some("13") {
+		return Err(Error::new(426, "Unsupported WebSocket version"));
+	}
+
+	let key = match req.headers.get("Sec-WebSocket-Key") {
+		Some(k) => k.trim(),
+		None => return Err(Error::new(400, "Missing Sec-WebSocket-Key")),
+	};
+
+	match base64_decode(key) {
+		Some(k) if k.len() == 16 => {}
+		_ => return Err(Error::new(400, "Invalid Sec-WebSocket-Key")),
+	}
+
+	Ok(base64_encode(&sha1(
+		format!("{}{}", key, HANDSHAKE_GUID).as_bytes(),
+	)))
+}
+
+/// A combination of matcher and handler function for WebSocket endpoints. Matching requests are upgraded before the
+/// handler function is called with the [WebSocket].
+pub struct WebSocketHandler {
+	matcher_fn: fn(req: &Request) -> bool,
+	handler_fn: fn(req: &Request, ws: WebSocket),
+}
+
+impl WebSocketHandler {
+	/// Create a new WebSocketHandler from a matcher function and a handler function
+	pub fn new(
+		matcher_fn: fn(req: &Request) -> bool,
+		handler_fn: fn(&Request, WebSocket),
+	) -> WebSocketHandler {
+		WebSocketHandler {
+			matcher_fn,
+			handler_fn,
+		}
+	}
+}
+
+impl super::RequestHandler for WebSocketHandler {
+	fn matches(&self, req: &Request) -> bool {
+		(self.matcher_fn)(req)
+	}
+
+	fn handle(&self, req: &Request, res: Response) {
+		match WebSocket::upgrade(req, res) {
+			Ok(ws) => (self.handler_fn)(req, ws),
+			Err(e) => log_error!("WebSocket upgrade failed for {}: {}", req.uri, e),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn close_reasons_are_truncated_at_char_boundaries() {
+		assert_eq!(truncate_reason("going away"), "going away");
+
+		let reason = "a".repeat(122) + "ä";
+		assert_eq!(truncate_reason(&reason), "a".repeat(122));
+		assert_eq!(truncate_reason(&"ä".repeat(100)).len(), 122);
+	}
+}